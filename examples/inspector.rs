@@ -40,6 +40,10 @@ fn main() {
         println!("    Pen Settings Fill Color: {:08x}", note.pen_settings_fill_color());
         println!("    Pen Settings Graphics Shape Color: {:08x}", note.pen_settings_graphics_shape_color());
 
+        let pdf_file =
+            File::create(format!("{}.pdf", note.name())).expect("Failed to create PDF file");
+        note.export_pdf(pdf_file).expect("Failed to export PDF");
+
         let virtual_doc = note.virtual_doc().expect("No virtual doc found for note");
         println!(
             "    Virtual Doc ID: {}",
@@ -79,6 +83,14 @@ fn list_pages<R: std::io::Read + std::io::Seek>(note: &mut Note<R>, pages: Vec<P
             ))
             .expect("Failed to write PNG");
 
+        let svg_file = File::create(format!(
+            "{}_{}.svg",
+            note.name(),
+            page_id.to_simple_string()
+        ))
+        .expect("Failed to create SVG file");
+        page.write_svg(svg_file).expect("Failed to write SVG");
+
         let page_model = page.page_model();
         println!("        Page Model:",);
         println!("          Created: {}", page_model.created);
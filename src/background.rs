@@ -0,0 +1,506 @@
+use crate::{
+    color::Color,
+    error::{Error, Result},
+    id::PageUuid,
+    note_tree::BackgroundConfig,
+    render::Rect,
+};
+
+/// Distance between the lines of a lined page, in page units.
+const LINE_SPACING: f32 = 80.0;
+/// Distance between the lines of a grid page, in page units.
+const GRID_SPACING: f32 = 60.0;
+/// Distance between the dots of a dotted page, in page units.
+const DOT_SPACING: f32 = 60.0;
+
+/// Color of built-in line, grid and dot patterns.
+pub const PATTERN_COLOR: Color = Color::from_argb(0xFFC8_C8C8);
+/// Stroke width of built-in line and grid patterns, in page units.
+pub const PATTERN_LINE_WIDTH: f32 = 1.5;
+/// Radius of the dots of a dotted page, in page units.
+pub const PATTERN_DOT_RADIUS: f32 = 2.0;
+
+/// Background drawn under the ink of a page.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Background {
+    #[default]
+    Blank,
+    Lined {
+        spacing: f32,
+    },
+    Grid {
+        spacing: f32,
+    },
+    Dotted {
+        spacing: f32,
+    },
+    /// Template or resource image stored in the archive.
+    Image {
+        path: String,
+    },
+}
+
+impl Background {
+    /// Resolves the background of a page from the note's background configuration.
+    ///
+    /// Backgrounds referring to a file are looked up by file name in `files`, which lists the
+    /// template and resource files of the note. The virtual page's template path is used when
+    /// the configuration does not reference a file. Built-in patterns are recognized by their
+    /// title.
+    pub(crate) fn resolve(
+        config: &BackgroundConfig,
+        page_id: &PageUuid,
+        template_path: Option<&str>,
+        files: &[String],
+    ) -> Self {
+        let (visible, mut references, title) = if config.use_document_background {
+            (config.document_background.visible, Vec::new(), "")
+        } else if let Some(page_background) = config.page_backgrounds.get(page_id) {
+            (
+                page_background.visible,
+                vec![
+                    page_background.res_id.as_str(),
+                    page_background.value.as_str(),
+                ],
+                page_background.title.as_str(),
+            )
+        } else {
+            (true, Vec::new(), "")
+        };
+
+        if !visible {
+            return Self::Blank;
+        }
+
+        references.extend(template_path);
+        for reference in references.into_iter().filter(|r| !r.is_empty()) {
+            let name = reference.rsplit('/').next().unwrap_or(reference);
+            if let Some(path) = files.iter().find(|file| {
+                let file_name = file.rsplit('/').next().unwrap_or(file);
+                file_name == name || file_name.split('.').next() == Some(name)
+            }) {
+                return Self::Image { path: path.clone() };
+            }
+            log::debug!("Background file not found in archive: {}", reference);
+        }
+
+        Self::from_title(title)
+    }
+
+    fn from_title(title: &str) -> Self {
+        let title = title.to_lowercase();
+        if title.contains("dot") {
+            Self::Dotted {
+                spacing: DOT_SPACING,
+            }
+        } else if title.contains("grid") || title.contains("square") {
+            Self::Grid {
+                spacing: GRID_SPACING,
+            }
+        } else if title.contains("line") || title.contains("rule") {
+            Self::Lined {
+                spacing: LINE_SPACING,
+            }
+        } else {
+            Self::Blank
+        }
+    }
+
+    /// Returns the line segments of built-in line and grid patterns covering `area`.
+    ///
+    /// The pattern is aligned to the top left corner of the page, so it continues seamlessly
+    /// onto expanded canvases and stays in place when only part of the page is rendered.
+    pub fn pattern_lines(&self, page: &Rect, area: &Rect) -> Vec<((f32, f32), (f32, f32))> {
+        let (spacing, vertical) = match self {
+            Self::Lined { spacing } => (*spacing, false),
+            Self::Grid { spacing } => (*spacing, true),
+            _ => return Vec::new(),
+        };
+
+        let mut lines = steps(page.top, area.top, area.bottom, spacing)
+            .map(|y| ((area.left, y), (area.right, y)))
+            .collect::<Vec<_>>();
+        if vertical {
+            lines.extend(
+                steps(page.left, area.left, area.right, spacing)
+                    .map(|x| ((x, area.top), (x, area.bottom))),
+            );
+        }
+        lines
+    }
+
+    /// Returns the centres of the dots of a dotted page within `area`.
+    pub fn pattern_dots(&self, page: &Rect, area: &Rect) -> Vec<(f32, f32)> {
+        let Self::Dotted { spacing } = self else {
+            return Vec::new();
+        };
+
+        steps(page.top, area.top, area.bottom, *spacing)
+            .flat_map(|y| steps(page.left, area.left, area.right, *spacing).map(move |x| (x, y)))
+            .collect()
+    }
+}
+
+/// Returns the multiples of `spacing` away from `origin` that lie strictly between `start` and
+/// `end`.
+fn steps(origin: f32, start: f32, end: f32, spacing: f32) -> impl Iterator<Item = f32> {
+    let (first, last) = if spacing > 0.0 {
+        (
+            ((start - origin) / spacing).floor() as i64 + 1,
+            ((end - origin) / spacing).ceil() as i64 - 1,
+        )
+    } else {
+        (1, 0)
+    };
+    (first..=last).map(move |index| origin + index as f32 * spacing)
+}
+
+/// A decoded background image with non-premultiplied RGBA pixels.
+#[derive(Debug, Clone)]
+pub struct BackgroundImage {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+    /// The encoded PNG file as stored in the archive.
+    pub png_data: Vec<u8>,
+}
+
+impl BackgroundImage {
+    pub fn decode_png(png_data: Vec<u8>) -> Result<Self> {
+        let mut decoder = png::Decoder::new(std::io::Cursor::new(&png_data));
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info()?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer)?;
+        buffer.truncate(info.buffer_size());
+
+        let rgba = match info.color_type {
+            png::ColorType::Rgba => buffer,
+            png::ColorType::Rgb => buffer
+                .chunks_exact(3)
+                .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], u8::MAX])
+                .collect(),
+            png::ColorType::GrayscaleAlpha => buffer
+                .chunks_exact(2)
+                .flat_map(|ga| [ga[0], ga[0], ga[0], ga[1]])
+                .collect(),
+            png::ColorType::Grayscale => buffer
+                .iter()
+                .flat_map(|gray| [*gray, *gray, *gray, u8::MAX])
+                .collect(),
+            png::ColorType::Indexed => {
+                return Err(Error::UnsupportedImage(
+                    "Indexed PNG was not expanded".to_string(),
+                ));
+            }
+        };
+
+        Ok(Self {
+            width: info.width,
+            height: info.height,
+            rgba,
+            png_data,
+        })
+    }
+
+    /// Returns a copy of the image with every pixel passed through `map`.
+    pub fn map_colors(&self, map: impl Fn(Color) -> Color) -> Result<Self> {
+        let rgba = self
+            .rgba
+            .chunks_exact(4)
+            .flat_map(|pixel| {
+                let color = map(Color::new(pixel[3], pixel[0], pixel[1], pixel[2]));
+                [color.red(), color.green(), color.blue(), color.alpha()]
+            })
+            .collect::<Vec<_>>();
+
+        // Vector output embeds the encoded image, so it has to match the mapped pixels
+        let mut png_data = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png_data, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&rgba)?;
+        writer.finish()?;
+
+        Ok(Self {
+            width: self.width,
+            height: self.height,
+            rgba,
+            png_data,
+        })
+    }
+
+    /// Returns the pixels as premultiplied ARGB values, as expected by raqote.
+    pub fn premultiplied_argb(&self) -> Vec<u32> {
+        self.rgba
+            .chunks_exact(4)
+            .map(|pixel| {
+                let alpha = pixel[3] as u32;
+                let premultiply = |channel: u8| (channel as u32 * alpha + 127) / 255;
+                (alpha << 24)
+                    | (premultiply(pixel[0]) << 16)
+                    | (premultiply(pixel[1]) << 8)
+                    | premultiply(pixel[2])
+            })
+            .collect()
+    }
+}
+
+/// A page background ready to be drawn, including its image data if it has one.
+#[derive(Debug, Clone, Default)]
+pub struct LoadedBackground {
+    pub background: Background,
+    pub image: Option<BackgroundImage>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{note_tree::PageBackground, testing};
+
+    const PAGE: Rect = Rect {
+        left: 0.0,
+        top: 0.0,
+        right: 200.0,
+        bottom: 200.0,
+    };
+
+    fn page_background(res_id: &str, title: &str, visible: bool) -> PageBackground {
+        PageBackground {
+            cloud: false,
+            global: false,
+            height: 200.0,
+            res_id: res_id.to_string(),
+            res_index: 0,
+            title: title.to_string(),
+            type_: 0,
+            value: String::new(),
+            visible,
+            width: 200.0,
+        }
+    }
+
+    fn resolve(
+        page_background: Option<PageBackground>,
+        template_path: Option<&str>,
+        files: &[&str],
+    ) -> Background {
+        let mut config = testing::metadata().background_config;
+        let page_id = PageUuid::new_random();
+        config
+            .page_backgrounds
+            .extend(page_background.map(|background| (page_id, background)));
+        let files = files
+            .iter()
+            .map(|file| file.to_string())
+            .collect::<Vec<_>>();
+        Background::resolve(&config, &page_id, template_path, &files)
+    }
+
+    fn png(color_type: png::ColorType, pixels: &[u8], palette: Option<&[u8]>) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut encoder = png::Encoder::new(&mut data, 2, 1);
+        encoder.set_color(color_type);
+        encoder.set_depth(png::BitDepth::Eight);
+        if let Some(palette) = palette {
+            encoder.set_palette(palette.to_vec());
+        }
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(pixels).unwrap();
+        writer.finish().unwrap();
+        data
+    }
+
+    #[test]
+    fn patterns_are_recognized_by_title() {
+        let lined = Background::Lined {
+            spacing: LINE_SPACING,
+        };
+        let grid = Background::Grid {
+            spacing: GRID_SPACING,
+        };
+        let dotted = Background::Dotted {
+            spacing: DOT_SPACING,
+        };
+        for (title, expected) in [
+            ("Lines", &lined),
+            ("College ruled", &lined),
+            ("GRID 5mm", &grid),
+            ("Squared", &grid),
+            ("Dotted", &dotted),
+            // Dots win over the grid they are laid out in
+            ("Dot grid", &dotted),
+            ("Blank", &Background::Blank),
+            ("", &Background::Blank),
+        ] {
+            assert_eq!(
+                resolve(Some(page_background("", title, true)), None, &[]),
+                *expected,
+                "{}",
+                title
+            );
+        }
+    }
+
+    #[test]
+    fn files_are_found_by_name() {
+        let files = ["note/resource/abc123.png", "note/template/lines.png"];
+        let image = |path: &str| Background::Image {
+            path: path.to_string(),
+        };
+
+        // By the resource ID without extension, before the title
+        assert_eq!(
+            resolve(Some(page_background("abc123", "Grid", true)), None, &files),
+            image(files[0])
+        );
+        // By the file name of the template path
+        assert_eq!(
+            resolve(None, Some("/storage/templates/lines.png"), &files),
+            image(files[1])
+        );
+        // Missing files fall back to the title
+        assert_eq!(
+            resolve(Some(page_background("missing", "Grid", true)), None, &files),
+            Background::Grid {
+                spacing: GRID_SPACING
+            }
+        );
+        assert_eq!(resolve(None, None, &files), Background::Blank);
+    }
+
+    #[test]
+    fn hidden_backgrounds_are_blank() {
+        let files = ["note/resource/abc123.png"];
+        assert_eq!(
+            resolve(
+                Some(page_background("abc123", "Lines", false)),
+                None,
+                &files
+            ),
+            Background::Blank
+        );
+
+        let mut config = testing::metadata().background_config;
+        let page_id = PageUuid::new_random();
+        config
+            .page_backgrounds
+            .insert(page_id, page_background("abc123", "Lines", true));
+        config.use_document_background = true;
+        let files = files.map(str::to_string);
+        // The document background ignores the page's
+        assert_eq!(
+            Background::resolve(&config, &page_id, None, &files),
+            Background::Blank
+        );
+        config.document_background.visible = false;
+        assert_eq!(
+            Background::resolve(&config, &page_id, Some("abc123.png"), &files),
+            Background::Blank
+        );
+    }
+
+    #[test]
+    fn patterns_are_aligned_to_the_page() {
+        let lined = Background::Lined { spacing: 80.0 };
+        assert_eq!(
+            lined.pattern_lines(&PAGE, &PAGE),
+            [((0.0, 80.0), (200.0, 80.0)), ((0.0, 160.0), (200.0, 160.0))]
+        );
+        // Cropped areas keep the lines in place
+        let area = Rect {
+            left: 50.0,
+            top: 90.0,
+            right: 150.0,
+            bottom: 250.0,
+        };
+        assert_eq!(
+            lined.pattern_lines(&PAGE, &area),
+            [
+                ((50.0, 160.0), (150.0, 160.0)),
+                ((50.0, 240.0), (150.0, 240.0))
+            ]
+        );
+
+        let grid = Background::Grid { spacing: 80.0 };
+        let lines = grid.pattern_lines(&PAGE, &area);
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[2], ((80.0, 90.0), (80.0, 250.0)));
+
+        // Expanded canvases continue the pattern past the page
+        let dotted = Background::Dotted { spacing: 60.0 };
+        let canvas = Rect {
+            left: -100.0,
+            ..PAGE
+        };
+        let dots = dotted.pattern_dots(&PAGE, &canvas);
+        assert_eq!(dots.len(), 5 * 3);
+        assert_eq!(dots[0], (-60.0, 60.0));
+        assert!(dotted.pattern_lines(&PAGE, &PAGE).is_empty());
+        assert!(grid.pattern_dots(&PAGE, &PAGE).is_empty());
+        assert!(Background::Blank.pattern_lines(&PAGE, &PAGE).is_empty());
+        assert!(
+            Background::Lined { spacing: 0.0 }
+                .pattern_lines(&PAGE, &PAGE)
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn images_are_decoded_to_rgba() {
+        let rgba = [255, 0, 0, 255, 0, 0, 255, 128];
+        for (color_type, pixels, palette) in [
+            (png::ColorType::Rgba, &rgba[..], None),
+            (png::ColorType::Rgb, &[255, 0, 0, 0, 0, 255][..], None),
+            (
+                png::ColorType::Indexed,
+                &[0, 1][..],
+                Some(&[255, 0, 0, 0, 0, 255][..]),
+            ),
+        ] {
+            let data = png(color_type, pixels, palette);
+            let image = BackgroundImage::decode_png(data.clone()).unwrap();
+            assert_eq!((image.width, image.height), (2, 1));
+            assert_eq!(image.png_data, data);
+            let opaque = [255, 0, 0, 255, 0, 0, 255, 255];
+            let expected = if color_type == png::ColorType::Rgba {
+                &rgba
+            } else {
+                &opaque
+            };
+            assert_eq!(image.rgba, expected, "{:?}", color_type);
+        }
+
+        let gray = BackgroundImage::decode_png(png(
+            png::ColorType::GrayscaleAlpha,
+            &[10, 255, 20, 0],
+            None,
+        ))
+        .unwrap();
+        assert_eq!(gray.rgba, [10, 10, 10, 255, 20, 20, 20, 0]);
+
+        assert!(BackgroundImage::decode_png(b"not a png".to_vec()).is_err());
+    }
+
+    #[test]
+    fn images_are_premultiplied_and_mapped() {
+        let image = BackgroundImage::decode_png(png(
+            png::ColorType::Rgba,
+            &[255, 0, 0, 255, 0, 0, 255, 128],
+            None,
+        ))
+        .unwrap();
+        assert_eq!(image.premultiplied_argb(), [0xFFFF_0000, 0x8000_0080]);
+
+        let mapped = image
+            .map_colors(|color| Color::new(color.alpha(), color.blue(), color.green(), color.red()))
+            .unwrap();
+        assert_eq!(mapped.rgba, [0, 0, 255, 255, 255, 0, 0, 128]);
+        // The encoded image is updated for vector output
+        assert_eq!(
+            BackgroundImage::decode_png(mapped.png_data).unwrap().rgba,
+            mapped.rgba
+        );
+    }
+}
@@ -0,0 +1,241 @@
+use crate::{
+    color::Color,
+    geometry::Cap,
+    points::Point,
+    shape::{LineType, Shape},
+};
+
+/// Pressure range reported by the stylus when a shape does not specify it.
+const DEFAULT_MAX_PRESSURE: f32 = 4095.0;
+
+/// Lower bound of the pressure factor, so light touches still leave a visible line.
+const MIN_PRESSURE_FACTOR: f32 = 0.1;
+
+/// Edge length of the square grain texture used for pencil-like pens.
+pub const TEXTURE_SIZE: i32 = 64;
+
+/// Pen a stroke was drawn with, decoded from the pen shape type numbers of the Notes app.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum PenType {
+    Pencil,
+    #[default]
+    Ballpoint,
+    Fountain,
+    Brush,
+    Marker,
+    Highlighter,
+    Charcoal,
+    Unknown(u32),
+}
+
+impl PenType {
+    // Uncertain: derived from the keys of `penWithMap` in the pen settings
+    pub fn from_raw(value: u32) -> Self {
+        match value {
+            2 => Self::Pencil,
+            5 => Self::Fountain,
+            15 => Self::Marker,
+            21 => Self::Brush,
+            22 => Self::Charcoal,
+            36 => Self::Ballpoint,
+            37 => Self::Highlighter,
+            value => Self::Unknown(value),
+        }
+    }
+
+    pub fn to_raw(&self) -> u32 {
+        match self {
+            Self::Pencil => 2,
+            Self::Fountain => 5,
+            Self::Marker => 15,
+            Self::Brush => 21,
+            Self::Charcoal => 22,
+            Self::Ballpoint => 36,
+            Self::Highlighter => 37,
+            Self::Unknown(value) => *value,
+        }
+    }
+}
+
+impl std::fmt::Display for PenType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unknown(value) => write!(f, "Unknown({})", value),
+            pen_type => write!(f, "{:?}", pen_type),
+        }
+    }
+}
+
+impl serde::Serialize for PenType {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_u32(self.to_raw())
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for PenType {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let value: u32 = serde::Deserialize::deserialize(deserializer)?;
+        Ok(Self::from_raw(value))
+    }
+}
+
+/// How ink is combined with what is already on the page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Blend {
+    #[default]
+    Normal,
+    /// Darkens the page like a translucent marker, so other ink stays visible whatever the
+    /// drawing order.
+    Multiply,
+    /// Lightens the page, the counterpart of `Multiply` on dark pages.
+    Screen,
+}
+
+/// Describes how the points of a stroke are turned into ink.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Brush {
+    /// Nominal stroke width at full pressure, in page units.
+    pub width: f32,
+    /// Pressure value that corresponds to the nominal width.
+    pub max_pressure: f32,
+    pub color: Color,
+    pub line_type: LineType,
+    /// Offset into the dash pattern at which the line starts, in page units.
+    pub dash_phase: f32,
+    pub pen_type: PenType,
+    pub blend: Blend,
+}
+
+impl Brush {
+    pub fn from_shape(shape: &Shape) -> Self {
+        Self {
+            width: shape.stroke_width,
+            max_pressure: if shape.render_scale.max_pressure > 0.0 {
+                shape.render_scale.max_pressure
+            } else {
+                DEFAULT_MAX_PRESSURE
+            },
+            color: shape.color,
+            line_type: shape
+                .line_style
+                .as_ref()
+                .map(|line_style| line_style.line_type())
+                .unwrap_or_default(),
+            dash_phase: shape
+                .line_style
+                .as_ref()
+                .map(|line_style| line_style.phase)
+                .unwrap_or_default(),
+            pen_type: shape.pen_type.unwrap_or_default(),
+            blend: match shape.pen_type {
+                Some(PenType::Highlighter) => Blend::Multiply,
+                _ => Blend::Normal,
+            },
+        }
+    }
+
+    /// Returns the ink width at the given point.
+    ///
+    /// Fountain pens and brushes taper with pressure, pencils and ballpoints respond only
+    /// slightly, markers and highlighters keep a constant width.
+    pub fn point_width(&self, point: &Point) -> f32 {
+        let factor = (point.pressure as f32 / self.max_pressure).clamp(MIN_PRESSURE_FACTOR, 1.0);
+        let factor = match self.pen_type {
+            PenType::Fountain | PenType::Brush | PenType::Unknown(_) => factor,
+            PenType::Pencil | PenType::Charcoal => 0.6 + 0.4 * factor,
+            PenType::Ballpoint => 0.8 + 0.2 * factor,
+            PenType::Marker | PenType::Highlighter => 1.0,
+        };
+        self.width * factor
+    }
+
+    /// Returns the color the ink is laid down with, including the translucency of the pen.
+    pub fn ink_color(&self) -> Color {
+        let opacity = match self.pen_type {
+            PenType::Pencil => 0.8,
+            PenType::Charcoal => 0.9,
+            PenType::Highlighter => 0.5,
+            _ => 1.0,
+        };
+        Color::new(
+            (self.color.alpha() as f32 * opacity).round() as u8,
+            self.color.red(),
+            self.color.green(),
+            self.color.blue(),
+        )
+    }
+
+    pub(crate) fn cap(&self) -> Cap {
+        match self.pen_type {
+            PenType::Marker | PenType::Highlighter => Cap::Square,
+            _ => Cap::Round,
+        }
+    }
+
+    /// Returns a tileable, premultiplied ARGB grain texture for pens that leave a textured line
+    /// on the device, or `None` for pens with solid ink.
+    pub fn texture(&self) -> Option<Vec<u32>> {
+        let strength = match self.pen_type {
+            PenType::Pencil => 0.6,
+            PenType::Charcoal => 0.8,
+            _ => return None,
+        };
+
+        let color = self.ink_color();
+        let mut state = 0x2545_F491_u32;
+        let texture = (0..TEXTURE_SIZE * TEXTURE_SIZE)
+            .map(|_| {
+                // xorshift32, deterministic so repeated renders are identical
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                let noise = (state & 0xFF) as f32 / 255.0;
+                let alpha = color.alpha() as f32 * (1.0 - strength * noise);
+                let premultiply = |channel: u8| (channel as f32 * alpha / 255.0).round() as u32;
+                ((alpha.round() as u32) << 24)
+                    | (premultiply(color.red()) << 16)
+                    | (premultiply(color.green()) << 8)
+                    | premultiply(color.blue())
+            })
+            .collect();
+        Some(texture)
+    }
+
+    /// Returns alternating on/off lengths of the line pattern, or `None` for solid lines.
+    ///
+    /// Lengths scale with the brush width, so patterns keep their look for thick lines. A zero
+    /// length "on" interval yields a round dot.
+    pub fn dash_pattern(&self) -> Option<Vec<f32>> {
+        let unit = self.width.max(1.0);
+        match self.line_type {
+            LineType::Solid => None,
+            LineType::Dashed => Some(vec![6.0 * unit, 4.0 * unit]),
+            LineType::Dotted => Some(vec![0.0, 2.5 * unit]),
+            LineType::DashDot => Some(vec![6.0 * unit, 3.0 * unit, 0.0, 3.0 * unit]),
+            LineType::Unknown(value) => {
+                log::warn!("Unknown line type {}, drawing a solid line", value);
+                None
+            }
+        }
+    }
+}
+
+impl Default for Brush {
+    fn default() -> Self {
+        Self {
+            width: 1.0,
+            max_pressure: DEFAULT_MAX_PRESSURE,
+            color: Color::BLACK,
+            line_type: LineType::Solid,
+            dash_phase: 0.0,
+            pen_type: PenType::default(),
+            blend: Blend::Normal,
+        }
+    }
+}
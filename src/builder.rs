@@ -0,0 +1,712 @@
+use std::{collections::HashMap, io::Write};
+
+use chrono::{DateTime, Duration, Utc};
+use zip::{ZipWriter, write::SimpleFileOptions};
+
+use crate::{
+    brush::PenType,
+    color::Color,
+    error::Result,
+    id::{
+        LayerId, NoteUuid, PageModelUuid, PageUuid, PenId, PointsUuid, ShapeGroupUuid, StrokeUuid,
+        VirtualDocUuid, VirtualPageUuid,
+    },
+    json::{Dimensions, Layer},
+    note_tree::{
+        BackgroundConfig, BackgroundSettings, CanvasState, DeviceDimensions, DeviceInfo,
+        DocBackground, LineStyle, NoteMetadata, NoteTree, PageInfo, PenLineStyle, PenSettings,
+        QuickPenList, ViewPortPos, ZoomInfo,
+    },
+    page_model::{PageModel, PageModelGroup},
+    points::{Header, Point, PointsFile, Stroke, UuidLayout},
+    shape::{DisplayScale, Shape, ShapeGroup},
+    virtual_doc::{Content, VirtualDoc},
+    virtual_page::VirtualPage,
+};
+
+/// Pressure range written for created strokes.
+pub(crate) const MAX_PRESSURE: f32 = 4095.0;
+
+/// Pen width written to the pen settings of created notes.
+pub(crate) const DEFAULT_PEN_WIDTH: f32 = 3.0;
+
+/// Layer all created strokes are placed on.
+const LAYER: LayerId = LayerId::new(0);
+
+// Uncertain: version of the points files written by the Notes app
+const POINTS_VERSION: u32 = 1;
+
+// Uncertain: name of the root directory of multi-note archives
+const MULTI_NOTE_ROOT: &str = "notes";
+
+/// Screen of the device a note is created for. Pages default to its size in pixels.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Device {
+    pub name: String,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Device {
+    pub fn new(name: impl Into<String>, width: f32, height: f32) -> Self {
+        Self {
+            name: name.into(),
+            width,
+            height,
+        }
+    }
+
+    /// The Boox Note Air 4 C, which the format was reverse engineered from.
+    pub fn note_air_4c() -> Self {
+        Self::new("NoteAir4C", 1860.0, 2480.0)
+    }
+}
+
+impl Default for Device {
+    fn default() -> Self {
+        Self::note_air_4c()
+    }
+}
+
+/// A stroke to add to a created page.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StrokeBuilder {
+    pub pen_type: PenType,
+    pub color: Color,
+    pub width: f32,
+    /// Points of the stroke. Their timestamps are milliseconds since the note was created.
+    pub points: Vec<Point>,
+}
+
+/// A page to add to a created note.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PageBuilder {
+    size: Option<(f32, f32)>,
+    pub(crate) strokes: Vec<StrokeBuilder>,
+}
+
+impl PageBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the size of the page in page units, instead of the size of the device screen.
+    pub fn size(mut self, width: f32, height: f32) -> Self {
+        self.size = Some((width, height));
+        self
+    }
+
+    pub fn stroke(mut self, stroke: StrokeBuilder) -> Self {
+        self.strokes.push(stroke);
+        self
+    }
+}
+
+/// Creates a new note from scratch, ready to be written as a `.note` archive.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NoteBuilder {
+    name: String,
+    device: Device,
+    created: DateTime<Utc>,
+    pages: Vec<PageBuilder>,
+}
+
+impl NoteBuilder {
+    pub fn new(name: impl Into<String>) -> Self {
+        let now = Utc::now();
+        Self {
+            name: name.into(),
+            device: Device::default(),
+            // Timestamps are stored in milliseconds
+            created: DateTime::from_timestamp_millis(now.timestamp_millis()).unwrap_or(now),
+            pages: Vec::new(),
+        }
+    }
+
+    pub fn device(mut self, device: Device) -> Self {
+        self.device = device;
+        self
+    }
+
+    pub fn created(mut self, created: DateTime<Utc>) -> Self {
+        self.created = created;
+        self
+    }
+
+    pub fn page(mut self, page: PageBuilder) -> Self {
+        self.pages.push(page);
+        self
+    }
+
+    /// Writes the note as a single-note archive.
+    pub fn write(&self, writer: impl Write + std::io::Seek) -> Result {
+        let note = self.build();
+        let root = note.metadata.note_id.to_simple_string();
+
+        let mut zip = ZipWriter::new(writer);
+        let mut note_info = Vec::new();
+        NoteTree::new(vec![note.metadata.clone()]).write(&mut note_info)?;
+        write_file(&mut zip, &format!("{}/note/pb/note_info", root), &note_info)?;
+        note.write_files(&mut zip, &root)?;
+        zip.finish()?;
+        Ok(())
+    }
+
+    /// Writes several notes into one multi-note archive.
+    pub fn write_multi(notes: &[NoteBuilder], writer: impl Write + std::io::Seek) -> Result {
+        let notes = notes.iter().map(NoteBuilder::build).collect::<Vec<_>>();
+
+        let mut zip = ZipWriter::new(writer);
+        let mut note_tree = Vec::new();
+        NoteTree::new(notes.iter().map(|note| note.metadata.clone()).collect())
+            .write(&mut note_tree)?;
+        write_file(
+            &mut zip,
+            &format!("{}/note_tree", MULTI_NOTE_ROOT),
+            &note_tree,
+        )?;
+        for note in &notes {
+            let root = format!(
+                "{}/{}",
+                MULTI_NOTE_ROOT,
+                note.metadata.note_id.to_simple_string()
+            );
+            note.write_files(&mut zip, &root)?;
+        }
+        zip.finish()?;
+        Ok(())
+    }
+
+    fn build(&self) -> BuiltNote {
+        let pages = self
+            .pages
+            .iter()
+            .enumerate()
+            .map(|(index, page)| self.build_page(index, page))
+            .collect::<Vec<_>>();
+        let page_rect = dimensions(self.device.width, self.device.height);
+        let note_id = NoteUuid::new_random();
+        let first_page_id = pages
+            .first()
+            .map(|page| page.page_model.page_id)
+            .unwrap_or_else(PageUuid::new_random);
+
+        let metadata = NoteMetadata {
+            note_id,
+            created: self.created,
+            modified: pages
+                .iter()
+                .map(|page| page.page_model.modified)
+                .max()
+                .unwrap_or(self.created),
+            name: self.name.clone(),
+            flag: 0,
+            pen_width: DEFAULT_PEN_WIDTH,
+            scale_factor: 1.0,
+            pen_settings: PenSettings {
+                fill_color: Color::BLACK.argb(),
+                graphics_shape_color: Color::BLACK.argb(),
+                graphics_shape_type: 0,
+                normal_pen_shape_type: PenType::default(),
+                pen_line_style: PenLineStyle {
+                    line_style: LineStyle {
+                        phase: 0.0,
+                        type_: 0,
+                    },
+                },
+                pen_width_map: HashMap::from([(
+                    PenType::default().to_raw() as u8,
+                    DEFAULT_PEN_WIDTH,
+                )]),
+                quick_pen_list: QuickPenList {
+                    quick_pens: Vec::new(),
+                    selected_id: PenId::from_id(0),
+                },
+                shape_line_style: PenLineStyle {
+                    line_style: LineStyle {
+                        phase: 0.0,
+                        type_: 0,
+                    },
+                },
+            },
+            canvas_state: CanvasState {
+                canvas_expand_type: String::new(),
+                cover_page_id: String::new(),
+                default_page_rect: page_rect.clone(),
+                page_info_map: pages
+                    .iter()
+                    .map(|page| {
+                        let dimensions = &page.page_model.dimensions;
+                        let page_info = PageInfo {
+                            current_layer_id: LAYER,
+                            height: dimensions.height() as u32,
+                            last_modify_time: page.page_model.modified.timestamp_millis() as u64,
+                            layer_count: 1,
+                            layer_list: page.page_model.layers.clone(),
+                            width: dimensions.width() as u32,
+                        };
+                        (page.page_model.page_id, page_info)
+                    })
+                    .collect(),
+                zoom_info: ZoomInfo {
+                    fit_to_screen: true,
+                    scale_type: 0,
+                    view_port_height: self.device.height,
+                    view_port_pos: ViewPortPos {
+                        is_empty: true,
+                        pressure: 0.0,
+                        size: 0.0,
+                        tilt_x: 0,
+                        tilt_y: 0,
+                        timestamp: 0,
+                        x: 0.0,
+                        y: 0.0,
+                    },
+                    view_port_width: self.device.width,
+                    viewport_scale: 1.0,
+                },
+            },
+            background_config: BackgroundConfig {
+                background_config: BackgroundSettings {
+                    apply_all_page: false,
+                    as_default: false,
+                    canvas_auto_expand: false,
+                    scale_type: 0,
+                },
+                document_background: DocBackground {
+                    cloud: false,
+                    global: false,
+                    height: self.device.height,
+                    res_index: 0,
+                    type_: 0,
+                    visible: true,
+                    width: self.device.width,
+                },
+                page_backgrounds: HashMap::new(),
+                use_document_background: false,
+            },
+            device_info: DeviceInfo {
+                device_name: self.device.name.clone(),
+                size: DeviceDimensions {
+                    width: self.device.width,
+                    height: self.device.height,
+                },
+            },
+            fill_color: Color::BLACK.argb(),
+            pen_type: PenType::default(),
+            active_pages: pages.iter().map(|page| page.page_model.page_id).collect(),
+            reserved_pages: Vec::new(),
+            canvas_width: self.device.width,
+            canvas_height: self.device.height,
+            location: String::new(),
+            has_share_section: 0,
+            stroke_data_len: 0,
+            has_share_user: 0,
+            share_user: String::new(),
+            detached_pages: Vec::new(),
+            raw: Vec::new(),
+        };
+
+        // Uncertain: values written by the Notes app for notes that are not backed by a document
+        let virtual_doc = VirtualDoc {
+            virtual_doc_id: VirtualDocUuid::new_random(),
+            created: metadata.created,
+            modified: metadata.modified,
+            page_id: first_page_id,
+            stability: 0.0,
+            content: Content {
+                content_id: note_id.to_simple_string(),
+                content_page_id: first_page_id.to_simple_string(),
+                content_page_size: page_rect,
+                content_relative_path: String::new(),
+                content_type: "note".to_string(),
+            },
+            raw: Vec::new(),
+        };
+
+        BuiltNote {
+            metadata,
+            virtual_doc,
+            pages,
+        }
+    }
+
+    fn build_page(&self, index: usize, page: &PageBuilder) -> BuiltPage {
+        let page_id = PageUuid::new_random();
+        let (width, height) = page.size.unwrap_or((self.device.width, self.device.height));
+        let page_rect = dimensions(width, height);
+        let shape_group_id = ShapeGroupUuid::new_random();
+
+        let mut points_file = PointsFile::new(Header {
+            version: POINTS_VERSION,
+            page_id,
+            page_id_layout: UuidLayout::Hyphenated,
+            points_id: PointsUuid::new_random(),
+        });
+        let points_id = points_file.header().points_id;
+
+        let shapes = page
+            .strokes
+            .iter()
+            .enumerate()
+            .map(|(z_order, stroke)| {
+                let stroke_id = StrokeUuid::new_random();
+                let time = |point: Option<&Point>| {
+                    self.created
+                        + Duration::milliseconds(
+                            point.map(|point| point.timestamp_rel).unwrap_or_default() as i64,
+                        )
+                };
+                points_file.insert_stroke(
+                    stroke_id,
+                    Stroke {
+                        points: stroke.points.clone(),
+                    },
+                );
+                Shape {
+                    stroke_id,
+                    created: time(stroke.points.first()),
+                    modified: time(stroke.points.last()),
+                    color: stroke.color,
+                    pen_type: Some(stroke.pen_type),
+                    stroke_width: stroke.width,
+                    bbox: bounding_box(&stroke.points, stroke.width),
+                    render_scale: DisplayScale {
+                        display_scale: 1.0,
+                        max_pressure: MAX_PRESSURE,
+                        revised_display_scale: 1.0,
+                        source: 0,
+                    },
+                    z_order: z_order as i64,
+                    points_id: Some(points_id),
+                    line_style: None,
+                    shape_group_id,
+                    layer_id: Some(LAYER),
+                    points_json: "[]".to_string(),
+                    raw: Vec::new(),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let modified = shapes
+            .iter()
+            .map(|shape| shape.modified)
+            .max()
+            .unwrap_or(self.created);
+
+        BuiltPage {
+            page_model: PageModel {
+                page_id,
+                layers: vec![Layer {
+                    id: LAYER,
+                    lock: false,
+                    show: true,
+                }],
+                created: self.created,
+                modified,
+                dimensions: page_rect.clone(),
+                raw: Vec::new(),
+            },
+            virtual_page: VirtualPage {
+                page_id,
+                created: self.created,
+                modified,
+                zoom_scale: 1.0,
+                dimensions: page_rect.clone(),
+                layout: page_rect.clone(),
+                geo: page_rect,
+                geo_layout: String::new(),
+                template_path: String::new(),
+                page_number: (index + 1).to_string(),
+                raw: Vec::new(),
+            },
+            shape_group_id,
+            shapes,
+            points_file,
+        }
+    }
+}
+
+/// A note with all of its files, ready to be written.
+struct BuiltNote {
+    metadata: NoteMetadata,
+    virtual_doc: VirtualDoc,
+    pages: Vec<BuiltPage>,
+}
+
+struct BuiltPage {
+    page_model: PageModel,
+    virtual_page: VirtualPage,
+    shape_group_id: ShapeGroupUuid,
+    shapes: Vec<Shape>,
+    points_file: PointsFile,
+}
+
+impl BuiltNote {
+    /// Writes the files of the note below `root`.
+    fn write_files<W: Write + std::io::Seek>(&self, zip: &mut ZipWriter<W>, root: &str) -> Result {
+        let mut buffer = Vec::new();
+        self.virtual_doc.write(&mut buffer)?;
+        write_file(
+            zip,
+            &format!(
+                "{}/virtual/doc/pb/{}",
+                root,
+                self.metadata.note_id.to_simple_string()
+            ),
+            &buffer,
+        )?;
+
+        for page in &self.pages {
+            let page_id = page.page_model.page_id.to_simple_string();
+            let timestamp = page.page_model.modified.timestamp_millis();
+
+            let mut buffer = Vec::new();
+            PageModelGroup {
+                page_models: vec![page.page_model.clone()],
+            }
+            .write(&mut buffer)?;
+            write_file(
+                zip,
+                &format!(
+                    "{}/pageModel/pb/{}",
+                    root,
+                    PageModelUuid::new_random().to_simple_string()
+                ),
+                &buffer,
+            )?;
+
+            let mut buffer = Vec::new();
+            page.virtual_page.write(&mut buffer)?;
+            write_file(
+                zip,
+                &format!(
+                    "{}/virtual/page/pb/{}",
+                    root,
+                    VirtualPageUuid::new_random().to_simple_string()
+                ),
+                &buffer,
+            )?;
+
+            // Uncertain: name of the file inside the shape group archive
+            let shape_group_name = format!(
+                "{}#{}#{}",
+                page_id,
+                page.shape_group_id.to_simple_string(),
+                timestamp
+            );
+            let mut buffer = Vec::new();
+            ShapeGroup::new(shape_group_name.clone(), page.shapes.clone()).write(&mut buffer)?;
+            write_file(
+                zip,
+                &format!("{}/shape/{}.zip", root, shape_group_name),
+                &buffer,
+            )?;
+
+            // Uncertain: the part after the points UUID is not used when reading
+            let mut buffer = Vec::new();
+            page.points_file.write(&mut buffer)?;
+            write_file(
+                zip,
+                &format!(
+                    "{}/point/{}/{}#{}#points",
+                    root,
+                    page_id,
+                    page_id,
+                    page.points_file.header().points_id.to_simple_string()
+                ),
+                &buffer,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn write_file<W: Write + std::io::Seek>(zip: &mut ZipWriter<W>, path: &str, data: &[u8]) -> Result {
+    zip.start_file(path, SimpleFileOptions::default())?;
+    zip.write_all(data)?;
+    Ok(())
+}
+
+fn dimensions(width: f32, height: f32) -> Dimensions {
+    Dimensions {
+        top: 0.0,
+        right: width,
+        bottom: height,
+        left: 0.0,
+        empty: false,
+        stability: 0,
+    }
+}
+
+/// Returns the bounding box of the points, grown by half the stroke width.
+fn bounding_box(points: &[Point], width: f32) -> Dimensions {
+    if points.is_empty() {
+        return Dimensions {
+            empty: true,
+            ..dimensions(0.0, 0.0)
+        };
+    }
+
+    let radius = width / 2.0;
+    let fold = |init: f32, select: fn(&Point) -> f32, pick: fn(f32, f32) -> f32| {
+        points.iter().map(select).fold(init, pick)
+    };
+    Dimensions {
+        top: fold(f32::MAX, |point| point.y, f32::min) - radius,
+        right: fold(f32::MIN, |point| point.x, f32::max) + radius,
+        bottom: fold(f32::MIN, |point| point.y, f32::max) + radius,
+        left: fold(f32::MAX, |point| point.x, f32::min) - radius,
+        empty: false,
+        stability: 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeSet, io::Cursor};
+
+    use regex::Regex;
+
+    use super::*;
+    use crate::{NoteFile, testing};
+
+    /// Returns the paths of the files of an archive relative to the directory of the note,
+    /// with IDs and numbers replaced by placeholders. The note info of single-note archives
+    /// is left out, as multi-note archives keep it in the note tree.
+    fn path_layout(archive: &[u8]) -> BTreeSet<String> {
+        let id = Regex::new(r"[0-9a-f]{8}-?[0-9a-f]{4}-?[0-9a-f]{4}-?[0-9a-f]{4}-?[0-9a-f]{12}")
+            .unwrap();
+        let number = Regex::new(r"\d+").unwrap();
+        testing::entries(archive)
+            .into_keys()
+            .filter(|path| !path.ends_with('/'))
+            .filter_map(|path| {
+                let path = id.replace_all(&path, "{id}");
+                let path = number.replace_all(&path, "{n}");
+                path.split_once("{id}/").map(|(_, path)| path.to_string())
+            })
+            .filter(|path| path != "note/pb/note_info")
+            .collect()
+    }
+
+    #[test]
+    fn created_note_is_read_back() {
+        let builder = testing::note();
+        let file = NoteFile::read(Cursor::new(testing::archive())).unwrap();
+        let notes = file.list_notes();
+        assert_eq!(notes.len(), 1);
+        let (note_id, name) = notes.into_iter().next().unwrap();
+        assert_eq!(name, "Test");
+
+        let mut note = file.get_note(&note_id).unwrap();
+        let page_ids = note.active_pages().to_vec();
+        assert_eq!(page_ids.len(), builder.pages.len());
+
+        for (page_id, page_builder) in page_ids.iter().zip(&builder.pages) {
+            let mut page = note.get_page(page_id).unwrap();
+            assert_eq!(page.page_model().page_id, *page_id);
+            assert_eq!(page.virtual_page().as_ref().unwrap().page_id, *page_id);
+
+            // Points files are found by the points UUID in their name
+            let points_files = page.points_files().unwrap().clone();
+            assert_eq!(points_files.len(), 1);
+            let (points_id, points_files) = points_files.into_iter().next().unwrap();
+            assert_eq!(points_files.len(), 1);
+            assert_eq!(points_files[0].header().points_id, points_id);
+            assert_eq!(points_files[0].header().page_id, *page_id);
+
+            let strokes = page.strokes().unwrap();
+            assert_eq!(strokes.len(), page_builder.strokes.len());
+            for ((shape, stroke), stroke_builder) in strokes.iter().zip(&page_builder.strokes) {
+                assert_eq!(shape.pen_type, Some(stroke_builder.pen_type));
+                assert_eq!(shape.color, stroke_builder.color);
+                assert_eq!(shape.stroke_width, stroke_builder.width);
+                assert_eq!(shape.points_id, Some(points_id));
+                assert_eq!(stroke.points, stroke_builder.points);
+            }
+        }
+
+        let virtual_doc = note.virtual_doc().unwrap();
+        assert_eq!(virtual_doc.page_id, page_ids[0]);
+        assert_eq!(virtual_doc.content.content_id, note_id.to_simple_string());
+        assert_eq!(
+            virtual_doc.content.content_page_size,
+            dimensions(builder.device.width, builder.device.height)
+        );
+    }
+
+    #[test]
+    fn created_notes_are_read_back_from_one_archive() {
+        let builders = [testing::note(), NoteBuilder::new("Empty")];
+        let mut buffer = Cursor::new(Vec::new());
+        NoteBuilder::write_multi(&builders, &mut buffer).unwrap();
+
+        let file = NoteFile::read(Cursor::new(buffer.into_inner())).unwrap();
+        let notes = file.list_notes();
+        assert_eq!(notes.len(), 2);
+        for (note_id, name) in notes {
+            let mut note = file.get_note(&note_id).unwrap();
+            let pages = if name == "Test" { 3 } else { 0 };
+            assert_eq!(note.active_pages().len(), pages);
+            let virtual_doc = note.virtual_doc().unwrap();
+            assert_eq!(virtual_doc.content.content_id, note_id.to_simple_string());
+        }
+    }
+
+    /// Rebuilds the device samples from their strokes, which must give the same strokes and
+    /// only files of the kinds a device writes.
+    #[test]
+    fn device_samples_are_rebuilt() {
+        for (path, archive) in testing::device_samples() {
+            let file = NoteFile::read(Cursor::new(archive.clone())).unwrap();
+            let sample_layout = path_layout(&archive);
+            for note_id in file.list_notes().into_keys() {
+                let mut note = file.get_note(&note_id).unwrap();
+                let mut builder = NoteBuilder::new(note.name());
+                let mut expected = Vec::new();
+                for page_id in note.active_pages().to_vec() {
+                    let mut page = note.get_page(&page_id).unwrap();
+                    let rect = page.page_rect();
+                    let mut page_builder = PageBuilder::new().size(rect.width(), rect.height());
+                    let strokes = page.strokes().unwrap();
+                    for (shape, stroke) in &strokes {
+                        page_builder = page_builder.stroke(StrokeBuilder {
+                            pen_type: shape.pen_type.unwrap_or_default(),
+                            color: shape.color,
+                            width: shape.stroke_width,
+                            points: stroke.points.clone(),
+                        });
+                    }
+                    builder = builder.page(page_builder);
+                    expected.push(strokes);
+                }
+
+                let mut buffer = Cursor::new(Vec::new());
+                builder.write(&mut buffer).unwrap();
+                let built = buffer.into_inner();
+                let unknown = path_layout(&built)
+                    .difference(&sample_layout)
+                    .cloned()
+                    .collect::<Vec<_>>();
+                assert!(unknown.is_empty(), "{}: {:?}", path.display(), unknown);
+
+                let file = NoteFile::read(Cursor::new(built)).unwrap();
+                let built_id = *file.list_notes().keys().next().unwrap();
+                let mut built = file.get_note(&built_id).unwrap();
+                assert_eq!(built.active_pages().len(), expected.len());
+                for (page_id, expected) in built.active_pages().to_vec().iter().zip(expected) {
+                    let strokes = built.get_page(page_id).unwrap().strokes().unwrap();
+                    assert_eq!(strokes.len(), expected.len(), "{}", path.display());
+                    for ((shape, stroke), (expected_shape, expected_stroke)) in
+                        strokes.iter().zip(&expected)
+                    {
+                        assert_eq!(stroke.points, expected_stroke.points);
+                        assert_eq!(shape.color, expected_shape.color);
+                        assert_eq!(shape.stroke_width, expected_shape.stroke_width);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,81 @@
+/// A 32-bit ARGB color as stored by the Boox Notes app.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Color(u32);
+
+impl Color {
+    pub const BLACK: Self = Self(0xFF00_0000);
+    pub const WHITE: Self = Self(0xFFFF_FFFF);
+
+    pub const fn from_argb(argb: u32) -> Self {
+        Self(argb)
+    }
+
+    pub fn new(alpha: u8, red: u8, green: u8, blue: u8) -> Self {
+        Self(u32::from_be_bytes([alpha, red, green, blue]))
+    }
+
+    pub fn argb(&self) -> u32 {
+        self.0
+    }
+
+    pub fn alpha(&self) -> u8 {
+        (self.0 >> 24) as u8
+    }
+
+    pub fn red(&self) -> u8 {
+        (self.0 >> 16) as u8
+    }
+
+    pub fn green(&self) -> u8 {
+        (self.0 >> 8) as u8
+    }
+
+    pub fn blue(&self) -> u8 {
+        self.0 as u8
+    }
+
+    /// Returns the color with its HSL lightness inverted, keeping hue, saturation and alpha.
+    /// Black becomes white and vice versa, while saturated colors stay recognizable.
+    pub fn invert_lightness(&self) -> Self {
+        let channels = [self.red(), self.green(), self.blue()];
+        let max = channels.iter().max().copied().unwrap_or_default() as i32;
+        let min = channels.iter().min().copied().unwrap_or_default() as i32;
+        let invert = |channel: u8| (channel as i32 + 255 - max - min).clamp(0, 255) as u8;
+        Self::new(
+            self.alpha(),
+            invert(self.red()),
+            invert(self.green()),
+            invert(self.blue()),
+        )
+    }
+
+    /// Returns the HSL lightness of the color, between 0 and 1.
+    pub fn lightness(&self) -> f32 {
+        let channels = [self.red(), self.green(), self.blue()];
+        let max = channels.iter().max().copied().unwrap_or_default() as f32;
+        let min = channels.iter().min().copied().unwrap_or_default() as f32;
+        (max + min) / 2.0 / 255.0
+    }
+
+    /// Formats the color channels as a `#rrggbb` string, ignoring alpha.
+    pub fn to_hex_rgb(&self) -> String {
+        format!("#{:06x}", self.0 & 0x00FF_FFFF)
+    }
+}
+
+impl std::fmt::Display for Color {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Color({:08x})", self.0)
+    }
+}
+
+impl From<Color> for raqote::SolidSource {
+    fn from(color: Color) -> Self {
+        raqote::SolidSource::from_unpremultiplied_argb(
+            color.alpha(),
+            color.red(),
+            color.green(),
+            color.blue(),
+        )
+    }
+}
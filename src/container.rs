@@ -1,9 +1,11 @@
 use std::{
+    collections::BTreeMap,
+    io::{Read, Write},
     path::Path,
     sync::{Arc, RwLock},
 };
 
-use zip::ZipArchive;
+use zip::{ZipArchive, ZipWriter, write::SimpleFileOptions};
 
 use crate::error::{Error, Result};
 
@@ -18,6 +20,9 @@ pub struct Container<R: std::io::Read + std::io::Seek> {
     container_type: Arc<ContainerType>,
     archive: Arc<RwLock<ZipArchive<R>>>,
     root_path: Arc<String>,
+    /// Files added or replaced (`Some`) and removed (`None`) since the archive was opened, by
+    /// absolute path. Reads see these changes, writing the archive applies them.
+    changes: Arc<RwLock<BTreeMap<String, Option<Vec<u8>>>>>,
 }
 
 impl<R: std::io::Read + std::io::Seek> Container<R> {
@@ -43,6 +48,7 @@ impl<R: std::io::Read + std::io::Seek> Container<R> {
             container_type: Arc::new(container_type),
             archive: Arc::new(RwLock::new(archive)),
             root_path: Arc::new(root_path),
+            changes: Arc::new(RwLock::new(BTreeMap::new())),
         })
     }
 
@@ -50,7 +56,7 @@ impl<R: std::io::Read + std::io::Seek> Container<R> {
         &self.container_type
     }
 
-    fn get_file_path(&self, path: &str) -> String {
+    pub fn get_file_path(&self, path: &str) -> String {
         if self.container_type.as_ref() == &ContainerType::SingleNote {
             return path.to_string();
         }
@@ -59,42 +65,163 @@ impl<R: std::io::Read + std::io::Seek> Container<R> {
 
     pub fn list_directory(&self, path: &str) -> Vec<String> {
         let prefixed_path = self.get_file_path(path);
-        self.archive
+        let changes = self.changes.read().unwrap();
+        let mut names = self
+            .archive
             .read()
             .unwrap()
             .file_names()
             .filter_map(|name| {
-                if name.starts_with(&prefixed_path) && !name.ends_with("/") {
+                if name.starts_with(&prefixed_path)
+                    && !name.ends_with("/")
+                    && !changes.contains_key(name)
+                {
                     Some(name.to_string())
                 } else {
                     None
                 }
             })
-            .collect()
+            .collect::<Vec<_>>();
+        names.extend(
+            changes
+                .iter()
+                .filter(|(name, data)| name.starts_with(&prefixed_path) && data.is_some())
+                .map(|(name, _)| name.clone()),
+        );
+        names
     }
 
     pub fn get_file_relative<T, F>(&mut self, path: &str, file_op_fn: F) -> Result<T>
     where
-        F: FnOnce(zip::read::ZipFile<'_, R>) -> Result<T>,
+        F: FnOnce(&mut dyn Read) -> Result<T>,
     {
         let file_path = self.get_file_path(path);
-        let mut archive = self.archive.write().unwrap();
-        let file = archive.by_name(&file_path).map_err(Error::Zip)?;
-        file_op_fn(file)
+        self.get_file_absolute(&file_path, file_op_fn)
     }
 
     pub fn get_file_absolute<T, F>(&mut self, path: &str, file_op_fn: F) -> Result<T>
     where
-        F: FnOnce(zip::read::ZipFile<'_, R>) -> Result<T>,
+        F: FnOnce(&mut dyn Read) -> Result<T>,
     {
+        match self.changes.read().unwrap().get(path) {
+            Some(Some(data)) => return file_op_fn(&mut data.as_slice()),
+            Some(None) => return Err(Error::Zip(zip::result::ZipError::FileNotFound)),
+            None => {}
+        }
+
+        let mut archive = self.archive.write().unwrap();
+        let mut file = archive.by_name(path).map_err(Error::Zip)?;
+        file_op_fn(&mut file)
+    }
+
+    /// Adds or replaces a file, given by its absolute path.
+    pub fn put_file(&self, path: String, data: Vec<u8>) {
+        self.changes.write().unwrap().insert(path, Some(data));
+    }
+
+    /// Removes a file, given by its absolute path.
+    pub fn remove_file(&self, path: &str) {
+        self.changes.write().unwrap().insert(path.to_string(), None);
+    }
+
+    /// Returns the stored size of a file, given by its absolute path: the compressed size of
+    /// archive entries, or the length of added files.
+    pub fn file_size(&self, path: &str) -> Option<u64> {
+        match self.changes.read().unwrap().get(path) {
+            Some(Some(data)) => return Some(data.len() as u64),
+            Some(None) => return None,
+            None => {}
+        }
+
         let mut archive = self.archive.write().unwrap();
-        let file = archive.by_name(path).map_err(Error::Zip)?;
-        file_op_fn(file)
+        let index = archive.index_for_name(path)?;
+        let file = archive.by_index_raw(index).ok()?;
+        Some(file.compressed_size())
     }
 
     pub fn root_path(&self) -> &str {
         &self.root_path
     }
+
+    /// Copies the entries whose absolute path starts with `prefix` into `zip`, replacing the
+    /// prefix by `new_prefix`. Paths listed in `exclude` are skipped.
+    pub fn copy_files<W: Write + std::io::Seek>(
+        &self,
+        zip: &mut ZipWriter<W>,
+        prefix: &str,
+        new_prefix: &str,
+        exclude: &[String],
+    ) -> Result {
+        let mut archive = self.archive.write().unwrap();
+        let changes = self.changes.read().unwrap();
+        let rename = |name: &str| format!("{}{}", new_prefix, &name[prefix.len()..]);
+
+        for index in 0..archive.len() {
+            let file = archive.by_index_raw(index)?;
+            let name = file.name().to_string();
+            if name.starts_with(prefix) && !exclude.contains(&name) && !changes.contains_key(&name)
+            {
+                zip.raw_copy_file_rename(file, rename(&name))?;
+            }
+        }
+
+        for (name, data) in changes.iter() {
+            if let Some(data) = data
+                && name.starts_with(prefix)
+                && !exclude.contains(name)
+            {
+                zip.start_file(rename(name), SimpleFileOptions::default())?;
+                zip.write_all(data)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Copies every entry of the archive into a new archive written to `writer`, applying the
+    /// files added, replaced or removed since it was opened. Entries listed in `replacements`
+    /// by absolute path are written with the given content instead. All other entries are
+    /// copied byte for byte without being decompressed.
+    pub fn write<W>(&self, writer: W, replacements: &BTreeMap<String, Vec<u8>>) -> Result<W>
+    where
+        W: std::io::Write + std::io::Seek,
+    {
+        let mut archive = self.archive.write().unwrap();
+        let changes = self.changes.read().unwrap();
+        let mut zip = ZipWriter::new(writer);
+
+        for index in 0..archive.len() {
+            let file = archive.by_index_raw(index)?;
+            let change = match replacements.get(file.name()) {
+                Some(data) => Some(Some(data)),
+                None => changes.get(file.name()).map(Option::as_ref),
+            };
+            match change {
+                Some(Some(data)) => {
+                    let name = file.name().to_string();
+                    let options = SimpleFileOptions::default()
+                        .compression_method(file.compression())
+                        .last_modified_time(file.last_modified().unwrap_or_default());
+                    drop(file);
+                    zip.start_file(name, options)?;
+                    zip.write_all(data)?;
+                }
+                Some(None) => {}
+                None => zip.raw_copy_file(file)?,
+            }
+        }
+
+        for (name, data) in changes.iter() {
+            if let Some(data) = data
+                && archive.index_for_name(name).is_none()
+            {
+                zip.start_file(name.as_str(), SimpleFileOptions::default())?;
+                zip.write_all(data)?;
+            }
+        }
+
+        Ok(zip.finish()?)
+    }
 }
 
 impl<R: std::io::Read + std::io::Seek> Clone for Container<R> {
@@ -103,6 +230,7 @@ impl<R: std::io::Read + std::io::Seek> Clone for Container<R> {
             container_type: self.container_type.clone(),
             archive: self.archive.clone(),
             root_path: self.root_path.clone(),
+            changes: self.changes.clone(),
         }
     }
 }
@@ -1,3 +1,5 @@
+use crate::id::{NoteUuid, PageUuid};
+
 pub type Result<T = ()> = std::result::Result<T, Error>;
 
 #[derive(Debug, thiserror::Error)]
@@ -13,6 +15,14 @@ pub enum Error {
     },
     #[error("Zip error: {0}")]
     Zip(#[from] zip::result::ZipError),
+    #[error("PNG decode error: {0}")]
+    PngDecode(#[from] png::DecodingError),
+    #[error("PNG encode error: {0}")]
+    PngEncode(#[from] png::EncodingError),
+    #[error("GIF encode error: {0}")]
+    GifEncode(#[from] gif::EncodingError),
+    #[error("Unsupported image: {0}")]
+    UnsupportedImage(String),
     #[error("Protobuf decode error: {0}")]
     ProtobufDecode(#[from] prost::DecodeError),
     #[error("UUID parse error: {0}")]
@@ -25,4 +35,20 @@ pub enum Error {
     InvalidTimestampFormat(String),
     #[error("Stroke not found")]
     StrokeNotFound,
+    #[error("Too many points for a points file: {0}")]
+    TooManyPoints(usize),
+    #[error("Replay has no frames")]
+    EmptyReplay,
+    #[error("Page not found: {0}")]
+    PageNotFound(PageUuid),
+    #[error("Note not found: {0}")]
+    NoteNotFound(NoteUuid),
+    #[error("Note found in more than one file: {0}")]
+    DuplicateNote(NoteUuid),
+    #[error("No multi-note archive to take the root directory of merged notes from")]
+    UnknownMultiNoteRoot,
+    #[error("Page order does not contain the active pages of the note")]
+    InvalidPageOrder,
+    #[error("Invalid InkML: {0}")]
+    InvalidInkml(String),
 }
\ No newline at end of file
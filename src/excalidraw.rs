@@ -0,0 +1,288 @@
+use std::io::Write;
+
+use crate::{
+    brush::Brush,
+    color::Color,
+    error::{Error, Result},
+    points::{Point, Stroke},
+    render::Rect,
+    shape::Shape,
+};
+
+/// Excalidraw measures scenes in CSS pixels, 96 per inch.
+const PIXELS_PER_INCH: f32 = 96.0;
+
+// Uncertain: Excalidraw draws free-hand lines this many times wider than their stroke width
+const FREEDRAW_WIDTH_FACTOR: f32 = 4.25;
+
+/// Writes the strokes as an Excalidraw scene with one free-hand element per stroke.
+pub fn write_excalidraw(
+    writer: impl Write,
+    strokes: &[(Shape, Stroke)],
+    units_per_inch: f32,
+) -> Result {
+    let scale = PIXELS_PER_INCH / units_per_inch;
+
+    let elements = strokes
+        .iter()
+        .filter(|(_, stroke)| {
+            if stroke.points.is_empty() {
+                log::warn!("No points to export for stroke");
+            }
+            !stroke.points.is_empty()
+        })
+        .map(|(shape, stroke)| {
+            let brush = Brush::from_shape(shape);
+            let color = brush.ink_color();
+            // The points may lie outside the stored box, so grow it to hold all of them and
+            // keep the relative points positive
+            let point_rect = |point: &Point| Rect {
+                left: point.x,
+                top: point.y,
+                right: point.x,
+                bottom: point.y,
+            };
+            let points_rect = stroke
+                .points
+                .iter()
+                .fold(point_rect(&stroke.points[0]), |rect, point| {
+                    rect.union(&point_rect(point))
+                });
+            let bbox = if shape.bbox.is_empty() {
+                points_rect
+            } else {
+                Rect::from(&shape.bbox).union(&points_rect)
+            };
+            // Derive the random values Excalidraw expects from the stroke ID, so exports are
+            // reproducible
+            let id = shape.stroke_id.to_simple_string();
+            let seed = u32::from_str_radix(&id[..8], 16).unwrap_or_default() & 0x7FFF_FFFF;
+
+            json::Element {
+                id,
+                element_type: "freedraw",
+                x: bbox.left * scale,
+                y: bbox.top * scale,
+                width: bbox.width() * scale,
+                height: bbox.height() * scale,
+                angle: 0.0,
+                stroke_color: color.to_hex_rgb(),
+                background_color: "transparent",
+                fill_style: "solid",
+                stroke_width: brush.width * scale / FREEDRAW_WIDTH_FACTOR,
+                stroke_style: "solid",
+                roughness: 0,
+                opacity: (color.alpha() as f32 / 255.0 * 100.0).round() as u8,
+                group_ids: Vec::new(),
+                frame_id: None,
+                roundness: None,
+                seed,
+                version: 1,
+                version_nonce: seed ^ 0x5A5A_5A5A,
+                is_deleted: false,
+                bound_elements: None,
+                updated: shape.modified.timestamp_millis(),
+                link: None,
+                locked: false,
+                points: stroke
+                    .points
+                    .iter()
+                    .map(|point| [(point.x - bbox.left) * scale, (point.y - bbox.top) * scale])
+                    .collect(),
+                pressures: stroke
+                    .points
+                    .iter()
+                    .map(|point| (point.pressure as f32 / brush.max_pressure).clamp(0.0, 1.0))
+                    .collect(),
+                simulate_pressure: false,
+                last_committed_point: None,
+            }
+        })
+        .collect();
+
+    let scene = json::Scene {
+        scene_type: "excalidraw",
+        version: 2,
+        source: concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION")),
+        elements,
+        app_state: json::AppState {
+            view_background_color: Color::WHITE.to_hex_rgb(),
+            grid_size: None,
+        },
+        files: json::Files {},
+    };
+
+    serde_json::to_writer_pretty(writer, &scene).map_err(|e| Error::Json {
+        error: e,
+        json_string: String::new(),
+    })
+}
+
+mod json {
+    use serde::Serialize;
+
+    #[derive(Debug, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct Scene {
+        #[serde(rename = "type")]
+        pub scene_type: &'static str,
+        pub version: u32,
+        pub source: &'static str,
+        pub elements: Vec<Element>,
+        pub app_state: AppState,
+        pub files: Files,
+    }
+
+    #[derive(Debug, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct AppState {
+        pub view_background_color: String,
+        pub grid_size: Option<u32>,
+    }
+
+    /// Binary files referenced by image elements, of which none are written.
+    #[derive(Debug, Serialize)]
+    pub struct Files {}
+
+    #[derive(Debug, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct Element {
+        pub id: String,
+        #[serde(rename = "type")]
+        pub element_type: &'static str,
+        pub x: f32,
+        pub y: f32,
+        pub width: f32,
+        pub height: f32,
+        pub angle: f32,
+        pub stroke_color: String,
+        pub background_color: &'static str,
+        pub fill_style: &'static str,
+        pub stroke_width: f32,
+        pub stroke_style: &'static str,
+        pub roughness: u32,
+        pub opacity: u8,
+        pub group_ids: Vec<String>,
+        pub frame_id: Option<String>,
+        pub roundness: Option<u32>,
+        pub seed: u32,
+        pub version: u32,
+        pub version_nonce: u32,
+        pub is_deleted: bool,
+        pub bound_elements: Option<Vec<String>>,
+        pub updated: i64,
+        pub link: Option<String>,
+        pub locked: bool,
+        /// Points relative to the element origin.
+        pub points: Vec<[f32; 2]>,
+        /// Pressure of each point, between 0 and 1.
+        pub pressures: Vec<f32>,
+        pub simulate_pressure: bool,
+        pub last_committed_point: Option<[f32; 2]>,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use serde_json::Value;
+
+    use super::*;
+    use crate::{NoteFile, testing};
+
+    /// Strokes of the first page of the test note, with a stored box smaller than the points
+    /// of the first stroke, and an empty stroke.
+    fn strokes() -> Vec<(Shape, Stroke)> {
+        let file = NoteFile::read(Cursor::new(testing::archive())).unwrap();
+        let note_id = *file.list_notes().keys().next().unwrap();
+        let mut note = file.get_note(&note_id).unwrap();
+        let page_id = note.active_pages()[0];
+        let mut strokes = note.get_page(&page_id).unwrap().strokes().unwrap();
+
+        let (shape, _) = &mut strokes[0];
+        shape.bbox.left += 30.0;
+        shape.bbox.top += 4.0;
+        shape.bbox.right -= 30.0;
+        let empty = (strokes[1].0.clone(), Stroke { points: Vec::new() });
+        strokes.push(empty);
+        strokes
+    }
+
+    #[test]
+    fn points_are_relative_to_their_extents() {
+        let strokes = strokes();
+        let units_per_inch = 200.0;
+        let mut buffer = Vec::new();
+        write_excalidraw(&mut buffer, &strokes, units_per_inch).unwrap();
+        let scene = serde_json::from_slice::<Value>(&buffer).unwrap();
+
+        let elements = scene["elements"].as_array().unwrap();
+        assert_eq!(elements.len(), strokes.len() - 1);
+
+        let scale = PIXELS_PER_INCH / units_per_inch;
+        for (element, (_, stroke)) in elements.iter().zip(&strokes) {
+            let number = |value: &Value| value.as_f64().unwrap() as f32;
+            let (width, height) = (number(&element["width"]), number(&element["height"]));
+            let points = element["points"].as_array().unwrap();
+            assert_eq!(points.len(), stroke.points.len());
+            for point in points {
+                let (x, y) = (number(&point[0]), number(&point[1]));
+                assert!((0.0..=width).contains(&x), "{} outside 0..{}", x, width);
+                assert!((0.0..=height).contains(&y), "{} outside 0..{}", y, height);
+            }
+
+            let pressures = element["pressures"].as_array().unwrap();
+            assert_eq!(pressures.len(), stroke.points.len());
+            assert!(
+                pressures
+                    .iter()
+                    .all(|pressure| (0.0..=1.0).contains(&number(pressure)))
+            );
+        }
+
+        // The box of the first stroke grows to its points
+        let left = strokes[0]
+            .1
+            .points
+            .iter()
+            .map(|point| point.x)
+            .fold(f32::MAX, f32::min);
+        assert_eq!(elements[0]["x"].as_f64().unwrap() as f32, left * scale);
+    }
+
+    #[test]
+    fn exports_device_samples() {
+        for (label, mut note) in testing::device_sample_notes() {
+            for page_id in note.active_pages().to_vec() {
+                let mut page = note.get_page(&page_id).unwrap();
+                let strokes = page
+                    .visible_strokes()
+                    .unwrap()
+                    .into_iter()
+                    .filter(|(_, stroke)| !stroke.points.is_empty())
+                    .collect::<Vec<_>>();
+                let mut buffer = Vec::new();
+                page.write_excalidraw(&mut buffer).unwrap();
+                let scene = serde_json::from_slice::<Value>(&buffer).unwrap();
+
+                let elements = scene["elements"].as_array().unwrap();
+                assert_eq!(elements.len(), strokes.len(), "{}", label);
+                for (element, (_, stroke)) in elements.iter().zip(&strokes) {
+                    let number = |value: &Value| value.as_f64().unwrap();
+                    let (width, height) = (number(&element["width"]), number(&element["height"]));
+                    let points = element["points"].as_array().unwrap();
+                    assert_eq!(points.len(), stroke.points.len(), "{}", label);
+                    assert!(
+                        points.iter().all(|point| {
+                            (0.0..=width).contains(&number(&point[0]))
+                                && (0.0..=height).contains(&number(&point[1]))
+                        }),
+                        "{}",
+                        label
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,794 @@
+use std::f32::consts::PI;
+
+/// Number of segments used to approximate a half circle.
+const CAP_SEGMENTS: usize = 8;
+
+/// Upper bound of the number of line segments a single curve segment is flattened into.
+const MAX_CURVE_SEGMENTS: usize = 64;
+
+/// Distance of the control points of a cubic Bézier approximating a quarter circle, relative
+/// to its radius.
+const QUARTER_CIRCLE_KAPPA: f32 = 0.552_284_8;
+
+/// Shape of the ends of an outline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Cap {
+    #[default]
+    Round,
+    Square,
+}
+
+/// A sampled position on a stroke centre line together with the ink width at that position.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WidthPoint {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+}
+
+/// Builds a closed, variable-width outline polygon around a centre line.
+///
+/// The outline follows the left side of the line, wraps around the end with the given cap and
+/// returns along the right side. It may self-intersect at sharp turns, so it is meant to be
+/// filled with the non-zero winding rule.
+pub fn outline(points: &[WidthPoint], cap: Cap) -> Vec<(f32, f32)> {
+    let points = dedup(points);
+
+    match points.as_slice() {
+        [] => Vec::new(),
+        [point] => dot(point, cap),
+        points => {
+            let normals = normals(points);
+            let mut polygon = Vec::with_capacity(points.len() * 2 + CAP_SEGMENTS * 2);
+
+            for (point, normal) in points.iter().zip(&normals) {
+                let radius = point.width / 2.0;
+                polygon.push((point.x + normal.0 * radius, point.y + normal.1 * radius));
+            }
+            let last = points.len() - 1;
+            push_cap(&mut polygon, &points[last], normals[last], cap);
+
+            for (point, normal) in points.iter().zip(&normals).rev() {
+                let radius = point.width / 2.0;
+                polygon.push((point.x - normal.0 * radius, point.y - normal.1 * radius));
+            }
+            push_cap(
+                &mut polygon,
+                &points[0],
+                (-normals[0].0, -normals[0].1),
+                cap,
+            );
+
+            polygon
+        }
+    }
+}
+
+/// Segment of an [`OutlinePath`], continuing from the end of the previous segment.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PathSegment {
+    Line((f32, f32)),
+    Curve {
+        control1: (f32, f32),
+        control2: (f32, f32),
+        end: (f32, f32),
+    },
+}
+
+/// A closed path of line and cubic Bézier segments.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutlinePath {
+    pub start: (f32, f32),
+    pub segments: Vec<PathSegment>,
+}
+
+/// Builds the outline of [`outline`] around the smooth curve [`fit_curve`] fits through the
+/// centre line, keeping its cubic segments instead of flattening them into a polygon. Returns
+/// `None` for an empty centre line.
+pub fn curve_outline(points: &[WidthPoint], cap: Cap) -> Option<OutlinePath> {
+    let curves = fit_curve(points, 0.0);
+    if curves.is_empty() {
+        return dedup(points).first().map(|point| curve_dot(point, cap));
+    }
+
+    // Offsets the control points of each segment along the normals at its ends
+    let normals = curves
+        .iter()
+        .map(|(curve, _, _)| {
+            let start = normalize((
+                curve.control1.0 - curve.start.0,
+                curve.control1.1 - curve.start.1,
+            ));
+            let end = normalize((
+                curve.end.0 - curve.control2.0,
+                curve.end.1 - curve.control2.1,
+            ));
+            ((-start.1, start.0), (-end.1, end.0))
+        })
+        .collect::<Vec<_>>();
+    let offset = |point: (f32, f32), normal: (f32, f32), width: f32, side: f32| {
+        let radius = width / 2.0 * side;
+        (point.0 + normal.0 * radius, point.1 + normal.1 * radius)
+    };
+
+    let (first, first_width, _) = &curves[0];
+    let mut path = OutlinePath {
+        start: offset(first.start, normals[0].0, *first_width, 1.0),
+        segments: Vec::with_capacity(curves.len() * 2 + 4),
+    };
+    for ((curve, start_width, end_width), (start_normal, end_normal)) in curves.iter().zip(&normals)
+    {
+        path.segments.push(PathSegment::Curve {
+            control1: offset(curve.control1, *start_normal, *start_width, 1.0),
+            control2: offset(curve.control2, *end_normal, *end_width, 1.0),
+            end: offset(curve.end, *end_normal, *end_width, 1.0),
+        });
+    }
+    let (last, _, last_width) = &curves[curves.len() - 1];
+    push_curve_cap(
+        &mut path.segments,
+        last.end,
+        normals[normals.len() - 1].1,
+        *last_width,
+        cap,
+    );
+
+    for ((curve, start_width, end_width), (start_normal, end_normal)) in
+        curves.iter().zip(&normals).rev()
+    {
+        path.segments.push(PathSegment::Curve {
+            control1: offset(curve.control2, *end_normal, *end_width, -1.0),
+            control2: offset(curve.control1, *start_normal, *start_width, -1.0),
+            end: offset(curve.start, *start_normal, *start_width, -1.0),
+        });
+    }
+    let start_normal = normals[0].0;
+    push_curve_cap(
+        &mut path.segments,
+        first.start,
+        (-start_normal.0, -start_normal.1),
+        *first_width,
+        cap,
+    );
+
+    Some(path)
+}
+
+/// Splits a centre line into the "on" intervals of a dash pattern.
+///
+/// `pattern` alternates between "on" and "off" lengths, starting with "on". `phase` is the
+/// distance into the pattern at which the line starts.
+pub fn dash(points: &[WidthPoint], pattern: &[f32], phase: f32) -> Vec<Vec<WidthPoint>> {
+    let total = pattern.iter().sum::<f32>();
+    if points.is_empty() || pattern.iter().any(|length| *length < 0.0) || total <= 0.0 {
+        return vec![points.to_vec()];
+    }
+
+    let mut index = 0;
+    let mut offset = phase.rem_euclid(total);
+    for _ in 0..pattern.len() {
+        if offset < pattern[index] {
+            break;
+        }
+        offset -= pattern[index];
+        index = (index + 1) % pattern.len();
+    }
+    let mut remaining = (pattern[index] - offset).max(0.0);
+
+    let mut dashes = Vec::new();
+    let mut current = if index % 2 == 0 {
+        vec![points[0]]
+    } else {
+        Vec::new()
+    };
+
+    for pair in points.windows(2) {
+        let (start, end) = (pair[0], pair[1]);
+        let length = (end.x - start.x).hypot(end.y - start.y);
+        let mut travelled = 0.0;
+
+        while length - travelled > remaining {
+            travelled += remaining;
+            // Ends the current dash when "on", starts the next one when "off".
+            current.push(interpolate(&start, &end, travelled / length));
+            if index % 2 == 0 {
+                dashes.push(std::mem::take(&mut current));
+            }
+            index = (index + 1) % pattern.len();
+            remaining = pattern[index];
+        }
+
+        remaining -= length - travelled;
+        if index % 2 == 0 {
+            current.push(end);
+        }
+    }
+
+    if index % 2 == 0 && !current.is_empty() {
+        dashes.push(current);
+    }
+
+    dashes
+}
+
+/// A cubic Bézier curve segment.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CubicBezier {
+    pub start: (f32, f32),
+    pub control1: (f32, f32),
+    pub control2: (f32, f32),
+    pub end: (f32, f32),
+}
+
+impl CubicBezier {
+    pub fn point_at(&self, t: f32) -> (f32, f32) {
+        let u = 1.0 - t;
+        let (a, b, c, d) = (u * u * u, 3.0 * u * u * t, 3.0 * u * t * t, t * t * t);
+        (
+            a * self.start.0 + b * self.control1.0 + c * self.control2.0 + d * self.end.0,
+            a * self.start.1 + b * self.control1.1 + c * self.control2.1 + d * self.end.1,
+        )
+    }
+
+    /// Returns the number of line segments needed to stay within `tolerance` of the curve,
+    /// following Wang's formula.
+    fn segment_count(&self, tolerance: f32) -> usize {
+        let second_difference = |a: (f32, f32), b: (f32, f32), c: (f32, f32)| {
+            (a.0 - 2.0 * b.0 + c.0).hypot(a.1 - 2.0 * b.1 + c.1)
+        };
+        let deviation = second_difference(self.start, self.control1, self.control2)
+            .max(second_difference(self.control1, self.control2, self.end));
+        let count = (0.75 * deviation / tolerance.max(f32::EPSILON))
+            .sqrt()
+            .ceil();
+        (count as usize).clamp(1, MAX_CURVE_SEGMENTS)
+    }
+}
+
+/// Fits a smooth curve through the centre line, after dropping the samples that lie within
+/// `tolerance` of it. Returns one segment per pair of remaining samples, together with the ink
+/// widths at their ends.
+pub fn fit_curve(points: &[WidthPoint], tolerance: f32) -> Vec<(CubicBezier, f32, f32)> {
+    let points = simplify(&dedup(points), tolerance);
+    let last = points.len().saturating_sub(1);
+
+    (0..last)
+        .map(|index| {
+            let before = points[index.saturating_sub(1)];
+            let start = points[index];
+            let end = points[index + 1];
+            let after = points[(index + 2).min(last)];
+
+            // Catmull-Rom tangents, scaled to the segment length so uneven sampling does not
+            // make the curve overshoot.
+            let length = (end.x - start.x).hypot(end.y - start.y) / 3.0;
+            let start_tangent = normalize((end.x - before.x, end.y - before.y));
+            let end_tangent = normalize((after.x - start.x, after.y - start.y));
+            let curve = CubicBezier {
+                start: (start.x, start.y),
+                control1: (
+                    start.x + start_tangent.0 * length,
+                    start.y + start_tangent.1 * length,
+                ),
+                control2: (
+                    end.x - end_tangent.0 * length,
+                    end.y - end_tangent.1 * length,
+                ),
+                end: (end.x, end.y),
+            };
+            (curve, start.width, end.width)
+        })
+        .collect()
+}
+
+/// Replaces the centre line by a smooth curve through its samples, flattened into points no
+/// further than `tolerance` from the curve.
+pub fn smooth(points: &[WidthPoint], tolerance: f32) -> Vec<WidthPoint> {
+    let curves = fit_curve(points, 0.0);
+    let Some((first, start_width, _)) = curves.first() else {
+        return dedup(points);
+    };
+
+    let mut result = vec![WidthPoint {
+        x: first.start.0,
+        y: first.start.1,
+        width: *start_width,
+    }];
+    for (curve, start_width, end_width) in &curves {
+        let count = curve.segment_count(tolerance);
+        result.extend((1..=count).map(|step| {
+            let t = step as f32 / count as f32;
+            let (x, y) = curve.point_at(t);
+            WidthPoint {
+                x,
+                y,
+                width: start_width + (end_width - start_width) * t,
+            }
+        }));
+    }
+    result
+}
+
+/// Drops samples that deviate less than `tolerance` from the line through their neighbours,
+/// in position or half width, using the Ramer-Douglas-Peucker algorithm.
+fn simplify(points: &[WidthPoint], tolerance: f32) -> Vec<WidthPoint> {
+    points
+        .iter()
+        .zip(simplify_mask(points, tolerance))
+        .filter_map(|(point, keep)| keep.then_some(*point))
+        .collect()
+}
+
+/// Returns for every sample whether [`simplify`] keeps it.
+pub fn simplify_mask(points: &[WidthPoint], tolerance: f32) -> Vec<bool> {
+    if tolerance <= 0.0 || points.len() < 3 {
+        return vec![true; points.len()];
+    }
+
+    let mut keep = vec![false; points.len()];
+    keep[0] = true;
+    keep[points.len() - 1] = true;
+
+    let mut ranges = vec![(0, points.len() - 1)];
+    while let Some((first, last)) = ranges.pop() {
+        let (start, end) = (&points[first], &points[last]);
+        let length = (end.x - start.x).hypot(end.y - start.y);
+
+        let farthest = (first + 1..last)
+            .map(|index| {
+                let point = &points[index];
+                let t = if length < f32::EPSILON {
+                    0.0
+                } else {
+                    (((point.x - start.x) * (end.x - start.x)
+                        + (point.y - start.y) * (end.y - start.y))
+                        / (length * length))
+                        .clamp(0.0, 1.0)
+                };
+                let nearest = interpolate(start, end, t);
+                let distance = (point.x - nearest.x)
+                    .hypot(point.y - nearest.y)
+                    .max((point.width - nearest.width).abs() / 2.0);
+                (index, distance)
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+
+        if let Some((index, distance)) = farthest
+            && distance > tolerance
+        {
+            keep[index] = true;
+            ranges.push((first, index));
+            ranges.push((index, last));
+        }
+    }
+
+    keep
+}
+
+fn interpolate(start: &WidthPoint, end: &WidthPoint, t: f32) -> WidthPoint {
+    WidthPoint {
+        x: start.x + (end.x - start.x) * t,
+        y: start.y + (end.y - start.y) * t,
+        width: start.width + (end.width - start.width) * t,
+    }
+}
+
+fn dedup(points: &[WidthPoint]) -> Vec<WidthPoint> {
+    let mut result: Vec<WidthPoint> = Vec::with_capacity(points.len());
+    for point in points {
+        match result.last() {
+            Some(last) if (last.x - point.x).hypot(last.y - point.y) < f32::EPSILON => {}
+            _ => result.push(*point),
+        }
+    }
+    result
+}
+
+fn normals(points: &[WidthPoint]) -> Vec<(f32, f32)> {
+    let directions = points
+        .windows(2)
+        .map(|pair| normalize((pair[1].x - pair[0].x, pair[1].y - pair[0].y)))
+        .collect::<Vec<_>>();
+
+    (0..points.len())
+        .map(|index| {
+            let tangent = match (index.checked_sub(1), directions.get(index)) {
+                (Some(prev), Some(next)) => {
+                    let sum = (directions[prev].0 + next.0, directions[prev].1 + next.1);
+                    if sum.0.hypot(sum.1) < f32::EPSILON {
+                        *next
+                    } else {
+                        normalize(sum)
+                    }
+                }
+                (Some(prev), None) => directions[prev],
+                (None, Some(next)) => *next,
+                (None, None) => (1.0, 0.0),
+            };
+            (-tangent.1, tangent.0)
+        })
+        .collect()
+}
+
+fn normalize(vector: (f32, f32)) -> (f32, f32) {
+    let length = vector.0.hypot(vector.1);
+    if length < f32::EPSILON {
+        (1.0, 0.0)
+    } else {
+        (vector.0 / length, vector.1 / length)
+    }
+}
+
+/// Appends a cap around `point`, starting at the side `normal` points to and turning through
+/// the direction of travel.
+fn push_cap(polygon: &mut Vec<(f32, f32)>, point: &WidthPoint, normal: (f32, f32), cap: Cap) {
+    let radius = point.width / 2.0;
+    match cap {
+        Cap::Round => {
+            let start = normal.1.atan2(normal.0);
+            for step in 1..CAP_SEGMENTS {
+                let angle = start - PI * step as f32 / CAP_SEGMENTS as f32;
+                polygon.push((
+                    point.x + angle.cos() * radius,
+                    point.y + angle.sin() * radius,
+                ));
+            }
+        }
+        Cap::Square => {
+            let tangent = (normal.1, -normal.0);
+            for side in [1.0, -1.0] {
+                polygon.push((
+                    point.x + (normal.0 * side + tangent.0) * radius,
+                    point.y + (normal.1 * side + tangent.1) * radius,
+                ));
+            }
+        }
+    }
+}
+
+/// Appends the cap of [`push_cap`] as curve segments, ending on the side opposite to `normal`.
+fn push_curve_cap(
+    segments: &mut Vec<PathSegment>,
+    point: (f32, f32),
+    normal: (f32, f32),
+    width: f32,
+    cap: Cap,
+) {
+    let radius = width / 2.0;
+    let tangent = (normal.1, -normal.0);
+    let at = |a: (f32, f32), b: (f32, f32)| {
+        (
+            point.0 + (a.0 + b.0) * radius,
+            point.1 + (a.1 + b.1) * radius,
+        )
+    };
+    let scaled = |vector: (f32, f32), factor: f32| (vector.0 * factor, vector.1 * factor);
+    let opposite = scaled(normal, -1.0);
+
+    match cap {
+        Cap::Round => {
+            let k = QUARTER_CIRCLE_KAPPA;
+            segments.push(PathSegment::Curve {
+                control1: at(normal, scaled(tangent, k)),
+                control2: at(tangent, scaled(normal, k)),
+                end: at(tangent, (0.0, 0.0)),
+            });
+            segments.push(PathSegment::Curve {
+                control1: at(tangent, scaled(opposite, k)),
+                control2: at(opposite, scaled(tangent, k)),
+                end: at(opposite, (0.0, 0.0)),
+            });
+        }
+        Cap::Square => {
+            segments.push(PathSegment::Line(at(normal, tangent)));
+            segments.push(PathSegment::Line(at(opposite, tangent)));
+            segments.push(PathSegment::Line(at(opposite, (0.0, 0.0))));
+        }
+    }
+}
+
+/// Builds the outline of a single point as curve segments.
+fn curve_dot(point: &WidthPoint, cap: Cap) -> OutlinePath {
+    let radius = point.width / 2.0;
+    let mut path = OutlinePath {
+        start: (point.x, point.y - radius),
+        segments: Vec::new(),
+    };
+    let (x, y) = (point.x, point.y);
+    push_curve_cap(&mut path.segments, (x, y), (0.0, -1.0), point.width, cap);
+    push_curve_cap(&mut path.segments, (x, y), (0.0, 1.0), point.width, cap);
+    path
+}
+
+fn dot(point: &WidthPoint, cap: Cap) -> Vec<(f32, f32)> {
+    let radius = point.width / 2.0;
+    match cap {
+        Cap::Round => (0..CAP_SEGMENTS * 2)
+            .map(|step| {
+                let angle = PI * step as f32 / CAP_SEGMENTS as f32;
+                (
+                    point.x + angle.cos() * radius,
+                    point.y + angle.sin() * radius,
+                )
+            })
+            .collect(),
+        Cap::Square => vec![
+            (point.x - radius, point.y - radius),
+            (point.x + radius, point.y - radius),
+            (point.x + radius, point.y + radius),
+            (point.x - radius, point.y + radius),
+        ],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(x: f32, y: f32, width: f32) -> WidthPoint {
+        WidthPoint { x, y, width }
+    }
+
+    fn assert_near(actual: (f32, f32), expected: (f32, f32)) {
+        assert!(
+            (actual.0 - expected.0).abs() < 1e-3 && (actual.1 - expected.1).abs() < 1e-3,
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+
+    /// Returns the end of the last segment of a path.
+    fn path_end(path: &OutlinePath) -> (f32, f32) {
+        match path.segments.last().unwrap() {
+            PathSegment::Line(end) | PathSegment::Curve { end, .. } => *end,
+        }
+    }
+
+    #[test]
+    fn empty_lines_have_no_outline() {
+        assert!(outline(&[], Cap::Round).is_empty());
+        assert!(curve_outline(&[], Cap::Round).is_none());
+        assert!(fit_curve(&[], 1.0).is_empty());
+        assert!(smooth(&[], 1.0).is_empty());
+        assert!(simplify_mask(&[], 1.0).is_empty());
+        assert_eq!(dash(&[], &[1.0, 1.0], 0.0), vec![Vec::new()]);
+    }
+
+    #[test]
+    fn single_points_are_dots() {
+        let center = point(10.0, 20.0, 4.0);
+        let round = outline(&[center], Cap::Round);
+        assert_eq!(round.len(), CAP_SEGMENTS * 2);
+        for (x, y) in round {
+            assert!(((x - 10.0).hypot(y - 20.0) - 2.0).abs() < 1e-4);
+        }
+        assert_eq!(
+            outline(&[center], Cap::Square),
+            [(8.0, 18.0), (12.0, 18.0), (12.0, 22.0), (8.0, 22.0)]
+        );
+
+        for cap in [Cap::Round, Cap::Square] {
+            let path = curve_outline(&[center], cap).unwrap();
+            assert_eq!(path.start, (10.0, 18.0));
+            assert_near(path_end(&path), path.start);
+        }
+    }
+
+    #[test]
+    fn duplicate_points_are_merged() {
+        let center = point(10.0, 20.0, 4.0);
+        assert_eq!(
+            outline(&[center, center, center], Cap::Round),
+            outline(&[center], Cap::Round)
+        );
+        assert_eq!(
+            curve_outline(&[center, center], Cap::Round),
+            curve_outline(&[center], Cap::Round)
+        );
+
+        let end = point(30.0, 20.0, 4.0);
+        assert_eq!(
+            fit_curve(&[center, center, end, end], 0.0),
+            fit_curve(&[center, end], 0.0)
+        );
+        assert_eq!(
+            outline(&[center, center, end, end], Cap::Square),
+            outline(&[center, end], Cap::Square)
+        );
+    }
+
+    #[test]
+    fn zero_width_outlines_collapse_onto_the_line() {
+        let points = [
+            point(0.0, 5.0, 0.0),
+            point(10.0, 5.0, 0.0),
+            point(20.0, 5.0, 0.0),
+        ];
+        for cap in [Cap::Round, Cap::Square] {
+            for (x, y) in outline(&points, cap) {
+                assert!((0.0..=20.0).contains(&x));
+                assert!((y - 5.0).abs() < 1e-4);
+            }
+
+            let path = curve_outline(&points, cap).unwrap();
+            assert_near(path.start, (0.0, 5.0));
+            for segment in &path.segments {
+                let ends = match segment {
+                    PathSegment::Line(end) => vec![*end],
+                    PathSegment::Curve {
+                        control1,
+                        control2,
+                        end,
+                    } => vec![*control1, *control2, *end],
+                };
+                for (x, y) in ends {
+                    assert!(x.is_finite() && (y - 5.0).abs() < 1e-4);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn outlines_are_closed() {
+        let points = [
+            point(0.0, 0.0, 2.0),
+            point(10.0, 10.0, 4.0),
+            point(20.0, 0.0, 6.0),
+        ];
+        for cap in [Cap::Round, Cap::Square] {
+            let path = curve_outline(&points, cap).unwrap();
+            assert_near(path_end(&path), path.start);
+            // Offset from the first sample by its half width
+            assert_near(
+                path.start,
+                (
+                    -std::f32::consts::FRAC_1_SQRT_2,
+                    std::f32::consts::FRAC_1_SQRT_2,
+                ),
+            );
+        }
+    }
+
+    #[test]
+    fn curves_pass_through_the_samples() {
+        let points = [
+            point(0.0, 0.0, 1.0),
+            point(10.0, 10.0, 2.0),
+            point(20.0, 0.0, 3.0),
+        ];
+        let curves = fit_curve(&points, 0.0);
+        assert_eq!(curves.len(), 2);
+        for ((curve, start_width, end_width), pair) in curves.iter().zip(points.windows(2)) {
+            assert_eq!(curve.start, (pair[0].x, pair[0].y));
+            assert_eq!(curve.end, (pair[1].x, pair[1].y));
+            assert_eq!((*start_width, *end_width), (pair[0].width, pair[1].width));
+        }
+
+        // Catmull-Rom tangents: the peak is crossed horizontally, the ends point at their
+        // neighbours, and control points lie a third of the segment length away
+        let third = 200.0f32.sqrt() / 3.0;
+        let diagonal = third * std::f32::consts::FRAC_1_SQRT_2;
+        assert_near(curves[0].0.control1, (diagonal, diagonal));
+        assert_near(curves[0].0.control2, (10.0 - third, 10.0));
+        assert_near(curves[1].0.control1, (10.0 + third, 10.0));
+        assert_near(curves[1].0.control2, (20.0 - diagonal, diagonal));
+        assert_near(curves[0].0.point_at(0.0), (0.0, 0.0));
+        assert_near(curves[0].0.point_at(1.0), (10.0, 10.0));
+    }
+
+    #[test]
+    fn straight_lines_stay_straight() {
+        let points = [
+            point(0.0, 0.0, 1.0),
+            point(10.0, 0.0, 1.0),
+            point(20.0, 0.0, 1.0),
+        ];
+        assert_eq!(smooth(&points, 0.1), points);
+        for (curve, _, _) in fit_curve(&points, 0.0) {
+            assert_eq!(curve.control1.1, 0.0);
+            assert_eq!(curve.control2.1, 0.0);
+        }
+    }
+
+    #[test]
+    fn smoothing_is_bounded_by_the_tolerance() {
+        let points = [
+            point(0.0, 0.0, 1.0),
+            point(10.0, 10.0, 2.0),
+            point(20.0, 0.0, 3.0),
+        ];
+        let coarse = smooth(&points, 10.0);
+        let fine = smooth(&points, 0.01);
+        assert!(coarse.len() < fine.len());
+        // A tolerance of zero is clamped to the most segments per curve
+        assert_eq!(smooth(&points, 0.0).len(), 1 + 2 * MAX_CURVE_SEGMENTS);
+
+        for smoothed in [coarse, fine] {
+            assert_eq!(smoothed[0], points[0]);
+            assert_eq!(*smoothed.last().unwrap(), points[2]);
+            assert!(smoothed.iter().any(|sample| {
+                (sample.x - 10.0).abs() < 1e-4 && (sample.y - 10.0).abs() < 1e-4
+            }));
+        }
+    }
+
+    #[test]
+    fn simplify_drops_samples_within_the_tolerance() {
+        let bump = [
+            point(0.0, 0.0, 1.0),
+            point(10.0, 5.0, 1.0),
+            point(20.0, 0.0, 1.0),
+        ];
+        assert_eq!(simplify_mask(&bump, 4.9), [true, true, true]);
+        assert_eq!(simplify_mask(&bump, 5.1), [true, false, true]);
+        // Tolerances of zero and below keep every sample
+        assert_eq!(simplify_mask(&bump, 0.0), [true, true, true]);
+        assert_eq!(simplify_mask(&bump, -1.0), [true, true, true]);
+        assert_eq!(simplify_mask(&bump[..2], 100.0), [true, true]);
+
+        // Changes of the half width count as deviation
+        let swelling = [
+            point(0.0, 0.0, 1.0),
+            point(10.0, 0.0, 5.0),
+            point(20.0, 0.0, 1.0),
+        ];
+        assert_eq!(simplify_mask(&swelling, 1.0), [true, true, true]);
+        assert_eq!(simplify_mask(&swelling, 3.0), [true, false, true]);
+
+        // Lines returning to their start keep the farthest sample
+        let returning = [
+            point(0.0, 0.0, 1.0),
+            point(10.0, 0.0, 1.0),
+            point(0.0, 0.0, 1.0),
+        ];
+        assert_eq!(simplify_mask(&returning, 1.0), [true, true, true]);
+        assert_eq!(fit_curve(&bump, 5.1).len(), 1);
+    }
+
+    #[test]
+    fn dashes_follow_the_pattern() {
+        let line = [point(0.0, 0.0, 1.0), point(100.0, 0.0, 1.0)];
+        let starts_and_ends = |dashes: Vec<Vec<WidthPoint>>| {
+            dashes
+                .iter()
+                .map(|dash| (dash[0].x.round(), dash[dash.len() - 1].x.round()))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            starts_and_ends(dash(&line, &[10.0, 10.0], 0.0)),
+            [
+                (0.0, 10.0),
+                (20.0, 30.0),
+                (40.0, 50.0),
+                (60.0, 70.0),
+                (80.0, 90.0)
+            ]
+        );
+        assert_eq!(
+            starts_and_ends(dash(&line, &[10.0, 10.0], 5.0)),
+            [
+                (0.0, 5.0),
+                (15.0, 25.0),
+                (35.0, 45.0),
+                (55.0, 65.0),
+                (75.0, 85.0),
+                (95.0, 100.0)
+            ]
+        );
+        // Starting in a gap
+        assert_eq!(
+            starts_and_ends(dash(&line, &[10.0, 40.0], 20.0)),
+            [(30.0, 40.0), (80.0, 90.0)]
+        );
+
+        // Invalid patterns keep the line solid
+        for pattern in [&[][..], &[0.0, 0.0], &[10.0, -1.0]] {
+            assert_eq!(dash(&line, pattern, 0.0), vec![line.to_vec()]);
+        }
+        let single = [point(5.0, 5.0, 1.0)];
+        assert_eq!(dash(&single, &[10.0, 10.0], 0.0), vec![single.to_vec()]);
+        assert!(dash(&single, &[10.0, 10.0], 15.0).is_empty());
+    }
+}
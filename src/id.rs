@@ -50,6 +50,11 @@ macro_rules! implement_uuid {
                 id
             }
 
+            /// Generates a fresh random UUID.
+            pub fn new_random() -> Self {
+                Self::new(uuid::Uuid::new_v4())
+            }
+
             pub fn from_str(s: &str) -> crate::error::Result<Self> {
                 let id = Self(uuid::Uuid::parse_str(s).inspect_err(|e| {
                     log::error!("Failed to parse UUID from byte string: {}", s);
@@ -75,6 +80,10 @@ macro_rules! implement_uuid {
             pub fn to_hyphenated_string(&self) -> String {
                 self.0.hyphenated().to_string()
             }
+
+            pub fn uuid(&self) -> uuid::Uuid {
+                self.0
+            }
         }
 
         impl std::fmt::Display for $name {
@@ -93,6 +102,17 @@ macro_rules! implement_uuid {
             }
         }
 
+        // New IDs use the simple format of the archive paths, `update_json` keeps the spelling
+        // of IDs read from JSON
+        impl serde::Serialize for $name {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: serde::Serializer,
+            {
+                serializer.serialize_str(&self.to_simple_string())
+            }
+        }
+
         impl CheckUuid for $name {
             fn id(&self) -> &uuid::Uuid {
                 &self.0
@@ -115,6 +135,32 @@ implement_uuid!(PenUuid);
 implement_uuid!(ShapeGroupUuid);
 implement_uuid!(PointsUuid);
 
+/// Adds formatting of IDs the crate writes back into files and file names, in the style they
+/// were read in.
+macro_rules! implement_string_like {
+    ($name:ident) => {
+        impl $name {
+            /// Formats the UUID for writing it back in place of `original`. Returns `original`
+            /// if it already refers to this UUID, otherwise keeps its hyphenated or simple
+            /// style.
+            pub(crate) fn to_string_like(self, original: &str) -> String {
+                match uuid::Uuid::parse_str(original) {
+                    Ok(id) if id == self.0 => original.to_string(),
+                    _ if original.len() == 36 => self.to_hyphenated_string(),
+                    _ => self.to_simple_string(),
+                }
+            }
+        }
+    };
+}
+
+implement_string_like!(NoteUuid);
+implement_string_like!(VirtualDocUuid);
+implement_string_like!(StrokeUuid);
+implement_string_like!(PageUuid);
+implement_string_like!(ShapeGroupUuid);
+implement_string_like!(PointsUuid);
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum PenId {
     Uuid(PenUuid),
@@ -140,6 +186,18 @@ impl std::fmt::Display for PenId {
     }
 }
 
+impl serde::Serialize for PenId {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match self {
+            Self::Uuid(uuid) => serializer.serialize_str(&uuid.to_simple_string()),
+            Self::Id(id) => serializer.serialize_str(&id.to_string()),
+        }
+    }
+}
+
 impl<'de> serde::Deserialize<'de> for PenId {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -155,13 +213,17 @@ impl<'de> serde::Deserialize<'de> for PenId {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct LayerId(u32);
 
 impl LayerId {
-    pub fn new(id: u32) -> Self {
+    pub const fn new(id: u32) -> Self {
         Self(id)
     }
+
+    pub fn id(&self) -> u32 {
+        self.0
+    }
 }
 
 impl std::fmt::Display for LayerId {
@@ -170,6 +232,15 @@ impl std::fmt::Display for LayerId {
     }
 }
 
+impl serde::Serialize for LayerId {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_u32(self.0)
+    }
+}
+
 impl<'de> serde::Deserialize<'de> for LayerId {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
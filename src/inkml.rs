@@ -0,0 +1,555 @@
+use std::{collections::HashMap, io::Write};
+
+use regex::Regex;
+
+use crate::{
+    brush::PenType,
+    builder::{DEFAULT_PEN_WIDTH, MAX_PRESSURE, PageBuilder, StrokeBuilder},
+    color::Color,
+    error::{Error, Result},
+    points::{Point, Stroke},
+    shape::Shape,
+};
+
+/// Namespace of the `xml:` attributes, such as `xml:id`.
+const XML_NAMESPACE: &str = "http://www.w3.org/XML/1998/namespace";
+
+/// Writes the strokes as an InkML document with one trace per stroke. Coordinates are in page
+/// units, time in milliseconds since the first stroke was created.
+pub fn write_inkml(
+    mut writer: impl Write,
+    strokes: &[(Shape, Stroke)],
+    units_per_inch: f32,
+) -> Result {
+    let start = strokes
+        .iter()
+        .map(|(shape, _)| shape.created)
+        .min()
+        .unwrap_or_default();
+
+    writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(writer, r#"<ink xmlns="http://www.w3.org/2003/InkML">"#)?;
+    writeln!(writer, "<definitions>")?;
+    writeln!(writer, r#"<context xml:id="context">"#)?;
+    writeln!(writer, r#"<inkSource xml:id="source">"#)?;
+    writeln!(writer, r##"<traceFormat href="#format"/>"##)?;
+    writeln!(writer, "<channelProperties>")?;
+    for channel in ["X", "Y"] {
+        writeln!(
+            writer,
+            r#"<channelProperty channel="{}" name="resolution" value="{}" units="1/in"/>"#,
+            channel, units_per_inch
+        )?;
+    }
+    writeln!(writer, "</channelProperties>")?;
+    writeln!(writer, "</inkSource>")?;
+    writeln!(writer, r#"<traceFormat xml:id="format">"#)?;
+    writeln!(writer, r#"<channel name="X" type="decimal"/>"#)?;
+    writeln!(writer, r#"<channel name="Y" type="decimal"/>"#)?;
+    writeln!(
+        writer,
+        r#"<channel name="F" type="integer" min="0" max="{}"/>"#,
+        MAX_PRESSURE
+    )?;
+    // Uncertain: units of the tilt values are unknown
+    writeln!(writer, r#"<channel name="OTx" type="integer"/>"#)?;
+    writeln!(writer, r#"<channel name="OTy" type="integer"/>"#)?;
+    writeln!(
+        writer,
+        r##"<channel name="T" type="integer" units="ms" respectTo="#start"/>"##
+    )?;
+    writeln!(writer, "</traceFormat>")?;
+    writeln!(
+        writer,
+        r#"<timestamp xml:id="start" timeString="{}"/>"#,
+        start.to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+    )?;
+    writeln!(writer, "</context>")?;
+
+    let mut brushes = Vec::new();
+    let brush_ids = strokes
+        .iter()
+        .map(|(shape, _)| {
+            let brush = (
+                shape.pen_type.unwrap_or_default(),
+                shape.color,
+                shape.stroke_width.to_bits(),
+            );
+            brushes
+                .iter()
+                .position(|existing| *existing == brush)
+                .unwrap_or_else(|| {
+                    brushes.push(brush);
+                    brushes.len() - 1
+                })
+        })
+        .collect::<Vec<_>>();
+    for (index, (pen_type, color, width)) in brushes.iter().enumerate() {
+        writeln!(writer, r#"<brush xml:id="brush{}">"#, index)?;
+        writeln!(
+            writer,
+            r#"<brushProperty name="width" value="{}"/>"#,
+            f32::from_bits(*width)
+        )?;
+        writeln!(
+            writer,
+            r#"<brushProperty name="color" value="{}"/>"#,
+            color.to_hex_rgb()
+        )?;
+        if color.alpha() != u8::MAX {
+            writeln!(
+                writer,
+                r#"<brushProperty name="transparency" value="{}"/>"#,
+                u8::MAX - color.alpha()
+            )?;
+        }
+        writeln!(
+            writer,
+            r#"<brushProperty name="penType" value="{}"/>"#,
+            pen_type.to_raw()
+        )?;
+        writeln!(writer, "</brush>")?;
+    }
+    writeln!(writer, "</definitions>")?;
+
+    for ((shape, stroke), brush_id) in strokes.iter().zip(brush_ids) {
+        if stroke.points.is_empty() {
+            log::warn!("No points to export for stroke");
+            continue;
+        }
+
+        let created = (shape.created - start).num_milliseconds();
+        let first = stroke.points[0].timestamp_rel;
+        let points = stroke
+            .points
+            .iter()
+            .map(|point| {
+                format!(
+                    "{} {} {} {} {} {}",
+                    point.x,
+                    point.y,
+                    point.pressure,
+                    point.tilt_x,
+                    point.tilt_y,
+                    created + point.timestamp_rel.saturating_sub(first) as i64
+                )
+            })
+            .collect::<Vec<_>>();
+        writeln!(
+            writer,
+            r##"<trace contextRef="#context" brushRef="#brush{}">{}</trace>"##,
+            brush_id,
+            points.join(", ")
+        )?;
+    }
+
+    writeln!(writer, "</ink>")?;
+
+    Ok(())
+}
+
+impl PageBuilder {
+    /// Reads the traces of an InkML document as the strokes of a new page. Coordinates are
+    /// taken as page units. Channels other than X, Y, F, OTx, OTy and T are ignored, as are
+    /// brush properties other than width, color, transparency and penType.
+    pub fn from_inkml(mut reader: impl std::io::Read) -> Result<Self> {
+        let mut document = String::new();
+        reader.read_to_string(&mut document)?;
+        let options = roxmltree::ParsingOptions {
+            allow_dtd: true,
+            ..Default::default()
+        };
+        let document = roxmltree::Document::parse_with_options(&document, options)
+            .map_err(|e| Error::InvalidInkml(format!("Invalid XML: {}", e)))?;
+
+        let channels = read_channels(&document);
+        let brushes = read_brushes(&document);
+
+        let mut traces = Vec::new();
+        for trace in document
+            .descendants()
+            .filter(|node| is_element(node, "trace"))
+        {
+            let brush = trace
+                .attribute("brushRef")
+                .and_then(|brush_ref| brushes.get(brush_ref.trim_start_matches('#')))
+                .cloned()
+                .unwrap_or_default();
+            let data = trace
+                .children()
+                .filter(|node| node.is_text())
+                .filter_map(|node| node.text())
+                .collect::<String>();
+            traces.push((brush, read_trace(&data, channels.len())?));
+        }
+
+        let channel = |name: &str| channels.iter().position(|channel| channel.name == name);
+        let (x, y) = channel("X")
+            .zip(channel("Y"))
+            .ok_or_else(|| Error::InvalidInkml("Missing X or Y channel".to_string()))?;
+        let (pressure, tilt_x, tilt_y, time) =
+            (channel("F"), channel("OTx"), channel("OTy"), channel("T"));
+        let pressure_scale = pressure
+            .and_then(|index| channels[index].max)
+            .filter(|max| *max > 0.0)
+            .map(|max| MAX_PRESSURE / max)
+            .unwrap_or(1.0);
+        let start = time
+            .and_then(|index| {
+                traces
+                    .iter()
+                    .flat_map(|(_, values)| values.iter().map(|point| point[index]))
+                    .reduce(f64::min)
+            })
+            .unwrap_or_default();
+
+        let mut page = Self::new();
+        for (brush, values) in traces {
+            let value = |point: &[f64], index: Option<usize>| index.map(|index| point[index]);
+            let points = values
+                .iter()
+                .map(|point| Point {
+                    timestamp_rel: value(point, time)
+                        .map(|time| (time - start).max(0.0) as u32)
+                        .unwrap_or_default(),
+                    x: point[x] as f32,
+                    y: point[y] as f32,
+                    tilt_x: value(point, tilt_x)
+                        .map(|tilt| tilt.clamp(i8::MIN as f64, i8::MAX as f64) as i8)
+                        .unwrap_or_default(),
+                    tilt_y: value(point, tilt_y)
+                        .map(|tilt| tilt.clamp(i8::MIN as f64, i8::MAX as f64) as i8)
+                        .unwrap_or_default(),
+                    pressure: value(point, pressure)
+                        .map(|pressure| (pressure as f32 * pressure_scale).clamp(0.0, MAX_PRESSURE))
+                        .unwrap_or(MAX_PRESSURE) as u16,
+                })
+                .collect::<Vec<_>>();
+            if points.is_empty() {
+                continue;
+            }
+            page = page.stroke(StrokeBuilder {
+                pen_type: brush.pen_type,
+                color: brush.color,
+                width: brush.width,
+                points,
+            });
+        }
+
+        Ok(page)
+    }
+}
+
+struct Channel {
+    name: String,
+    max: Option<f32>,
+}
+
+#[derive(Clone)]
+struct Brush {
+    pen_type: PenType,
+    color: Color,
+    width: f32,
+}
+
+impl Default for Brush {
+    fn default() -> Self {
+        Self {
+            pen_type: PenType::default(),
+            color: Color::BLACK,
+            width: DEFAULT_PEN_WIDTH,
+        }
+    }
+}
+
+/// Reads the channels of the first trace format that defines any, or the default X and Y
+/// channels if there is none.
+fn read_channels(document: &roxmltree::Document) -> Vec<Channel> {
+    let channels = document
+        .descendants()
+        .filter(|node| is_element(node, "traceFormat"))
+        .map(|format| {
+            format
+                .children()
+                .filter(|node| is_element(node, "channel"))
+                .filter_map(|channel| {
+                    Some(Channel {
+                        name: channel.attribute("name")?.to_string(),
+                        max: channel.attribute("max").and_then(|max| max.parse().ok()),
+                    })
+                })
+                .collect::<Vec<_>>()
+        })
+        .find(|channels| !channels.is_empty());
+    if let Some(channels) = channels {
+        return channels;
+    }
+
+    ["X", "Y"]
+        .into_iter()
+        .map(|name| Channel {
+            name: name.to_string(),
+            max: None,
+        })
+        .collect()
+}
+
+fn read_brushes(document: &roxmltree::Document) -> HashMap<String, Brush> {
+    let mut brushes = HashMap::new();
+    for node in document
+        .descendants()
+        .filter(|node| is_element(node, "brush"))
+    {
+        let Some(id) = node.attribute((XML_NAMESPACE, "id")) else {
+            continue;
+        };
+        let mut brush = Brush::default();
+        let mut alpha = u8::MAX;
+        for property in node
+            .children()
+            .filter(|node| is_element(node, "brushProperty"))
+        {
+            let Some((name, value)) = property.attribute("name").zip(property.attribute("value"))
+            else {
+                continue;
+            };
+            match name {
+                "width" => brush.width = value.parse().unwrap_or(brush.width),
+                "color" => {
+                    if let Some(rgb) = value
+                        .strip_prefix('#')
+                        .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                    {
+                        brush.color = Color::from_argb(0xFF00_0000 | rgb);
+                    }
+                }
+                "transparency" => {
+                    alpha = u8::MAX - value.parse::<u8>().unwrap_or_default();
+                }
+                "penType" => {
+                    if let Ok(raw) = value.parse() {
+                        brush.pen_type = PenType::from_raw(raw);
+                    }
+                }
+                _ => {}
+            }
+        }
+        brush.color = Color::new(
+            alpha,
+            brush.color.red(),
+            brush.color.green(),
+            brush.color.blue(),
+        );
+        brushes.insert(id.to_string(), brush);
+    }
+    brushes
+}
+
+/// Decodes the points of a trace, resolving the explicit (`!`), first difference (`'`) and
+/// second difference (`"`) value encodings.
+fn read_trace(data: &str, channel_count: usize) -> Result<Vec<Vec<f64>>> {
+    let value_regex =
+        Regex::new(r#"([!'"])?\s*([-+]?(?:\d+\.?\d*|\.\d+)(?:[eE][-+]?\d+)?|[TF*?])"#).unwrap();
+
+    let mut modes = vec!['!'; channel_count];
+    let mut values = vec![0.0; channel_count];
+    let mut velocities = vec![0.0; channel_count];
+    let mut points = Vec::new();
+    for point_data in data.split(',') {
+        if point_data.trim().is_empty() {
+            continue;
+        }
+
+        let mut point = values.clone();
+        for (index, captures) in value_regex
+            .captures_iter(point_data)
+            .take(channel_count)
+            .enumerate()
+        {
+            if let Some(mode) = captures.get(1) {
+                modes[index] = mode.as_str().chars().next().unwrap_or('!');
+            }
+            let value = match &captures[2] {
+                "T" => 1.0,
+                "F" => 0.0,
+                "*" | "?" => {
+                    point[index] = values[index] + velocities[index];
+                    continue;
+                }
+                value => value
+                    .parse::<f64>()
+                    .map_err(|e| Error::InvalidInkml(format!("Invalid value {}: {}", value, e)))?,
+            };
+            point[index] = match modes[index] {
+                '\'' => {
+                    velocities[index] = value;
+                    values[index] + value
+                }
+                '"' => {
+                    velocities[index] += value;
+                    values[index] + velocities[index]
+                }
+                _ => {
+                    velocities[index] = value - values[index];
+                    value
+                }
+            };
+        }
+        values.clone_from(&point);
+        points.push(point);
+    }
+    Ok(points)
+}
+
+/// Returns whether a node is an element with the given name, in any namespace.
+fn is_element(node: &roxmltree::Node, name: &str) -> bool {
+    node.is_element() && node.tag_name().name() == name
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{NoteFile, testing};
+
+    fn read(document: &str) -> PageBuilder {
+        PageBuilder::from_inkml(document.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn reads_channels_of_bare_trace_format() {
+        let page = read(
+            r#"<ink xmlns="http://www.w3.org/2003/InkML">
+                <traceFormat>
+                    <channel name="Y" type="decimal"/>
+                    <channel name="X" type="decimal"/>
+                    <channel name="F" type="integer" max="1"/>
+                </traceFormat>
+                <trace>10 20 1, 11 21 0.5</trace>
+            </ink>"#,
+        );
+        let points = &page.strokes[0].points;
+        assert_eq!((points[0].x, points[0].y), (20.0, 10.0));
+        assert_eq!((points[1].x, points[1].y), (21.0, 11.0));
+        assert_eq!(points[0].pressure, MAX_PRESSURE as u16);
+        assert_eq!(points[1].pressure, (MAX_PRESSURE / 2.0) as u16);
+    }
+
+    #[test]
+    fn reads_attributes_with_spaces_and_entities() {
+        let page = read(
+            r#"<ink xmlns="http://www.w3.org/2003/InkML">
+                <definitions>
+                    <brush xml:id = "pen&amp;marker">
+                        <brushProperty name = 'width' value = "2.5"/>
+                        <brushProperty name="color" value="&#x23;FF0000"/>
+                    </brush>
+                </definitions>
+                <trace id = "t1" brushRef = '#pen&amp;marker'>1 2, 3 4</trace>
+            </ink>"#,
+        );
+        let stroke = &page.strokes[0];
+        assert_eq!(stroke.width, 2.5);
+        assert_eq!(stroke.color, Color::from_argb(0xFFFF_0000));
+        assert_eq!(stroke.points.len(), 2);
+    }
+
+    /// The fixture follows the layout of the CROHME handwriting dataset: no definitions,
+    /// decimal X and Y channels only, annotations and nested trace groups.
+    #[test]
+    fn reads_crohme_documents() {
+        let page =
+            PageBuilder::from_inkml(&include_bytes!("../testdata/crohme.inkml")[..]).unwrap();
+        assert_eq!(
+            page.strokes
+                .iter()
+                .map(|stroke| stroke.points.len())
+                .collect::<Vec<_>>(),
+            [5, 5, 7]
+        );
+        for stroke in &page.strokes {
+            assert_eq!(stroke.pen_type, PenType::default());
+            assert_eq!(stroke.color, Color::BLACK);
+            assert_eq!(stroke.width, DEFAULT_PEN_WIDTH);
+            assert!(stroke.points.iter().all(|point| {
+                point.pressure == MAX_PRESSURE as u16 && point.timestamp_rel == 0
+            }));
+        }
+        let points = &page.strokes[1].points;
+        assert_eq!((points[0].x, points[0].y), (1.1298, 0.3142));
+        assert_eq!((points[4].x, points[4].y), (1.0213, 0.4355));
+        let points = &page.strokes[2].points;
+        assert_eq!((points[6].x, points[6].y), (1.2103, 0.3012));
+    }
+
+    #[test]
+    fn device_samples_are_read_back() {
+        let positions = |points: &[Point]| {
+            points
+                .iter()
+                .map(|point| (point.x, point.y))
+                .collect::<Vec<_>>()
+        };
+        for (label, mut note) in testing::device_sample_notes() {
+            for page_id in note.active_pages().to_vec() {
+                let mut page = note.get_page(&page_id).unwrap();
+                let strokes = page.visible_strokes().unwrap();
+                let mut document = Vec::new();
+                page.write_inkml(&mut document).unwrap();
+                let read = PageBuilder::from_inkml(document.as_slice()).unwrap();
+                let expected = strokes
+                    .iter()
+                    .filter(|(_, stroke)| !stroke.points.is_empty())
+                    .map(|(_, stroke)| positions(&stroke.points))
+                    .collect::<Vec<_>>();
+                let read = read
+                    .strokes
+                    .iter()
+                    .map(|stroke| positions(&stroke.points))
+                    .collect::<Vec<_>>();
+                assert_eq!(read, expected, "{}", label);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_documents() {
+        assert!(matches!(
+            PageBuilder::from_inkml("<ink><trace>1 2</ink>".as_bytes()),
+            Err(Error::InvalidInkml(_))
+        ));
+    }
+
+    #[test]
+    fn written_strokes_are_read_back() {
+        let file = NoteFile::read(Cursor::new(testing::archive())).unwrap();
+        let note_id = *file.list_notes().keys().next().unwrap();
+        let mut note = file.get_note(&note_id).unwrap();
+        let page_id = note.active_pages()[0];
+        let mut page = note.get_page(&page_id).unwrap();
+        let strokes = page.visible_strokes().unwrap();
+
+        let mut document = Vec::new();
+        page.write_inkml(&mut document).unwrap();
+        let read = PageBuilder::from_inkml(document.as_slice()).unwrap();
+
+        assert_eq!(read.strokes.len(), strokes.len());
+        for (read, (shape, stroke)) in read.strokes.iter().zip(&strokes) {
+            assert_eq!(Some(read.pen_type), shape.pen_type);
+            assert_eq!(read.color, shape.color);
+            assert_eq!(read.width, shape.stroke_width);
+            assert_eq!(read.points.len(), stroke.points.len());
+            let first = stroke.points[0].timestamp_rel;
+            for (read, point) in read.points.iter().zip(&stroke.points) {
+                assert_eq!(
+                    Point {
+                        timestamp_rel: point.timestamp_rel - first,
+                        ..point.clone()
+                    },
+                    *read
+                );
+            }
+        }
+    }
+}
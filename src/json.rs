@@ -1,8 +1,8 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 use crate::id::LayerId;
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Dimensions {
     pub top: f32,
@@ -13,7 +13,22 @@ pub struct Dimensions {
     pub stability: u32,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+impl Dimensions {
+    pub fn width(&self) -> f32 {
+        self.right - self.left
+    }
+
+    pub fn height(&self) -> f32 {
+        self.bottom - self.top
+    }
+
+    /// Returns `true` if the rectangle is flagged empty or has no area.
+    pub fn is_empty(&self) -> bool {
+        self.empty || self.width() <= 0.0 || self.height() <= 0.0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Layer {
     pub id: LayerId,
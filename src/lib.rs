@@ -1,28 +1,52 @@
-use std::{collections::HashMap, io::Read};
+use std::collections::HashMap;
 
-use raqote::{DrawOptions, DrawTarget, Source, StrokeStyle};
+use raqote::{AntialiasMode, DrawOptions, DrawTarget};
 
 use crate::{
+    background::{Background, BackgroundImage, LoadedBackground},
+    brush::PenType,
     error::{Error, Result},
-    id::{NoteUuid, PageModelUuid, PageUuid, PointsUuid, ShapeGroupUuid, VirtualPageUuid},
-    note_tree::{NoteMetadata, NoteTree},
+    id::{LayerId, NoteUuid, PageModelUuid, PageUuid, PointsUuid, ShapeGroupUuid, VirtualPageUuid},
+    json::{Dimensions, Layer},
+    note_tree::{BackgroundConfig, NoteMetadata, NoteTree},
     page_model::{PageModel, PageModelGroup},
-    shape::ShapeGroup,
+    points::Stroke,
+    render::{Palette, Rect, RenderOptions, RenderSize, Viewport},
+    replay::{Replay, ReplayOptions},
+    shape::{Shape, ShapeGroup},
     utils::convert_timestamp_to_datetime,
     virtual_doc::VirtualDoc,
     virtual_page::VirtualPage,
 };
 
 mod container;
+mod excalidraw;
+mod geometry;
+mod inkml;
 mod json;
 mod note_tree;
 mod page_model;
+mod page_ops;
+mod pdf;
+mod remarkable;
+mod rnote;
+mod svg;
+#[cfg(test)]
+mod testing;
+mod thumbnail;
 mod utils;
 mod virtual_doc;
+mod xopp;
 
+pub mod background;
+pub mod brush;
+pub mod builder;
+pub mod color;
 pub mod error;
 pub mod id;
 pub mod points;
+pub mod render;
+pub mod replay;
 pub mod shape;
 pub mod virtual_page;
 
@@ -64,6 +88,142 @@ impl<R: std::io::Read + std::io::Seek> NoteFile<R> {
             .get(note_id)
             .map(|metadata| Note::new(self.container.clone(), metadata.clone()))
     }
+
+    /// Writes the file back as a `.note` archive. The note tree is re-encoded if it changed,
+    /// files changed by edits are written with their new content and all other entries are
+    /// copied unchanged.
+    pub fn write(&self, writer: impl std::io::Write + std::io::Seek) -> Result {
+        let note_tree_path = self.note_tree_path();
+        let original = self
+            .container
+            .clone()
+            .get_file_absolute(&note_tree_path, |reader| {
+                let mut buffer = Vec::new();
+                reader.read_to_end(&mut buffer)?;
+                Ok(buffer)
+            })?;
+        let mut note_tree = Vec::new();
+        self.note_tree.write(&mut note_tree)?;
+
+        let mut replacements = std::collections::BTreeMap::new();
+        if note_tree != original {
+            replacements.insert(note_tree_path, note_tree);
+        }
+        self.container.write(writer, &replacements)?;
+        Ok(())
+    }
+
+    /// Writes a single note of the file as a single-note archive.
+    pub fn write_note(
+        &self,
+        note_id: &NoteUuid,
+        writer: impl std::io::Write + std::io::Seek,
+    ) -> Result {
+        let metadata = self
+            .note_tree
+            .get(note_id)
+            .ok_or(Error::NoteNotFound(*note_id))?;
+        let note_dir = note_id.to_simple_string();
+
+        let mut zip = zip::ZipWriter::new(writer);
+        let mut note_info = Vec::new();
+        NoteTree::new(vec![metadata.clone()]).write(&mut note_info)?;
+        zip.start_file(
+            format!("{}/note/pb/note_info", note_dir),
+            zip::write::SimpleFileOptions::default(),
+        )?;
+        std::io::Write::write_all(&mut zip, &note_info)?;
+        self.copy_note_files(&mut zip, note_id, &note_dir)?;
+        zip.finish()?;
+        Ok(())
+    }
+
+    /// Writes every note of the file as a single-note archive named after its ID into
+    /// `directory`, and returns the file paths.
+    pub fn split(&self, directory: impl AsRef<std::path::Path>) -> Result<Vec<std::path::PathBuf>> {
+        let directory = directory.as_ref();
+        std::fs::create_dir_all(directory)?;
+
+        self.note_tree
+            .note_ids()
+            .iter()
+            .map(|note_id| {
+                let path = directory.join(format!("{}.note", note_id.to_simple_string()));
+                let file = std::io::BufWriter::new(std::fs::File::create(&path)?);
+                self.write_note(note_id, file)?;
+                Ok(path)
+            })
+            .collect()
+    }
+
+    /// Writes the notes of all files into one multi-note archive, under the root directory of
+    /// the first multi-note archive among the files. The name of that directory as written by
+    /// the Notes app is not known, so merging only single-note archives fails with
+    /// [`Error::UnknownMultiNoteRoot`].
+    pub fn merge(files: &[NoteFile<R>], writer: impl std::io::Write + std::io::Seek) -> Result {
+        let root = files
+            .iter()
+            .find(|file| *file.container.container_type() == container::ContainerType::MultiNote)
+            .map(|file| file.container.root_path().to_string())
+            .ok_or(Error::UnknownMultiNoteRoot)?;
+
+        let mut notes = Vec::new();
+        for file in files {
+            for note_id in file.note_tree.note_ids() {
+                if notes
+                    .iter()
+                    .any(|(_, metadata): &(_, NoteMetadata)| metadata.note_id == note_id)
+                {
+                    return Err(Error::DuplicateNote(note_id));
+                }
+                notes.push((file, file.note_tree.notes[&note_id].clone()));
+            }
+        }
+
+        let mut zip = zip::ZipWriter::new(writer);
+        let mut note_tree = Vec::new();
+        NoteTree::new(notes.iter().map(|(_, metadata)| metadata.clone()).collect())
+            .write(&mut note_tree)?;
+        zip.start_file(
+            format!("{}/note_tree", root),
+            zip::write::SimpleFileOptions::default(),
+        )?;
+        std::io::Write::write_all(&mut zip, &note_tree)?;
+        for (file, metadata) in &notes {
+            let note_dir = format!("{}/{}", root, metadata.note_id.to_simple_string());
+            file.copy_note_files(&mut zip, &metadata.note_id, &note_dir)?;
+        }
+        zip.finish()?;
+        Ok(())
+    }
+
+    /// Returns the path of the file holding the note tree, or the note info of single-note
+    /// archives.
+    fn note_tree_path(&self) -> String {
+        if *self.container.container_type() == container::ContainerType::MultiNote {
+            format!("{}/note_tree", self.container.root_path())
+        } else {
+            format!("{}/note/pb/note_info", self.container.root_path())
+        }
+    }
+
+    /// Copies the files of a note into `zip`, moving them from the note's directory to
+    /// `note_dir`.
+    fn copy_note_files<W: std::io::Write + std::io::Seek>(
+        &self,
+        zip: &mut zip::ZipWriter<W>,
+        note_id: &NoteUuid,
+        note_dir: &str,
+    ) -> Result {
+        // Single-note archives are rooted in the note's directory, the way `Note` reads them
+        let prefix = self.container.get_file_path(&note_id.to_simple_string());
+        self.container.copy_files(
+            zip,
+            &format!("{}/", prefix),
+            &format!("{}/", note_dir),
+            &[self.note_tree_path()],
+        )
+    }
 }
 
 impl<R: std::io::Read + std::io::Seek> std::fmt::Debug for NoteFile<R> {
@@ -134,7 +294,7 @@ impl<R: std::io::Read + std::io::Seek> Note<R> {
         &self.metadata.fill_color
     }
 
-    pub fn pen_type(&self) -> &u32 {
+    pub fn pen_type(&self) -> &PenType {
         &self.metadata.pen_type
     }
 
@@ -178,12 +338,183 @@ impl<R: std::io::Read + std::io::Seek> Note<R> {
         Some(Page::new(
             self.container.clone(),
             page_id.clone(),
-            self.metadata.note_id.clone(),
+            &self.metadata,
             virtual_page,
             page_model,
         ))
     }
 
+    /// Renders reduced-detail previews of all active pages, each fitting into a square of
+    /// `max_size` pixels.
+    pub fn thumbnails(&mut self, max_size: u32) -> Result<Vec<(PageUuid, DrawTarget)>> {
+        self.render_thumbnails(max_size, None)
+    }
+
+    /// Like [`Self::thumbnails`], but keeps the previews in `cache_dir` and only renders pages
+    /// that were modified since their preview was stored.
+    pub fn thumbnails_cached(
+        &mut self,
+        max_size: u32,
+        cache_dir: impl AsRef<std::path::Path>,
+    ) -> Result<Vec<(PageUuid, DrawTarget)>> {
+        self.render_thumbnails(max_size, Some(cache_dir.as_ref()))
+    }
+
+    fn render_thumbnails(
+        &mut self,
+        max_size: u32,
+        cache_dir: Option<&std::path::Path>,
+    ) -> Result<Vec<(PageUuid, DrawTarget)>> {
+        let options = RenderOptions {
+            size: RenderSize::Fit {
+                width: max_size,
+                height: max_size,
+            },
+            draft: true,
+            ..RenderOptions::default()
+        };
+        let note_id = self.metadata.note_id;
+
+        let mut thumbnails = Vec::with_capacity(self.metadata.active_pages.len());
+        for page_id in self.metadata.active_pages.clone() {
+            let mut page = self
+                .get_page(&page_id)
+                .ok_or(Error::PageNotFound(page_id))?;
+
+            let Some(cache_dir) = cache_dir else {
+                thumbnails.push((page_id, page.render_with_options(&options)?));
+                continue;
+            };
+
+            let path = thumbnail::cache_path(
+                cache_dir,
+                &note_id,
+                &page_id,
+                max_size,
+                &page.page_model.modified,
+            );
+            let draw_target = match thumbnail::load(&path) {
+                Some(draw_target) => draw_target,
+                None => {
+                    let draw_target = page.render_with_options(&options)?;
+                    thumbnail::store(cache_dir, &note_id, &page_id, max_size, &path, &draw_target)?;
+                    draw_target
+                }
+            };
+            thumbnails.push((page_id, draw_target));
+        }
+
+        Ok(thumbnails)
+    }
+
+    /// Exports all active pages of the note as a multi-page vector PDF.
+    pub fn export_pdf(&mut self, writer: impl std::io::Write) -> Result {
+        self.export_pdf_with_options(writer, &RenderOptions::default())
+    }
+
+    pub fn export_pdf_with_options(
+        &mut self,
+        writer: impl std::io::Write,
+        options: &RenderOptions,
+    ) -> Result {
+        let mut pages = Vec::with_capacity(self.metadata.active_pages.len());
+        for page_id in self.metadata.active_pages.clone() {
+            let mut page = self
+                .get_page(&page_id)
+                .ok_or(Error::PageNotFound(page_id))?;
+            // PDF user space has 72 units per inch
+            let viewport = page.viewport(&RenderOptions {
+                size: RenderSize::Dpi(72.0),
+                ..options.clone()
+            })?;
+            pages.push(pdf::PdfPage {
+                strokes: page.visible_strokes()?,
+                background: page.load_background(options)?,
+                page: page.page_rect(),
+                viewport,
+            });
+        }
+
+        pdf::write_pdf(writer, &self.metadata, &pages, &options.palette)
+    }
+
+    /// Exports all active pages of the note as a Xournal++ document, with one layer per layer
+    /// of each page that is not hidden.
+    pub fn export_xopp(&mut self, writer: impl std::io::Write) -> Result {
+        let mut pages = Vec::with_capacity(self.metadata.active_pages.len());
+        for page_id in self.metadata.active_pages.clone() {
+            let mut page = self
+                .get_page(&page_id)
+                .ok_or(Error::PageNotFound(page_id))?;
+            let mut layers = page
+                .layers()
+                .iter()
+                .filter(|layer| layer.show)
+                .map(|layer| (layer.id, Vec::new()))
+                .collect::<Vec<_>>();
+            for (shape, stroke) in page.visible_strokes()? {
+                let layer_id = page.shape_layer(&shape);
+                match layers.iter_mut().find(|(id, _)| *id == layer_id) {
+                    Some((_, strokes)) => strokes.push((shape, stroke)),
+                    None => layers.push((layer_id, vec![(shape, stroke)])),
+                }
+            }
+            pages.push(xopp::XoppPage {
+                page: page.page_rect(),
+                units_per_inch: page.units_per_inch,
+                background: page.background(),
+                layers,
+            });
+        }
+
+        xopp::write_xopp(writer, &self.metadata, &pages)
+    }
+
+    /// Exports all active pages of the note as an Rnote document, stacking the pages in their
+    /// order.
+    ///
+    /// The document layout follows the file format of Rnote 0.9 but was not checked against
+    /// documents saved by Rnote, so Rnote may fail to open the export.
+    pub fn export_rnote(&mut self, writer: impl std::io::Write) -> Result {
+        let mut pages = Vec::with_capacity(self.metadata.active_pages.len());
+        for page_id in self.metadata.active_pages.clone() {
+            let mut page = self
+                .get_page(&page_id)
+                .ok_or(Error::PageNotFound(page_id))?;
+            pages.push(rnote::RnotePage {
+                page: page.page_rect(),
+                units_per_inch: page.units_per_inch,
+                background: page.background(),
+                strokes: page.visible_strokes()?,
+            });
+        }
+
+        rnote::write_rnote(writer, &pages)
+    }
+
+    /// Exports every active page of the note as a reMarkable v6 scene named after the page ID
+    /// into `directory`, and returns the file paths in page order.
+    pub fn export_remarkable(
+        &mut self,
+        directory: impl AsRef<std::path::Path>,
+    ) -> Result<Vec<std::path::PathBuf>> {
+        let directory = directory.as_ref();
+        std::fs::create_dir_all(directory)?;
+
+        self.metadata
+            .active_pages
+            .clone()
+            .iter()
+            .map(|page_id| {
+                let mut page = self.get_page(page_id).ok_or(Error::PageNotFound(*page_id))?;
+                let path = directory.join(format!("{}.rm", page_id.to_hyphenated_string()));
+                let file = std::io::BufWriter::new(std::fs::File::create(&path)?);
+                page.write_remarkable(file)?;
+                Ok(path)
+            })
+            .collect()
+    }
+
     pub fn virtual_doc(&mut self) -> Result<&VirtualDoc> {
         if self.virtual_doc.is_none() {
             let note_id = self.metadata.note_id.to_simple_string();
@@ -255,6 +586,10 @@ pub struct Page<R: std::io::Read + std::io::Seek> {
     page_id: PageUuid,
     virtual_page: Option<VirtualPage>,
     page_model: PageModel,
+    background_config: BackgroundConfig,
+    default_page_rect: Dimensions,
+    canvas_expands: bool,
+    units_per_inch: f32,
     shape_groups: Option<HashMap<ShapeGroupUuid, ShapeGroup>>,
     points_files: Option<HashMap<PointsUuid, Vec<points::PointsFile>>>,
 }
@@ -263,16 +598,22 @@ impl<R: std::io::Read + std::io::Seek> Page<R> {
     fn new(
         container: container::Container<R>,
         page_id: PageUuid,
-        note_id: NoteUuid,
+        metadata: &NoteMetadata,
         virtual_page: Option<VirtualPage>,
         page_model: PageModel,
     ) -> Self {
         Self {
             container,
             page_id,
-            note_id,
+            note_id: metadata.note_id,
             virtual_page,
             page_model,
+            background_config: metadata.background_config.clone(),
+            default_page_rect: metadata.canvas_state.default_page_rect.clone(),
+            // Uncertain: the values of the expand type other than an empty string are unknown
+            canvas_expands: metadata.background_config.background_config.canvas_auto_expand
+                || !metadata.canvas_state.canvas_expand_type.is_empty(),
+            units_per_inch: render::units_per_inch(metadata),
             shape_groups: None,
             points_files: None,
         }
@@ -286,6 +627,64 @@ impl<R: std::io::Read + std::io::Seek> Page<R> {
         &self.page_model
     }
 
+    /// Returns the physical width and height of the page in inches.
+    pub fn physical_size(&self) -> (f32, f32) {
+        let page_rect = self.page_rect();
+        (
+            page_rect.width() / self.units_per_inch,
+            page_rect.height() / self.units_per_inch,
+        )
+    }
+
+    /// Returns the page rect in page coordinates, which need not start at the origin.
+    ///
+    /// Falls back to the geometry of the virtual page and then to the default page rect of the
+    /// note when the page model has no dimensions.
+    pub fn page_rect(&self) -> Rect {
+        // Uncertain: `layout` places the page within the virtual document and is not used
+        let virtual_page_rects = self
+            .virtual_page
+            .iter()
+            .flat_map(|virtual_page| [&virtual_page.geo, &virtual_page.dimensions]);
+        std::iter::once(&self.page_model.dimensions)
+            .chain(virtual_page_rects)
+            .chain(std::iter::once(&self.default_page_rect))
+            .find(|dimensions| !dimensions.is_empty())
+            .map(Rect::from)
+            .unwrap_or(Rect {
+                left: 0.0,
+                top: 0.0,
+                right: self.default_page_rect.width().max(1.0),
+                bottom: self.default_page_rect.height().max(1.0),
+            })
+    }
+
+    /// Returns the area covered by the page. On auto-expanding canvases this is the page rect
+    /// grown to include all ink drawn past its edges.
+    pub fn canvas_rect(&mut self) -> Result<Rect> {
+        let page_rect = self.page_rect();
+        if !self.canvas_expands {
+            return Ok(page_rect);
+        }
+
+        let canvas_rect = self
+            .shape_groups()?
+            .values()
+            .flat_map(|shape_group| shape_group.shapes())
+            .filter(|shape| !shape.bbox.is_empty())
+            .fold(page_rect, |rect, shape| rect.union(&Rect::from(&shape.bbox)));
+        Ok(canvas_rect)
+    }
+
+    /// Returns how the page is mapped onto the output for the given options.
+    pub fn viewport(&mut self, options: &RenderOptions) -> Result<Viewport> {
+        let region = match options.crop {
+            Some(crop) => crop,
+            None => self.canvas_rect()?,
+        };
+        Ok(Viewport::new(region, options.size, self.units_per_inch))
+    }
+
     pub fn shape_groups(&mut self) -> Result<&HashMap<ShapeGroupUuid, ShapeGroup>> {
         if self.shape_groups.is_none() {
             let note_id = self.note_id.to_simple_string();
@@ -300,17 +699,29 @@ impl<R: std::io::Read + std::io::Seek> Page<R> {
                 let path_tail = shape_group_path.rsplit('/').next().unwrap();
                 let parts = path_tail.split('#').collect::<Vec<_>>();
                 let shape_group_id = ShapeGroupUuid::from_str(parts[1])?;
-                let _timestamp = convert_timestamp_to_datetime(
+                let timestamp = convert_timestamp_to_datetime(
                     parts[2].replace(".zip", "").parse::<u64>().map_err(|e| {
                         Error::InvalidTimestampFormat(format!("Failed to parse timestamp: {}", e))
                     })?,
-                );
+                )?;
+                // Uncertain: of several revisions of a shape group, the newest is assumed current
+                if shape_groups
+                    .get(&shape_group_id)
+                    .is_some_and(|(newest, _)| *newest > timestamp)
+                {
+                    continue;
+                }
                 let shape_group = self
                     .container
                     .get_file_absolute(&shape_group_path, |reader| ShapeGroup::read(reader))?;
-                shape_groups.insert(shape_group_id, shape_group);
+                shape_groups.insert(shape_group_id, (timestamp, shape_group));
             }
-            self.shape_groups = Some(shape_groups);
+            self.shape_groups = Some(
+                shape_groups
+                    .into_iter()
+                    .map(|(id, (_, shape_group))| (id, shape_group))
+                    .collect(),
+            );
         }
         Ok(self.shape_groups.as_ref().unwrap())
     }
@@ -332,7 +743,7 @@ impl<R: std::io::Read + std::io::Seek> Page<R> {
 
                 let file_data = self
                     .container
-                    .get_file_absolute(&stroke_path, |mut reader| {
+                    .get_file_absolute(&stroke_path, |reader| {
                         let mut buffer = Vec::new();
                         reader.read_to_end(&mut buffer).map_err(Error::Io)?;
                         Ok(buffer)
@@ -351,29 +762,96 @@ impl<R: std::io::Read + std::io::Seek> Page<R> {
         Ok(self.points_files.as_ref().unwrap())
     }
 
-    pub fn render(&mut self) -> Result<DrawTarget> {
-        let page_id = self.page_id.to_hyphenated_string();
-        let width = self.page_model.dimensions.right - self.page_model.dimensions.left;
-        let height = self.page_model.dimensions.bottom - self.page_model.dimensions.top;
-        let mut draw_target = DrawTarget::new(width as i32, height as i32);
-        let draw_options = DrawOptions::new();
-
-        draw_target.fill_rect(
-            0.0,
-            0.0,
-            width,
-            height,
-            &Source::Solid(raqote::Color::new(255, 255, 255, 255).into()),
-            &DrawOptions::new(),
+    /// Resolves the background of the page, either the document-wide one or its own.
+    pub fn background(&self) -> Background {
+        let note_id = self.note_id.to_simple_string();
+        let mut files = self
+            .container
+            .list_directory(&format!("{}/template/", note_id));
+        files.extend(
+            self.container
+                .list_directory(&format!("{}/resource/", note_id)),
         );
 
-        // Extract shape_groups and points_files into local variables to avoid multiple mutable borrows.
-        let shape_groups = {
+        Background::resolve(
+            &self.background_config,
+            &self.page_id,
+            self.virtual_page
+                .as_ref()
+                .map(|virtual_page| virtual_page.template_path.as_str()),
+            &files,
+        )
+    }
+
+    fn load_background(&mut self, options: &RenderOptions) -> Result<LoadedBackground> {
+        if !options.background {
+            return Ok(LoadedBackground::default());
+        }
+
+        let background = self.background();
+        let image = match &background {
+            Background::Image { path } if path.to_lowercase().ends_with(".png") => {
+                let data = self.container.get_file_absolute(path, |reader| {
+                    let mut buffer = Vec::new();
+                    reader.read_to_end(&mut buffer).map_err(Error::Io)?;
+                    Ok(buffer)
+                })?;
+                let image = BackgroundImage::decode_png(data)?;
+                if options.palette == Palette::Original {
+                    Some(image)
+                } else {
+                    Some(image.map_colors(|color| options.palette.map(color))?)
+                }
+            }
+            Background::Image { path } => {
+                log::warn!("Unsupported background image format: {}", path);
+                None
+            }
+            _ => None,
+        };
+
+        Ok(LoadedBackground { background, image })
+    }
+
+    pub fn layers(&self) -> &[Layer] {
+        &self.page_model.layers
+    }
+
+    /// Returns the layer a shape is drawn on. Shapes without layer information belong to the
+    /// bottom layer of the page.
+    ///
+    /// The layer of a shape is read from a field that was not confirmed against a note with
+    /// several layers, see [`Shape::layer_id`].
+    pub fn shape_layer(&self, shape: &Shape) -> LayerId {
+        shape
+            .layer_id
+            .or_else(|| self.page_model.layers.first().map(|layer| layer.id))
+            .unwrap_or_default()
+    }
+
+    /// Returns every shape of the page joined with its stroke, sorted by layer stack order and
+    /// z-order within each layer.
+    pub fn strokes(&mut self) -> Result<Vec<(Shape, Stroke)>> {
+        let page_id = self.page_id.to_hyphenated_string();
+
+        let mut shapes = {
             let sg = self.shape_groups().inspect_err(|_| {
                 log::error!("Failed to get shape groups for page ID: {}", page_id)
             })?;
-            sg.clone()
+            sg.values()
+                .flat_map(|shape_group| shape_group.shapes().iter().cloned())
+                .collect::<Vec<_>>()
         };
+        shapes.sort_by_key(|shape| {
+            let layer_id = self.shape_layer(shape);
+            let stack_position = self
+                .page_model
+                .layers
+                .iter()
+                .position(|layer| layer.id == layer_id)
+                .unwrap_or(self.page_model.layers.len());
+            (stack_position, layer_id, shape.z_order, shape.created)
+        });
 
         let points_files_vec = {
             let pf = self.points_files().inspect_err(|_| {
@@ -382,48 +860,209 @@ impl<R: std::io::Read + std::io::Seek> Page<R> {
             pf.values().flatten().collect::<Vec<_>>()
         };
 
-        for (shape_group_id, shape_group) in &shape_groups {
-            let mut shapes = shape_group.shapes().to_vec();
-            shapes.sort_by(|a, b| a.z_order.cmp(&b.z_order));
-
-            for shape in shapes {
-                if let Some(points_id) = shape.points_id {
-                    if let Some(points) = points_files_vec
-                        .iter()
-                        .find(|pf| pf.header().points_id == points_id)
-                    {
-                        points
-                            .get_stroke(&shape.stroke_id)
-                            .ok_or_else(|| {
-                                log::error!("Failed to get stroke for shape");
-                                Error::StrokeNotFound
-                            })
-                            .and_then(|stroke| {
-                                log::debug!("Rendering stroke for shape");
-                                log::debug!(
-                                    "Shape Group ID: {}, Stroke ID: {}",
-                                    shape_group_id.to_hyphenated_string(),
-                                    shape.stroke_id.to_hyphenated_string()
-                                );
-                                log::debug!("Shape: {:#x?}", shape);
-                                stroke.render(
-                                    &mut draw_target,
-                                    &draw_options,
-                                    &StrokeStyle::default(),
-                                )
-                            })?;
-                    } else {
-                        log::warn!(
-                            "No points files found for shape group: {}",
-                            shape_group_id.to_hyphenated_string()
-                        );
-                    }
+        let mut strokes = Vec::with_capacity(shapes.len());
+        for shape in shapes {
+            let Some(points_id) = shape.points_id else {
+                continue;
+            };
+            let Some(points) = points_files_vec
+                .iter()
+                .find(|pf| pf.header().points_id == points_id)
+            else {
+                log::warn!(
+                    "No points files found for shape group: {}",
+                    shape.shape_group_id.to_hyphenated_string()
+                );
+                continue;
+            };
+            let stroke = points.get_stroke(&shape.stroke_id).ok_or_else(|| {
+                log::error!("Failed to get stroke for shape");
+                Error::StrokeNotFound
+            })?;
+            strokes.push((shape, stroke.clone()));
+        }
+
+        Ok(strokes)
+    }
+
+    /// Returns the strokes of all layers that are not hidden, in drawing order.
+    pub fn visible_strokes(&mut self) -> Result<Vec<(Shape, Stroke)>> {
+        let hidden_layers = self
+            .page_model
+            .layers
+            .iter()
+            .filter(|layer| !layer.show)
+            .map(|layer| layer.id)
+            .collect::<Vec<_>>();
+
+        let mut strokes = self.strokes()?;
+        strokes.retain(|(shape, _)| !hidden_layers.contains(&self.shape_layer(shape)));
+        Ok(strokes)
+    }
+
+    /// Returns the strokes of the given layers in drawing order, whether they are shown or not.
+    ///
+    /// Shapes are assigned to layers by [`Self::shape_layer`], which is unverified for notes
+    /// with several layers.
+    pub fn layer_strokes(&mut self, layers: &[LayerId]) -> Result<Vec<(Shape, Stroke)>> {
+        let mut strokes = self.strokes()?;
+        strokes.retain(|(shape, _)| layers.contains(&self.shape_layer(shape)));
+        Ok(strokes)
+    }
+
+    /// Renders all layers of the page that are not hidden.
+    pub fn render(&mut self) -> Result<DrawTarget> {
+        self.render_with_options(&RenderOptions::default())
+    }
+
+    pub fn render_with_options(&mut self, options: &RenderOptions) -> Result<DrawTarget> {
+        let strokes = self.visible_strokes()?;
+        self.render_strokes(&strokes, options)
+    }
+
+    /// Renders only the given layers, e.g. to export an annotation layer on its own.
+    pub fn render_layers(&mut self, layers: &[LayerId]) -> Result<DrawTarget> {
+        self.render_layers_with_options(layers, &RenderOptions::default())
+    }
+
+    pub fn render_layers_with_options(
+        &mut self,
+        layers: &[LayerId],
+        options: &RenderOptions,
+    ) -> Result<DrawTarget> {
+        let strokes = self.layer_strokes(layers)?;
+        self.render_strokes(&strokes, options)
+    }
+
+    fn render_strokes(
+        &mut self,
+        strokes: &[(Shape, Stroke)],
+        options: &RenderOptions,
+    ) -> Result<DrawTarget> {
+        let mut draw_target = self.background_target(options)?;
+        let mut draw_options = DrawOptions::new();
+        // Points closer than an output pixel to the decimated line make no visible difference
+        let draft_tolerance = if options.draft {
+            draw_options.antialias = AntialiasMode::None;
+            Some(1.0 / self.viewport(options)?.scale)
+        } else {
+            None
+        };
+
+        for (shape, stroke) in strokes {
+            log::debug!("Rendering stroke for shape");
+            log::debug!(
+                "Shape Group ID: {}, Stroke ID: {}",
+                shape.shape_group_id.to_hyphenated_string(),
+                shape.stroke_id.to_hyphenated_string()
+            );
+            log::debug!("Shape: {:#x?}", shape);
+            let brush = options.palette.brush(shape);
+            match draft_tolerance {
+                Some(tolerance) => {
+                    stroke
+                        .decimate(tolerance)
+                        .render(&mut draw_target, &draw_options, &brush)?
                 }
+                None => stroke.render(&mut draw_target, &draw_options, &brush)?,
             }
         }
 
         Ok(draw_target)
     }
+
+    /// Creates a draw target for the page with its background drawn and the page to output
+    /// transform set.
+    fn background_target(&mut self, options: &RenderOptions) -> Result<DrawTarget> {
+        let viewport = self.viewport(options)?;
+        let mut draw_target = DrawTarget::new(viewport.width(), viewport.height());
+        draw_target.set_transform(&viewport.transform());
+
+        let background = self.load_background(options)?;
+        render::draw_background(
+            &mut draw_target,
+            &self.page_rect(),
+            &viewport.region,
+            &background,
+            &options.palette,
+        );
+        Ok(draw_target)
+    }
+
+    /// Replays how the page was written, yielding one frame per time step.
+    pub fn replay(&mut self, options: &ReplayOptions) -> Result<Replay> {
+        let strokes = self.visible_strokes()?;
+        let draw_target = self.background_target(&options.render)?;
+        Ok(Replay::new(draw_target, strokes, options))
+    }
+
+    /// Renders the page as an SVG document with one vector path per stroke.
+    pub fn to_svg(&mut self) -> Result<String> {
+        let mut buffer = Vec::new();
+        self.write_svg(&mut buffer)?;
+        Ok(String::from_utf8(buffer).expect("SVG output is always valid UTF-8"))
+    }
+
+    /// Writes the page as an SVG document to the given writer.
+    pub fn write_svg(&mut self, writer: impl std::io::Write) -> Result {
+        self.write_svg_with_options(writer, &RenderOptions::default())
+    }
+
+    pub fn write_svg_with_options(
+        &mut self,
+        writer: impl std::io::Write,
+        options: &RenderOptions,
+    ) -> Result {
+        let strokes = self.visible_strokes()?;
+        let background = self.load_background(options)?;
+        svg::write_svg(
+            writer,
+            &self.viewport(options)?,
+            &self.page_rect(),
+            &background,
+            &strokes,
+            &options.palette,
+        )
+    }
+
+    /// Writes the strokes of the page as a reMarkable v6 scene, scaled to fit the reMarkable
+    /// canvas, with one layer per layer of the page.
+    pub fn write_remarkable(&mut self, writer: impl std::io::Write) -> Result {
+        let mut layers = self
+            .layers()
+            .iter()
+            .map(|layer| (layer.clone(), Vec::new()))
+            .collect::<Vec<_>>();
+        for (shape, stroke) in self.strokes()? {
+            let layer_id = self.shape_layer(&shape);
+            match layers.iter_mut().find(|(layer, _)| layer.id == layer_id) {
+                Some((_, strokes)) => strokes.push((shape, stroke)),
+                None => layers.push((
+                    Layer {
+                        id: layer_id,
+                        lock: false,
+                        show: true,
+                    },
+                    vec![(shape, stroke)],
+                )),
+            }
+        }
+        remarkable::write_remarkable(writer, &self.page_rect(), &layers, self.note_id.uuid())
+    }
+
+    /// Writes the visible strokes of the page as an Excalidraw scene with one free-hand element
+    /// per stroke.
+    pub fn write_excalidraw(&mut self, writer: impl std::io::Write) -> Result {
+        let strokes = self.visible_strokes()?;
+        excalidraw::write_excalidraw(writer, &strokes, self.units_per_inch)
+    }
+
+    /// Writes the visible strokes of the page as an InkML document, with X, Y, pressure, tilt
+    /// and time channels.
+    pub fn write_inkml(&mut self, writer: impl std::io::Write) -> Result {
+        let strokes = self.visible_strokes()?;
+        inkml::write_inkml(writer, &strokes, self.units_per_inch)
+    }
 }
 
 impl<R: std::io::Read + std::io::Seek> std::fmt::Debug for Page<R> {
@@ -434,3 +1073,167 @@ impl<R: std::io::Read + std::io::Seek> std::fmt::Debug for Page<R> {
             .finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::testing;
+
+    fn write(file: &NoteFile<Cursor<Vec<u8>>>) -> Vec<u8> {
+        let mut buffer = Cursor::new(Vec::new());
+        file.write(&mut buffer).unwrap();
+        buffer.into_inner()
+    }
+
+    fn note_id(file: &NoteFile<Cursor<Vec<u8>>>) -> NoteUuid {
+        *file.list_notes().keys().next().unwrap()
+    }
+
+    #[test]
+    fn unchanged_file_is_written_unchanged() {
+        let mut entries = testing::entries(&testing::archive());
+        // Entries the crate fails to decode are copied all the same
+        let page_model_path = entries
+            .keys()
+            .find(|path| path.contains("/pageModel/pb/"))
+            .unwrap()
+            .clone();
+        entries.insert(page_model_path, b"not a page model".to_vec());
+        let archive = testing::zip(&entries);
+
+        let file = NoteFile::read(Cursor::new(archive.clone())).unwrap();
+        let written = write(&file);
+        assert_eq!(testing::entries(&written), entries);
+
+        let mut original = zip::ZipArchive::new(Cursor::new(archive)).unwrap();
+        let mut written = zip::ZipArchive::new(Cursor::new(written)).unwrap();
+        for index in 0..original.len() {
+            let original = original.by_index_raw(index).unwrap();
+            let written = written.by_index_raw(index).unwrap();
+            assert_eq!(written.name(), original.name());
+            assert_eq!(written.compression(), original.compression());
+            assert_eq!(written.crc32(), original.crc32());
+            assert_eq!(written.compressed_size(), original.compressed_size());
+        }
+    }
+
+    #[test]
+    fn edited_file_is_written_with_changes_only() {
+        let archive = testing::archive();
+        let entries = testing::entries(&archive);
+        let mut file = NoteFile::read(Cursor::new(archive)).unwrap();
+        let note_id = note_id(&file);
+        let mut order = file.get_note(&note_id).unwrap().active_pages().to_vec();
+        order.reverse();
+        file.reorder_pages(&note_id, &order).unwrap();
+
+        let written = write(&file);
+        let written_entries = testing::entries(&written);
+        assert_eq!(
+            written_entries.keys().collect::<Vec<_>>(),
+            entries.keys().collect::<Vec<_>>()
+        );
+        for (path, data) in &written_entries {
+            if path.ends_with("/note/pb/note_info") {
+                assert_ne!(*data, entries[path]);
+            } else {
+                assert_eq!(*data, entries[path], "{} changed", path);
+            }
+        }
+
+        let file = NoteFile::read(Cursor::new(written)).unwrap();
+        let note = file.get_note(&note_id).unwrap();
+        assert_eq!(note.active_pages(), order.as_slice());
+        assert_eq!(note.name(), "Test");
+    }
+
+    #[test]
+    fn device_samples_are_written_unchanged() {
+        for (path, archive) in testing::device_samples() {
+            let file = NoteFile::read(Cursor::new(archive.clone())).unwrap();
+            let written = write(&file);
+            assert_eq!(
+                testing::entries(&written),
+                testing::entries(&archive),
+                "{}",
+                path.display()
+            );
+        }
+    }
+
+    #[test]
+    fn edited_device_samples_are_written_with_changes_only() {
+        for (path, archive) in testing::device_samples() {
+            let entries = testing::entries(&archive);
+            let mut file = NoteFile::read(Cursor::new(archive)).unwrap();
+            let mut orders = Vec::new();
+            for note_id in file.list_notes().into_keys() {
+                let mut order = file.get_note(&note_id).unwrap().active_pages().to_vec();
+                order.reverse();
+                file.reorder_pages(&note_id, &order).unwrap();
+                orders.push((note_id, order));
+            }
+
+            let written = write(&file);
+            let written_entries = testing::entries(&written);
+            assert_eq!(
+                written_entries.keys().collect::<Vec<_>>(),
+                entries.keys().collect::<Vec<_>>(),
+                "{}",
+                path.display()
+            );
+            let note_tree_path = file.note_tree_path();
+            for (entry, data) in written_entries {
+                if entry != note_tree_path {
+                    assert_eq!(data, entries[&entry], "{} in {}", entry, path.display());
+                }
+            }
+            let file = NoteFile::read(Cursor::new(written)).unwrap();
+            for (note_id, order) in orders {
+                let note = file.get_note(&note_id).unwrap();
+                assert_eq!(note.active_pages(), order.as_slice(), "{}", path.display());
+            }
+        }
+    }
+
+    #[test]
+    fn merging_takes_the_root_of_a_multi_note_archive() {
+        // A root directory the crate does not write itself
+        let mut buffer = Cursor::new(Vec::new());
+        builder::NoteBuilder::write_multi(&[builder::NoteBuilder::new("Other")], &mut buffer)
+            .unwrap();
+        let multi_note = testing::entries(&buffer.into_inner())
+            .into_iter()
+            .map(|(path, data)| (path.replacen("notes/", "Notebooks/", 1), data))
+            .collect();
+        let files = [
+            NoteFile::read(Cursor::new(testing::archive())).unwrap(),
+            NoteFile::read(Cursor::new(testing::zip(&multi_note))).unwrap(),
+        ];
+
+        let mut buffer = Cursor::new(Vec::new());
+        NoteFile::merge(&files, &mut buffer).unwrap();
+        let merged = buffer.into_inner();
+        assert!(
+            testing::entries(&merged)
+                .keys()
+                .all(|path| path.starts_with("Notebooks/"))
+        );
+
+        let merged = NoteFile::read(Cursor::new(merged)).unwrap();
+        let mut names = merged.list_notes().into_values().collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["Other", "Test"]);
+        let note_id = note_id(&files[0]);
+        assert_eq!(merged.get_note(&note_id).unwrap().active_pages().len(), 3);
+    }
+
+    #[test]
+    fn merging_single_note_archives_fails() {
+        let files = [NoteFile::read(Cursor::new(testing::archive())).unwrap()];
+        let result = NoteFile::merge(&files, Cursor::new(Vec::new()));
+        assert!(matches!(result, Err(Error::UnknownMultiNoteRoot)));
+    }
+}
@@ -1,15 +1,23 @@
 use std::collections::HashMap;
 
+use prost::Message;
+
 use crate::{
+    brush::PenType,
     id::{NoteUuid, PageUuid},
-    utils::{convert_timestamp_to_datetime, parse_json},
+    utils::{
+        convert_datetime_to_timestamp, convert_timestamp_to_datetime, encode_protobuf, parse_json,
+        update_json,
+    },
 };
 use chrono::{DateTime, Utc};
-use json::*;
+pub use json::*;
 
 #[derive(Debug, Clone)]
 pub struct NoteTree {
     pub notes: HashMap<NoteUuid, NoteMetadata>,
+    /// Order of the notes in the file, so they are written back in the same order.
+    order: Vec<NoteUuid>,
 }
 
 impl NoteTree {
@@ -18,12 +26,47 @@ impl NoteTree {
         let notes = note_tree
             .notes
             .iter()
-            .map(|note| {
-                let note_metadata = NoteMetadata::from_protobuf(note)?;
-                Ok((note_metadata.note_id, note_metadata))
-            })
-            .collect::<crate::error::Result<_>>()?;
-        Ok(Self { notes })
+            .map(|note| NoteMetadata::from_protobuf(note))
+            .collect::<crate::error::Result<Vec<_>>>()?;
+        Ok(Self::new(notes))
+    }
+
+    pub fn new(notes: Vec<NoteMetadata>) -> Self {
+        Self {
+            order: notes.iter().map(|note| note.note_id).collect(),
+            notes: notes.into_iter().map(|note| (note.note_id, note)).collect(),
+        }
+    }
+
+    /// Returns the IDs of the notes in file order, followed by notes added since.
+    pub fn note_ids(&self) -> Vec<NoteUuid> {
+        let mut note_ids = self
+            .order
+            .iter()
+            .filter(|note_id| self.notes.contains_key(note_id))
+            .copied()
+            .collect::<Vec<_>>();
+        let mut added = self
+            .notes
+            .keys()
+            .filter(|note_id| !self.order.contains(note_id))
+            .copied()
+            .collect::<Vec<_>>();
+        added.sort_by_key(|note_id| note_id.to_simple_string());
+        note_ids.extend(added);
+        note_ids
+    }
+
+    pub fn write(&self, mut writer: impl std::io::Write) -> crate::error::Result {
+        let note_tree = protobuf::NoteTree {
+            notes: self
+                .note_ids()
+                .iter()
+                .map(|note_id| self.notes[note_id].to_protobuf())
+                .collect::<crate::error::Result<_>>()?,
+        };
+        writer.write_all(&note_tree.encode_to_vec())?;
+        Ok(())
     }
 
     pub fn get(&self, note_id: &NoteUuid) -> Option<&NoteMetadata> {
@@ -31,7 +74,7 @@ impl NoteTree {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct NoteMetadata {
     pub note_id: NoteUuid,
     pub created: DateTime<Utc>,
@@ -45,7 +88,7 @@ pub struct NoteMetadata {
     pub background_config: BackgroundConfig,
     pub device_info: DeviceInfo,
     pub fill_color: u32,
-    pub pen_type: u32,
+    pub pen_type: PenType,
     pub active_pages: Vec<PageUuid>,
     pub reserved_pages: Vec<PageUuid>,
     pub canvas_width: f32,
@@ -56,12 +99,43 @@ pub struct NoteMetadata {
     pub has_share_user: u32,
     pub share_user: String,
     pub detached_pages: Vec<PageUuid>,
+    /// Encoded message the metadata was read from, so fields the crate does not model survive
+    /// a rewrite. Empty for metadata created by the crate.
+    pub(crate) raw: Vec<u8>,
+}
+
+/// Matches a JSON string, so keys are only looked for outside of strings, or a numeric key.
+const PEN_SETTINGS_KEY_PATTERN: &str = r#""(?:[^"\\]|\\.)*"|([{,]\s*)"?(\d+)"?(\s*:)"#;
+
+/// Quotes the numeric keys of the pen settings, which the Notes app writes as bare numbers.
+fn fix_pen_settings_json(json: &str) -> String {
+    replace_pen_settings_keys(json, "\"")
+}
+
+/// Turns the numeric keys of the pen settings back into the bare numbers the Notes app writes.
+fn unfix_pen_settings_json(json: &str) -> String {
+    replace_pen_settings_keys(json, "")
+}
+
+fn replace_pen_settings_keys(json: &str, quote: &str) -> String {
+    let key_regex = regex::Regex::new(PEN_SETTINGS_KEY_PATTERN).unwrap();
+    key_regex
+        .replace_all(json, |captures: &regex::Captures| match captures.get(2) {
+            Some(key) => format!(
+                "{}{quote}{}{quote}{}",
+                &captures[1],
+                key.as_str(),
+                &captures[3]
+            ),
+            None => captures[0].to_string(),
+        })
+        .into_owned()
 }
 
 impl NoteMetadata {
-    pub fn from_protobuf(note: &protobuf::NoteMetadata) -> crate::error::Result<Self> {
-        let fix_regex = regex::Regex::new(r"(\d+):").unwrap();
-        let fixed_pen_settings_json = fix_regex.replace_all(&note.pen_settings_json, "\"$1\":");
+    pub fn from_protobuf(raw: &[u8]) -> crate::error::Result<Self> {
+        let note = protobuf::NoteMetadata::decode(raw)?;
+        let fixed_pen_settings_json = fix_pen_settings_json(&note.pen_settings_json);
 
         Ok(Self {
             note_id: NoteUuid::from_str(&note.note_id)?,
@@ -76,7 +150,7 @@ impl NoteMetadata {
             background_config: parse_json(&note.background_config_json)?,
             device_info: parse_json(&note.device_info_json)?,
             fill_color: note.fill_color,
-            pen_type: note.pen_type,
+            pen_type: PenType::from_raw(note.pen_type),
             active_pages: parse_json::<PageNameList>(&note.active_pages_json)?.page_name_list,
             reserved_pages: parse_json::<PageNameList>(&note.reserved_pages_json)?.page_name_list,
             canvas_width: note.canvas_width,
@@ -87,29 +161,86 @@ impl NoteMetadata {
             has_share_user: note.has_share_user,
             share_user: note.share_user.clone(),
             detached_pages: parse_json::<PageNameList>(&note.detached_pages_json)?.page_name_list,
+            raw: raw.to_vec(),
         })
     }
+
+    pub fn to_protobuf(&self) -> crate::error::Result<Vec<u8>> {
+        if Self::from_protobuf(&self.raw).is_ok_and(|original| original == *self) {
+            return Ok(self.raw.clone());
+        }
+
+        let mut note = protobuf::NoteMetadata::decode(self.raw.as_slice())?;
+        note.note_id = self.note_id.to_string_like(&note.note_id);
+        note.created = convert_datetime_to_timestamp(&self.created);
+        note.modified = convert_datetime_to_timestamp(&self.modified);
+        note.note_name = self.name.clone();
+        note.flag = self.flag;
+        note.pen_width = self.pen_width;
+        note.scale_factor = self.scale_factor;
+        let fixed_pen_settings_json = fix_pen_settings_json(&note.pen_settings_json);
+        let pen_settings_json = update_json(&fixed_pen_settings_json, &self.pen_settings)?;
+        if pen_settings_json != fixed_pen_settings_json {
+            note.pen_settings_json = unfix_pen_settings_json(&pen_settings_json);
+        }
+        note.canvas_state_json = update_json(&note.canvas_state_json, &self.canvas_state)?;
+        note.background_config_json =
+            update_json(&note.background_config_json, &self.background_config)?;
+        note.device_info_json = update_json(&note.device_info_json, &self.device_info)?;
+        note.fill_color = self.fill_color;
+        note.pen_type = self.pen_type.to_raw();
+        note.active_pages_json = update_page_list(&note.active_pages_json, &self.active_pages)?;
+        note.reserved_pages_json =
+            update_page_list(&note.reserved_pages_json, &self.reserved_pages)?;
+        note.canvas_width = self.canvas_width;
+        note.canvas_height = self.canvas_height;
+        note.location = self.location.clone();
+        note.has_share_section = self.has_share_section;
+        note.stroke_data_len = self.stroke_data_len;
+        note.has_share_user = self.has_share_user;
+        note.share_user = self.share_user.clone();
+        note.detached_pages_json =
+            update_page_list(&note.detached_pages_json, &self.detached_pages)?;
+
+        encode_protobuf(&note, &self.raw, protobuf::NoteMetadata::TAGS)
+    }
+}
+
+fn update_page_list(original: &str, pages: &[PageUuid]) -> crate::error::Result<String> {
+    update_json(
+        original,
+        &PageNameList {
+            page_name_list: pages.to_vec(),
+        },
+    )
 }
 
 mod json {
     use std::collections::HashMap;
 
-    use serde::Deserialize;
+    use serde::{Deserialize, Serialize};
 
     use crate::{
+        brush::PenType,
         id::{LayerId, PageUuid, PenId},
         json::{Dimensions, Layer},
     };
 
-    #[derive(Debug, Clone, Deserialize)]
+    #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
     #[serde(rename_all = "camelCase")]
     pub struct PenSettings {
-        #[serde(deserialize_with = "crate::utils::deserialize_color")]
+        #[serde(
+            deserialize_with = "crate::utils::deserialize_color",
+            serialize_with = "crate::utils::serialize_color"
+        )]
         pub fill_color: u32,
-        #[serde(deserialize_with = "crate::utils::deserialize_color")]
+        #[serde(
+            deserialize_with = "crate::utils::deserialize_color",
+            serialize_with = "crate::utils::serialize_color"
+        )]
         pub graphics_shape_color: u32,
         pub graphics_shape_type: u8,
-        pub normal_pen_shape_type: u8,
+        pub normal_pen_shape_type: PenType,
         pub pen_line_style: PenLineStyle,
         #[serde(rename = "penWithMap")]
         pub pen_width_map: HashMap<u8, f32>,
@@ -117,37 +248,40 @@ mod json {
         pub shape_line_style: PenLineStyle,
     }
 
-    #[derive(Debug, Clone, Deserialize)]
+    #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
     #[serde(rename_all = "camelCase")]
     pub struct PenLineStyle {
         pub line_style: LineStyle,
     }
 
-    #[derive(Debug, Clone, Deserialize)]
+    #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
     #[serde(rename_all = "camelCase")]
     pub struct LineStyle {
         pub phase: f32,
         pub type_: u8,
     }
 
-    #[derive(Debug, Clone, Deserialize)]
+    #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
     #[serde(rename_all = "camelCase")]
     pub struct QuickPenList {
         pub quick_pens: Vec<QuickPen>,
         pub selected_id: PenId,
     }
 
-    #[derive(Debug, Clone, Deserialize)]
+    #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
     #[serde(rename_all = "camelCase")]
     pub struct QuickPen {
-        #[serde(deserialize_with = "crate::utils::deserialize_color")]
+        #[serde(
+            deserialize_with = "crate::utils::deserialize_color",
+            serialize_with = "crate::utils::serialize_color"
+        )]
         pub color: u32,
         pub id: PenId,
-        pub type_: u8,
+        pub type_: PenType,
         pub width: f32,
     }
 
-    #[derive(Debug, Clone, Deserialize)]
+    #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
     #[serde(rename_all = "camelCase")]
     pub struct CanvasState {
         pub canvas_expand_type: String,
@@ -157,7 +291,7 @@ mod json {
         pub zoom_info: ZoomInfo,
     }
 
-    #[derive(Debug, Clone, Deserialize)]
+    #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
     #[serde(rename_all = "camelCase")]
     pub struct PageInfo {
         pub current_layer_id: LayerId,
@@ -168,7 +302,7 @@ mod json {
         pub width: u32,
     }
 
-    #[derive(Debug, Clone, Deserialize)]
+    #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
     #[serde(rename_all = "camelCase")]
     pub struct ZoomInfo {
         pub fit_to_screen: bool,
@@ -179,7 +313,7 @@ mod json {
         pub viewport_scale: f32,
     }
 
-    #[derive(Debug, Clone, Deserialize)]
+    #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
     #[serde(rename_all = "camelCase")]
     pub struct ViewPortPos {
         pub is_empty: bool,
@@ -192,7 +326,7 @@ mod json {
         pub y: f32,
     }
 
-    #[derive(Debug, Clone, Deserialize)]
+    #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
     #[serde(rename_all = "camelCase")]
     pub struct BackgroundConfig {
         #[serde(rename = "bkGroundConfig")]
@@ -205,7 +339,7 @@ mod json {
         pub use_document_background: bool,
     }
 
-    #[derive(Debug, Clone, Deserialize)]
+    #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
     #[serde(rename_all = "camelCase")]
     pub struct BackgroundSettings {
         pub apply_all_page: bool,
@@ -214,7 +348,7 @@ mod json {
         pub scale_type: u8,
     }
 
-    #[derive(Debug, Clone, Deserialize)]
+    #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
     #[serde(rename_all = "camelCase")]
     pub struct DocBackground {
         pub cloud: bool,
@@ -226,7 +360,7 @@ mod json {
         pub width: f32,
     }
 
-    #[derive(Debug, Clone, Deserialize)]
+    #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
     #[serde(rename_all = "camelCase")]
     pub struct PageBackground {
         pub cloud: bool,
@@ -241,21 +375,21 @@ mod json {
         pub width: f32,
     }
 
-    #[derive(Debug, Clone, Deserialize)]
+    #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
     #[serde(rename_all = "camelCase")]
     pub struct DeviceInfo {
         pub device_name: String,
         pub size: DeviceDimensions,
     }
 
-    #[derive(Debug, Clone, Deserialize)]
+    #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
     #[serde(rename_all = "camelCase")]
     pub struct DeviceDimensions {
         pub width: f32,
         pub height: f32,
     }
 
-    #[derive(Debug, Clone, Deserialize)]
+    #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
     #[serde(rename_all = "camelCase")]
     pub struct PageNameList {
         pub page_name_list: Vec<PageUuid>,
@@ -267,10 +401,11 @@ mod protobuf {
 
     use crate::error::Result;
 
+    /// Notes are kept encoded, so each can be written back with the fields it was read with.
     #[derive(Clone, PartialEq, Message)]
     pub struct NoteTree {
-        #[prost(message, repeated, tag = "1")]
-        pub notes: Vec<NoteMetadata>,
+        #[prost(bytes = "vec", repeated, tag = "1")]
+        pub notes: Vec<Vec<u8>>,
     }
 
     impl NoteTree {
@@ -332,4 +467,121 @@ mod protobuf {
         #[prost(string, tag = "44")]
         pub detached_pages_json: String,
     }
+
+    impl NoteMetadata {
+        pub const TAGS: &[u32] = &[
+            1, 2, 3, 6, 8, 9, 10, 11, 12, 13, 14, 15, 16, 20, 21, 22, 23, 24, 31, 32, 37, 39, 40,
+            44,
+        ];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use prost::Message;
+
+    use super::*;
+    use crate::{color::Color, testing};
+
+    /// Note info of a created note, with fields and JSON keys the crate does not model.
+    fn note_info() -> Vec<u8> {
+        let note_info = testing::entry(&testing::entries(&testing::archive()), "note_info");
+        let note_tree = protobuf::NoteTree::decode(note_info.as_slice()).unwrap();
+        let notes = note_tree
+            .notes
+            .iter()
+            .map(|note| {
+                let mut note = protobuf::NoteMetadata::decode(note.as_slice()).unwrap();
+                note.pen_settings_json = unfix_pen_settings_json(&testing::add_unknown_key(
+                    &fix_pen_settings_json(&note.pen_settings_json),
+                ));
+                note.canvas_state_json = testing::add_unknown_key(&note.canvas_state_json);
+                note.background_config_json =
+                    testing::add_unknown_key(&note.background_config_json);
+                note.device_info_json = testing::add_unknown_key(&note.device_info_json);
+                testing::add_unknown_field(&note.encode_to_vec())
+            })
+            .collect();
+        protobuf::NoteTree { notes }.encode_to_vec()
+    }
+
+    #[test]
+    fn unchanged_note_tree_is_written_unchanged() {
+        let note_info = note_info();
+        let mut buffer = Vec::new();
+        NoteTree::read(note_info.as_slice())
+            .unwrap()
+            .write(&mut buffer)
+            .unwrap();
+        assert_eq!(buffer, note_info);
+    }
+
+    #[test]
+    fn edited_note_tree_keeps_unknown_data() {
+        let mut note_tree = NoteTree::read(note_info().as_slice()).unwrap();
+        let note_id = note_tree.note_ids()[0];
+        let metadata = note_tree.notes.get_mut(&note_id).unwrap();
+        let removed = metadata.active_pages.remove(0);
+        metadata.canvas_state.page_info_map.remove(&removed);
+        metadata.name = "Renamed".to_string();
+        metadata.device_info.device_name = "Other".to_string();
+        metadata.pen_settings.fill_color = Color::WHITE.argb();
+        let expected = metadata.clone();
+
+        let mut buffer = Vec::new();
+        note_tree.write(&mut buffer).unwrap();
+        let written = NoteTree::read(buffer.as_slice()).unwrap();
+        let metadata = &written.notes[&note_id];
+        assert_eq!(
+            NoteMetadata {
+                raw: expected.raw.clone(),
+                ..metadata.clone()
+            },
+            expected
+        );
+
+        assert!(testing::has_unknown_field(&metadata.raw));
+        let note = protobuf::NoteMetadata::decode(metadata.raw.as_slice()).unwrap();
+        assert!(testing::has_unknown_key(&fix_pen_settings_json(
+            &note.pen_settings_json
+        )));
+        assert!(testing::has_unknown_key(&note.canvas_state_json));
+        assert!(testing::has_unknown_key(&note.background_config_json));
+        assert!(testing::has_unknown_key(&note.device_info_json));
+    }
+
+    #[test]
+    fn pen_settings_keep_bare_numeric_keys() {
+        let mut note = protobuf::NoteMetadata::decode(
+            protobuf::NoteTree::decode(note_info().as_slice())
+                .unwrap()
+                .notes[0]
+                .as_slice(),
+        )
+        .unwrap();
+        let mut pen_settings = serde_json::from_str::<serde_json::Value>(&fix_pen_settings_json(
+            &note.pen_settings_json,
+        ))
+        .unwrap();
+        pen_settings["penWithMap"]["12"] = serde_json::json!(2.5);
+        pen_settings["label"] = serde_json::json!("at {12:30, 1:2}");
+        note.pen_settings_json = unfix_pen_settings_json(&pen_settings.to_string());
+        assert!(note.pen_settings_json.contains("12:2.5"));
+        assert!(!note.pen_settings_json.contains(r#""12""#));
+        assert!(
+            note.pen_settings_json
+                .contains(r#""label":"at {12:30, 1:2}""#)
+        );
+
+        let mut metadata = NoteMetadata::from_protobuf(&note.encode_to_vec()).unwrap();
+        assert_eq!(metadata.pen_settings.pen_width_map[&12], 2.5);
+        metadata.pen_settings.pen_width_map.insert(12, 3.5);
+        let written = metadata.to_protobuf().unwrap();
+
+        let written = protobuf::NoteMetadata::decode(written.as_slice()).unwrap();
+        assert_eq!(
+            written.pen_settings_json,
+            note.pen_settings_json.replace("12:2.5", "12:3.5")
+        );
+    }
 }
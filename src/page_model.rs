@@ -1,7 +1,12 @@
+use prost::Message;
+
 use crate::{
     id::PageUuid,
     json::{Dimensions, Layer},
-    utils::{convert_timestamp_to_datetime, parse_json},
+    utils::{
+        convert_datetime_to_timestamp, convert_timestamp_to_datetime, encode_protobuf, parse_json,
+        update_json,
+    },
 };
 
 #[derive(Debug, Clone)]
@@ -15,23 +20,38 @@ impl PageModelGroup {
         let page_models = container
             .page_model
             .iter()
-            .map(PageModel::from_protobuf)
+            .map(|page_model| PageModel::from_protobuf(page_model))
             .collect::<crate::error::Result<_>>()?;
         Ok(Self { page_models })
     }
+
+    pub fn write(&self, mut writer: impl std::io::Write) -> crate::error::Result {
+        let container = protobuf::PageModelContainer {
+            page_model: self
+                .page_models
+                .iter()
+                .map(PageModel::to_protobuf)
+                .collect::<crate::error::Result<_>>()?,
+        };
+        writer.write_all(&container.encode_to_vec())?;
+        Ok(())
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct PageModel {
     pub page_id: PageUuid,
     pub layers: Vec<Layer>,
     pub created: chrono::DateTime<chrono::Utc>,
     pub modified: chrono::DateTime<chrono::Utc>,
     pub dimensions: Dimensions,
+    /// Encoded message the page model was read from.
+    pub(crate) raw: Vec<u8>,
 }
 
 impl PageModel {
-    pub fn from_protobuf(page_model: &protobuf::PageModel) -> crate::error::Result<Self> {
+    pub fn from_protobuf(raw: &[u8]) -> crate::error::Result<Self> {
+        let page_model = protobuf::PageModel::decode(raw)?;
         let page_model_layers: json::PageModelLayers = parse_json(&page_model.layers_json)?;
         Ok(Self {
             page_id: PageUuid::from_str(&page_model.page_uuid)?,
@@ -39,16 +59,37 @@ impl PageModel {
             created: convert_timestamp_to_datetime(page_model.created)?,
             modified: convert_timestamp_to_datetime(page_model.modified)?,
             dimensions: parse_json(&page_model.dimensions_json)?,
+            raw: raw.to_vec(),
         })
     }
+
+    pub fn to_protobuf(&self) -> crate::error::Result<Vec<u8>> {
+        if Self::from_protobuf(&self.raw).is_ok_and(|original| original == *self) {
+            return Ok(self.raw.clone());
+        }
+
+        let mut page_model = protobuf::PageModel::decode(self.raw.as_slice())?;
+        page_model.page_uuid = self.page_id.to_string_like(&page_model.page_uuid);
+        page_model.layers_json = update_json(
+            &page_model.layers_json,
+            &json::PageModelLayers {
+                layer_list: self.layers.clone(),
+            },
+        )?;
+        page_model.created = convert_datetime_to_timestamp(&self.created);
+        page_model.modified = convert_datetime_to_timestamp(&self.modified);
+        page_model.dimensions_json = update_json(&page_model.dimensions_json, &self.dimensions)?;
+
+        encode_protobuf(&page_model, &self.raw, protobuf::PageModel::TAGS)
+    }
 }
 
 mod json {
-    use serde::Deserialize;
+    use serde::{Deserialize, Serialize};
 
     use crate::json::Layer;
 
-    #[derive(Debug, Clone, Deserialize)]
+    #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
     #[serde(rename_all = "camelCase")]
     pub struct PageModelLayers {
         pub layer_list: Vec<Layer>,
@@ -60,8 +101,8 @@ mod protobuf {
 
     #[derive(Clone, PartialEq, Message)]
     pub struct PageModelContainer {
-        #[prost(message, repeated, tag = "1")]
-        pub page_model: Vec<PageModel>,
+        #[prost(bytes = "vec", repeated, tag = "1")]
+        pub page_model: Vec<Vec<u8>>,
     }
 
     impl PageModelContainer {
@@ -85,4 +126,74 @@ mod protobuf {
         #[prost(string, tag = "7")]
         pub dimensions_json: String,
     }
+
+    impl PageModel {
+        pub const TAGS: &[u32] = &[1, 2, 5, 6, 7];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use prost::Message;
+
+    use super::*;
+    use crate::{id::LayerId, testing};
+
+    /// Page model file of a created note, with fields and JSON keys the crate does not model.
+    fn page_model_file() -> Vec<u8> {
+        let file = testing::entry(&testing::entries(&testing::archive()), "/pageModel/pb/");
+        let container = protobuf::PageModelContainer::decode(file.as_slice()).unwrap();
+        let page_model = container
+            .page_model
+            .iter()
+            .map(|page_model| {
+                let mut page_model = protobuf::PageModel::decode(page_model.as_slice()).unwrap();
+                page_model.layers_json = testing::add_unknown_key(&page_model.layers_json);
+                page_model.dimensions_json = testing::add_unknown_key(&page_model.dimensions_json);
+                testing::add_unknown_field(&page_model.encode_to_vec())
+            })
+            .collect();
+        protobuf::PageModelContainer { page_model }.encode_to_vec()
+    }
+
+    #[test]
+    fn unchanged_page_model_is_written_unchanged() {
+        let file = page_model_file();
+        let mut buffer = Vec::new();
+        PageModelGroup::read(file.as_slice())
+            .unwrap()
+            .write(&mut buffer)
+            .unwrap();
+        assert_eq!(buffer, file);
+    }
+
+    #[test]
+    fn edited_page_model_keeps_unknown_data() {
+        let mut group = PageModelGroup::read(page_model_file().as_slice()).unwrap();
+        let page_model = &mut group.page_models[0];
+        page_model.layers.push(Layer {
+            id: LayerId::new(1),
+            lock: true,
+            show: false,
+        });
+        page_model.dimensions.right += 10.0;
+        let expected = page_model.clone();
+
+        let mut buffer = Vec::new();
+        group.write(&mut buffer).unwrap();
+        let written = PageModelGroup::read(buffer.as_slice()).unwrap();
+        let page_model = &written.page_models[0];
+        assert_eq!(
+            PageModel {
+                raw: expected.raw.clone(),
+                ..page_model.clone()
+            },
+            expected
+        );
+
+        assert!(testing::has_unknown_field(&page_model.raw));
+        let encoded = protobuf::PageModel::decode(page_model.raw.as_slice()).unwrap();
+        assert!(testing::has_unknown_key(&encoded.layers_json));
+        assert!(testing::has_unknown_key(&encoded.dimensions_json));
+    }
 }
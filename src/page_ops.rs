@@ -0,0 +1,1019 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::{Read, Seek},
+};
+
+use chrono::Utc;
+
+use crate::{
+    Note, NoteFile,
+    container::Container,
+    error::{Error, Result},
+    id::{
+        NoteUuid, PageModelUuid, PageUuid, PointsUuid, ShapeGroupUuid, StrokeUuid, VirtualPageUuid,
+    },
+    note_tree::NoteMetadata,
+    page_model::PageModelGroup,
+    points::PointsFile,
+    shape::ShapeGroup,
+    virtual_page::VirtualPage,
+};
+
+/// Files of the archive belonging to a page, by absolute path.
+struct PageFiles {
+    page_models: Vec<(String, PageModelGroup)>,
+    virtual_pages: Vec<(String, VirtualPage)>,
+    shape_groups: Vec<(String, ShapeGroup)>,
+    points_files: Vec<(String, PointsFile)>,
+}
+
+impl PageFiles {
+    fn find<R: Read + Seek>(
+        container: &mut Container<R>,
+        note_id: &NoteUuid,
+        page_id: &PageUuid,
+    ) -> Result<Self> {
+        let note_id = note_id.to_simple_string();
+        let page_id_str = page_id.to_simple_string();
+
+        let mut page_models = Vec::new();
+        for path in container.list_directory(&format!("{}/pageModel/pb", note_id)) {
+            let group =
+                container.get_file_absolute(&path, |reader| PageModelGroup::read(reader))?;
+            if group
+                .page_models
+                .iter()
+                .any(|model| model.page_id == *page_id)
+            {
+                page_models.push((path, group));
+            }
+        }
+
+        let mut virtual_pages = Vec::new();
+        for path in container.list_directory(&format!("{}/virtual/page/pb", note_id)) {
+            let virtual_page =
+                container.get_file_absolute(&path, |reader| VirtualPage::read(reader))?;
+            if virtual_page.page_id == *page_id {
+                virtual_pages.push((path, virtual_page));
+            }
+        }
+
+        let mut shape_groups = Vec::new();
+        for path in container.list_directory(&format!("{}/shape/{}#", note_id, page_id_str)) {
+            let shape_group =
+                container.get_file_absolute(&path, |reader| ShapeGroup::read(reader))?;
+            shape_groups.push((path, shape_group));
+        }
+
+        let mut points_files = Vec::new();
+        for path in container.list_directory(&format!("{}/point/{}/", note_id, page_id_str)) {
+            let points_file = container.get_file_absolute(&path, |reader| {
+                let mut buffer = Vec::new();
+                reader.read_to_end(&mut buffer)?;
+                PointsFile::read(std::io::Cursor::new(buffer))
+            })?;
+            points_files.push((path, points_file));
+        }
+
+        Ok(Self {
+            page_models,
+            virtual_pages,
+            shape_groups,
+            points_files,
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.page_models.is_empty()
+            && self.virtual_pages.is_empty()
+            && self.shape_groups.is_empty()
+            && self.points_files.is_empty()
+    }
+
+    /// Removes the files of the page. Page model files shared with other pages are rewritten
+    /// without it.
+    fn remove<R: Read + Seek>(self, container: &Container<R>, page_id: &PageUuid) -> Result {
+        for (path, mut group) in self.page_models {
+            group.page_models.retain(|model| model.page_id != *page_id);
+            if group.page_models.is_empty() {
+                container.remove_file(&path);
+            } else {
+                let mut buffer = Vec::new();
+                group.write(&mut buffer)?;
+                container.put_file(path, buffer);
+            }
+        }
+        let paths = self
+            .virtual_pages
+            .into_iter()
+            .map(|(path, _)| path)
+            .chain(self.shape_groups.into_iter().map(|(path, _)| path))
+            .chain(self.points_files.into_iter().map(|(path, _)| path));
+        for path in paths {
+            container.remove_file(&path);
+        }
+        Ok(())
+    }
+}
+
+/// Hands out the UUIDs of a copied page, either fresh ones or the original ones.
+struct IdMap {
+    fresh: bool,
+    strokes: HashMap<StrokeUuid, StrokeUuid>,
+    points: HashMap<PointsUuid, PointsUuid>,
+}
+
+impl IdMap {
+    fn stroke(&mut self, stroke_id: &StrokeUuid) -> StrokeUuid {
+        let fresh = self.fresh;
+        *self.strokes.entry(*stroke_id).or_insert_with(|| {
+            if fresh {
+                StrokeUuid::new_random()
+            } else {
+                *stroke_id
+            }
+        })
+    }
+
+    fn points(&mut self, points_id: &PointsUuid) -> PointsUuid {
+        let fresh = self.fresh;
+        *self.points.entry(*points_id).or_insert_with(|| {
+            if fresh {
+                PointsUuid::new_random()
+            } else {
+                *points_id
+            }
+        })
+    }
+}
+
+/// Replaces the first two `#` separated parts of a file name, keeping the rest.
+fn rename(file_name: &str, first: &str, second: &str) -> String {
+    let mut parts = file_name.splitn(3, '#');
+    parts.next();
+    parts.next();
+    match parts.next() {
+        Some(rest) => format!("{}#{}#{}", first, second, rest),
+        None => format!("{}#{}", first, second),
+    }
+}
+
+fn file_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// Returns the shape group ID and timestamp parts of a shape group file name.
+fn shape_revision(path: &str) -> Option<(&str, u64)> {
+    let mut parts = file_name(path).trim_end_matches(".zip").split('#');
+    parts.next();
+    let shape_group_id = parts.next()?;
+    let timestamp = parts.next()?.parse().ok()?;
+    Some((shape_group_id, timestamp))
+}
+
+fn touch(metadata: &mut NoteMetadata) {
+    let now = Utc::now();
+    metadata.modified =
+        chrono::DateTime::from_timestamp_millis(now.timestamp_millis()).unwrap_or(now);
+}
+
+impl<R: Read + Seek> NoteFile<R> {
+    fn note_metadata_mut(&mut self, note_id: &NoteUuid) -> Result<&mut NoteMetadata> {
+        self.note_tree
+            .notes
+            .get_mut(note_id)
+            .ok_or(Error::NoteNotFound(*note_id))
+    }
+
+    /// Changes the order of the active pages of a note. `order` must contain every active page
+    /// exactly once.
+    pub fn reorder_pages(&mut self, note_id: &NoteUuid, order: &[PageUuid]) -> Result {
+        let metadata = self.note_metadata_mut(note_id)?;
+
+        let mut expected = metadata
+            .active_pages
+            .iter()
+            .map(|page_id| page_id.to_simple_string())
+            .collect::<Vec<_>>();
+        let mut given = order
+            .iter()
+            .map(|page_id| page_id.to_simple_string())
+            .collect::<Vec<_>>();
+        expected.sort();
+        given.sort();
+        if expected != given {
+            return Err(Error::InvalidPageOrder);
+        }
+
+        metadata.active_pages = order.to_vec();
+        touch(metadata);
+        Ok(())
+    }
+
+    /// Deletes a page of a note together with its page model, virtual page, shape groups and
+    /// points files.
+    pub fn delete_page(&mut self, note_id: &NoteUuid, page_id: &PageUuid) -> Result {
+        let metadata = self.note_metadata_mut(note_id)?;
+        let listed = metadata.active_pages.contains(page_id)
+            || metadata.reserved_pages.contains(page_id)
+            || metadata.detached_pages.contains(page_id);
+        let files = PageFiles::find(&mut self.container, note_id, page_id)?;
+        if !listed && files.is_empty() {
+            return Err(Error::PageNotFound(*page_id));
+        }
+        files.remove(&self.container, page_id)?;
+
+        let metadata = self.note_metadata_mut(note_id)?;
+        metadata.active_pages.retain(|id| id != page_id);
+        metadata.reserved_pages.retain(|id| id != page_id);
+        metadata.detached_pages.retain(|id| id != page_id);
+        metadata.canvas_state.page_info_map.remove(page_id);
+        metadata.background_config.page_backgrounds.remove(page_id);
+        touch(metadata);
+        Ok(())
+    }
+
+    /// Duplicates a page within its note, with fresh UUIDs for the page, its shape groups,
+    /// strokes and points files. The copy is placed after the original and its ID returned.
+    pub fn duplicate_page(&mut self, note_id: &NoteUuid, page_id: &PageUuid) -> Result<PageUuid> {
+        let mut container = self.container.clone();
+        let metadata = self.note_metadata_mut(note_id)?.clone();
+        let copy_id = self.copy_page_from(&mut container, &metadata, page_id, note_id, true)?;
+
+        let metadata = self.note_metadata_mut(note_id)?;
+        metadata.active_pages.retain(|id| *id != copy_id);
+        let position = metadata
+            .active_pages
+            .iter()
+            .position(|id| id == page_id)
+            .map(|position| position + 1)
+            .unwrap_or(metadata.active_pages.len());
+        metadata.active_pages.insert(position, copy_id);
+        Ok(copy_id)
+    }
+
+    /// Moves a page to another note of the file, keeping its UUIDs.
+    pub fn move_page(&mut self, from: &NoteUuid, page_id: &PageUuid, to: &NoteUuid) -> Result {
+        if from == to {
+            return Ok(());
+        }
+        let mut container = self.container.clone();
+        let metadata = self.note_metadata_mut(from)?.clone();
+        self.copy_page_from(&mut container, &metadata, page_id, to, false)?;
+        self.delete_page(from, page_id)
+    }
+
+    /// Copies a page of a note, which may belong to another file, to the end of a note of this
+    /// file. The copy gets fresh UUIDs and its ID is returned.
+    pub fn copy_page<S: Read + Seek>(
+        &mut self,
+        source: &Note<S>,
+        page_id: &PageUuid,
+        to: &NoteUuid,
+    ) -> Result<PageUuid> {
+        let mut container = source.container.clone();
+        self.copy_page_from(&mut container, &source.metadata, page_id, to, true)
+    }
+
+    fn copy_page_from<S: Read + Seek>(
+        &mut self,
+        source: &mut Container<S>,
+        source_metadata: &NoteMetadata,
+        page_id: &PageUuid,
+        to: &NoteUuid,
+        fresh: bool,
+    ) -> Result<PageUuid> {
+        self.note_metadata_mut(to)?;
+        let files = PageFiles::find(source, &source_metadata.note_id, page_id)?;
+        if files.page_models.is_empty() {
+            return Err(Error::PageNotFound(*page_id));
+        }
+
+        let new_page_id = if fresh {
+            PageUuid::new_random()
+        } else {
+            *page_id
+        };
+        let mut ids = IdMap {
+            fresh,
+            strokes: HashMap::new(),
+            points: HashMap::new(),
+        };
+        let root = self.container.get_file_path(&to.to_simple_string());
+        let page_dir = new_page_id.to_simple_string();
+
+        for (_, mut group) in files.page_models {
+            group.page_models.retain(|model| model.page_id == *page_id);
+            for model in &mut group.page_models {
+                model.page_id = new_page_id;
+            }
+            let mut buffer = Vec::new();
+            group.write(&mut buffer)?;
+            self.container.put_file(
+                format!(
+                    "{}/pageModel/pb/{}",
+                    root,
+                    PageModelUuid::new_random().to_simple_string()
+                ),
+                buffer,
+            );
+        }
+
+        for (_, mut virtual_page) in files.virtual_pages {
+            virtual_page.page_id = new_page_id;
+            let mut buffer = Vec::new();
+            virtual_page.write(&mut buffer)?;
+            self.container.put_file(
+                format!(
+                    "{}/virtual/page/pb/{}",
+                    root,
+                    VirtualPageUuid::new_random().to_simple_string()
+                ),
+                buffer,
+            );
+        }
+
+        for (path, mut shape_group) in files.shape_groups {
+            let mut shape_group_ids = HashMap::new();
+            for shape in &mut shape_group.shapes {
+                shape.stroke_id = ids.stroke(&shape.stroke_id);
+                shape.points_id = shape.points_id.map(|points_id| ids.points(&points_id));
+                shape.shape_group_id =
+                    *shape_group_ids
+                        .entry(shape.shape_group_id)
+                        .or_insert_with(|| {
+                            if fresh {
+                                ShapeGroupUuid::new_random()
+                            } else {
+                                shape.shape_group_id
+                            }
+                        });
+            }
+
+            let name = file_name(&path);
+            let original = name.split('#').nth(1).unwrap_or_default();
+            let shape_group_id = ShapeGroupUuid::from_str(original)?;
+            let shape_group_id = *shape_group_ids.entry(shape_group_id).or_insert_with(|| {
+                if fresh {
+                    ShapeGroupUuid::new_random()
+                } else {
+                    shape_group_id
+                }
+            });
+            let shape_group_id = shape_group_id.to_string_like(original);
+            let shape_group_name = rename(name, &page_dir, &shape_group_id);
+            if shape_group.entry_name.contains('#') {
+                shape_group.entry_name =
+                    rename(&shape_group.entry_name, &page_dir, &shape_group_id);
+            }
+
+            let mut buffer = Vec::new();
+            shape_group.write(&mut buffer)?;
+            self.container
+                .put_file(format!("{}/shape/{}", root, shape_group_name), buffer);
+        }
+
+        for (path, mut points_file) in files.points_files {
+            let header = points_file.header_mut();
+            header.page_id = new_page_id;
+            header.points_id = ids.points(&header.points_id);
+            let points_id = header.points_id;
+            for stroke_id in points_file.stroke_ids().copied().collect::<Vec<_>>() {
+                let new_stroke_id = ids.stroke(&stroke_id);
+                points_file.rename_stroke(&stroke_id, new_stroke_id);
+            }
+
+            let name = file_name(&path);
+            let points_name = match name.split('#').nth(1) {
+                Some(original) => rename(name, &page_dir, &points_id.to_string_like(original)),
+                None => name.to_string(),
+            };
+            let mut buffer = Vec::new();
+            points_file.write(&mut buffer)?;
+            self.container.put_file(
+                format!("{}/point/{}/{}", root, page_dir, points_name),
+                buffer,
+            );
+        }
+
+        let page_info = source_metadata
+            .canvas_state
+            .page_info_map
+            .get(page_id)
+            .cloned();
+        let page_background = source_metadata
+            .background_config
+            .page_backgrounds
+            .get(page_id)
+            .cloned();
+        let metadata = self.note_metadata_mut(to)?;
+        if !metadata.active_pages.contains(&new_page_id) {
+            metadata.active_pages.push(new_page_id);
+        }
+        if let Some(page_info) = page_info {
+            metadata
+                .canvas_state
+                .page_info_map
+                .insert(new_page_id, page_info);
+        }
+        if let Some(page_background) = page_background {
+            metadata
+                .background_config
+                .page_backgrounds
+                .insert(new_page_id, page_background);
+        }
+        touch(metadata);
+
+        Ok(new_page_id)
+    }
+}
+
+impl<R: Read + Seek> NoteFile<R> {
+    /// Compacts a note with [`Note::compact`] and keeps its updated page lists. Returns the
+    /// number of bytes reclaimed.
+    pub fn compact(&mut self, note_id: &NoteUuid) -> Result<u64> {
+        let mut note = self
+            .get_note(note_id)
+            .ok_or(Error::NoteNotFound(*note_id))?;
+        let reclaimed = note.compact()?;
+        *self.note_metadata_mut(note_id)? = note.metadata;
+        Ok(reclaimed)
+    }
+}
+
+impl<R: Read + Seek> Note<R> {
+    /// Removes the files of pages that are not active, shape group files superseded by a newer
+    /// revision and points files not referenced by any shape. Returns the number of bytes the
+    /// stored files of the note shrank by.
+    ///
+    /// The files are removed from the archive shared with the [`NoteFile`], but the compacted
+    /// pages only from the reserved and detached page lists of this note; use
+    /// [`NoteFile::compact`] to keep them.
+    pub fn compact(&mut self) -> Result<u64> {
+        let note_id = self.metadata.note_id;
+        let note_dir = note_id.to_simple_string();
+        let size_before = self.size();
+
+        let active = self
+            .metadata
+            .active_pages
+            .iter()
+            .copied()
+            .collect::<HashSet<_>>();
+        let mut unreferenced = self
+            .metadata
+            .reserved_pages
+            .iter()
+            .chain(&self.metadata.detached_pages)
+            .copied()
+            .collect::<HashSet<_>>();
+        unreferenced.extend(
+            self.page_models()?
+                .values()
+                .flat_map(|group| group.page_models.iter().map(|model| model.page_id)),
+        );
+        unreferenced.extend(
+            self.virtual_pages()?
+                .values()
+                .map(|virtual_page| virtual_page.page_id),
+        );
+        for path in self
+            .container
+            .list_directory(&format!("{}/shape/", note_dir))
+        {
+            if let Some(page_id) = file_name(&path).split('#').next() {
+                unreferenced.extend(PageUuid::from_str(page_id).ok());
+            }
+        }
+        for path in self
+            .container
+            .list_directory(&format!("{}/point/", note_dir))
+        {
+            if let Some(page_id) = path.rsplit('/').nth(1) {
+                unreferenced.extend(PageUuid::from_str(page_id).ok());
+            }
+        }
+        unreferenced.retain(|page_id| !active.contains(page_id));
+
+        for page_id in &unreferenced {
+            PageFiles::find(&mut self.container, &note_id, page_id)?
+                .remove(&self.container, page_id)?;
+            self.metadata.canvas_state.page_info_map.remove(page_id);
+            self.metadata
+                .background_config
+                .page_backgrounds
+                .remove(page_id);
+        }
+        self.metadata
+            .reserved_pages
+            .retain(|page_id| !unreferenced.contains(page_id));
+        self.metadata
+            .detached_pages
+            .retain(|page_id| !unreferenced.contains(page_id));
+
+        for page_id in &self.metadata.active_pages {
+            let page_id_str = page_id.to_simple_string();
+
+            let mut latest = HashMap::new();
+            let shape_paths = self
+                .container
+                .list_directory(&format!("{}/shape/{}#", note_dir, page_id_str));
+            for path in &shape_paths {
+                if let Some((shape_group_id, timestamp)) = shape_revision(path) {
+                    let newest = latest.entry(shape_group_id).or_insert(timestamp);
+                    *newest = (*newest).max(timestamp);
+                }
+            }
+            let mut points_ids = HashSet::new();
+            for path in &shape_paths {
+                match shape_revision(path) {
+                    Some((shape_group_id, timestamp)) if latest[shape_group_id] > timestamp => {
+                        self.container.remove_file(path);
+                    }
+                    _ => {
+                        let shape_group = self
+                            .container
+                            .get_file_absolute(path, |reader| ShapeGroup::read(reader))?;
+                        points_ids.extend(
+                            shape_group
+                                .shapes
+                                .iter()
+                                .filter_map(|shape| shape.points_id),
+                        );
+                    }
+                }
+            }
+
+            for path in self
+                .container
+                .list_directory(&format!("{}/point/{}/", note_dir, page_id_str))
+            {
+                let points_id = file_name(&path)
+                    .split('#')
+                    .nth(1)
+                    .and_then(|points_id| PointsUuid::from_str(points_id).ok());
+                if points_id.is_some_and(|points_id| !points_ids.contains(&points_id)) {
+                    self.container.remove_file(&path);
+                }
+            }
+        }
+
+        self.page_models = None;
+        self.virtual_pages = None;
+
+        // Rewritten page model files count too, so compare the sizes instead of summing up the
+        // removed files
+        let reclaimed = size_before.saturating_sub(self.size());
+        if reclaimed > 0 || !unreferenced.is_empty() {
+            touch(&mut self.metadata);
+        }
+        Ok(reclaimed)
+    }
+
+    /// Returns the stored size of the files of the note.
+    fn size(&self) -> u64 {
+        let note_dir = self.metadata.note_id.to_simple_string();
+        self.container
+            .list_directory(&format!("{}/", note_dir))
+            .iter()
+            .filter_map(|path| self.container.file_size(path))
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{
+        brush::PenType,
+        builder::{NoteBuilder, PageBuilder},
+        note_tree::PageBackground,
+        points::Stroke,
+        shape::Shape,
+        testing,
+    };
+
+    type File = NoteFile<Cursor<Vec<u8>>>;
+
+    /// A file with the test note and a note with one page, each page with its own background.
+    fn file() -> File {
+        let notes = [
+            testing::note(),
+            NoteBuilder::new("Other")
+                .page(PageBuilder::new().stroke(testing::stroke(PenType::Pencil, 400.0))),
+        ];
+        let mut buffer = Cursor::new(Vec::new());
+        NoteBuilder::write_multi(&notes, &mut buffer).unwrap();
+        let mut file = NoteFile::read(Cursor::new(buffer.into_inner())).unwrap();
+
+        for note_id in file.list_notes().into_keys() {
+            let metadata = file.note_metadata_mut(&note_id).unwrap();
+            for (index, page_id) in metadata.active_pages.clone().into_iter().enumerate() {
+                metadata.background_config.page_backgrounds.insert(
+                    page_id,
+                    PageBackground {
+                        cloud: false,
+                        global: false,
+                        height: 2480.0,
+                        res_id: String::new(),
+                        res_index: index as u32,
+                        title: String::new(),
+                        type_: 0,
+                        value: String::new(),
+                        visible: true,
+                        width: 1860.0,
+                    },
+                );
+            }
+        }
+        file
+    }
+
+    /// Writes the file and reads it back.
+    fn reopen(file: &File) -> File {
+        let mut buffer = Cursor::new(Vec::new());
+        file.write(&mut buffer).unwrap();
+        NoteFile::read(Cursor::new(buffer.into_inner())).unwrap()
+    }
+
+    fn note_id(file: &File, name: &str) -> NoteUuid {
+        file.list_notes()
+            .into_iter()
+            .find(|(_, note_name)| note_name == name)
+            .map(|(note_id, _)| note_id)
+            .unwrap()
+    }
+
+    fn pages(file: &File, note_id: &NoteUuid) -> Vec<PageUuid> {
+        file.get_note(note_id).unwrap().active_pages().to_vec()
+    }
+
+    fn metadata(file: &File, note_id: &NoteUuid) -> NoteMetadata {
+        file.note_tree.get(note_id).unwrap().clone()
+    }
+
+    fn read_file(file: &mut File, path: &str) -> Vec<u8> {
+        file.container
+            .get_file_absolute(path, |reader| {
+                let mut buffer = Vec::new();
+                reader.read_to_end(&mut buffer)?;
+                Ok(buffer)
+            })
+            .unwrap()
+    }
+
+    fn files(file: &mut File, note_id: &NoteUuid, page_id: &PageUuid) -> PageFiles {
+        PageFiles::find(&mut file.container, note_id, page_id).unwrap()
+    }
+
+    /// Asserts that a page has exactly one of each of its files.
+    fn assert_complete(file: &mut File, note_id: &NoteUuid, page_id: &PageUuid) {
+        let files = files(file, note_id, page_id);
+        assert_eq!(files.page_models.len(), 1);
+        assert_eq!(files.virtual_pages.len(), 1);
+        assert_eq!(files.shape_groups.len(), 1);
+        assert_eq!(files.points_files.len(), 1);
+    }
+
+    fn strokes(file: &File, note_id: &NoteUuid, page_id: &PageUuid) -> Vec<(Shape, Stroke)> {
+        let mut note = file.get_note(note_id).unwrap();
+        note.get_page(page_id).unwrap().strokes().unwrap()
+    }
+
+    /// Asserts that the copied strokes hold the same points under fresh UUIDs, and that no UUID
+    /// of the note is used twice.
+    fn assert_fresh_copy(
+        file: &File,
+        note_id: &NoteUuid,
+        original: &[(Shape, Stroke)],
+        copy: &[(Shape, Stroke)],
+    ) {
+        assert_eq!(copy.len(), original.len());
+        for ((shape, stroke), (original_shape, original_stroke)) in copy.iter().zip(original) {
+            assert_eq!(stroke, original_stroke);
+            assert_ne!(shape.stroke_id, original_shape.stroke_id);
+            assert_ne!(shape.points_id, original_shape.points_id);
+            assert_ne!(shape.shape_group_id, original_shape.shape_group_id);
+        }
+
+        let shapes = pages(file, note_id)
+            .iter()
+            .flat_map(|page_id| strokes(file, note_id, page_id))
+            .map(|(shape, _)| shape)
+            .collect::<Vec<_>>();
+        let stroke_ids = shapes
+            .iter()
+            .map(|shape| shape.stroke_id)
+            .collect::<HashSet<_>>();
+        assert_eq!(stroke_ids.len(), shapes.len());
+        let page_groups = pages(file, note_id)
+            .iter()
+            .filter_map(|page_id| {
+                let shapes = strokes(file, note_id, page_id);
+                shapes
+                    .first()
+                    .map(|(shape, _)| (shape.points_id, shape.shape_group_id))
+            })
+            .collect::<Vec<_>>();
+        let points_ids = page_groups
+            .iter()
+            .map(|(points_id, _)| *points_id)
+            .collect::<HashSet<_>>();
+        let shape_group_ids = page_groups
+            .iter()
+            .map(|(_, id)| *id)
+            .collect::<HashSet<_>>();
+        assert_eq!(points_ids.len(), page_groups.len());
+        assert_eq!(shape_group_ids.len(), page_groups.len());
+    }
+
+    /// Returns the files of the [`testing::device_samples`], labelled with their paths.
+    fn device_samples() -> Vec<(String, File)> {
+        testing::device_samples()
+            .into_iter()
+            .map(|(path, archive)| {
+                let file = NoteFile::read(Cursor::new(archive)).unwrap();
+                (path.display().to_string(), file)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reorder_pages_changes_the_active_pages() {
+        let mut file = file();
+        let note_id = note_id(&file, "Test");
+        let mut order = pages(&file, &note_id);
+        order.rotate_left(1);
+
+        assert!(matches!(
+            file.reorder_pages(&note_id, &order[1..]),
+            Err(Error::InvalidPageOrder)
+        ));
+        file.reorder_pages(&note_id, &order).unwrap();
+
+        let mut file = reopen(&file);
+        assert_eq!(pages(&file, &note_id), order);
+        for page_id in &order {
+            assert_complete(&mut file, &note_id, page_id);
+        }
+    }
+
+    #[test]
+    fn delete_page_removes_its_files() {
+        let mut file = file();
+        let note_id = note_id(&file, "Test");
+        let page_ids = pages(&file, &note_id);
+        let deleted = page_ids[0];
+        file.delete_page(&note_id, &deleted).unwrap();
+
+        let mut file = reopen(&file);
+        assert_eq!(pages(&file, &note_id), page_ids[1..]);
+        assert!(files(&mut file, &note_id, &deleted).is_empty());
+        let metadata = metadata(&file, &note_id);
+        assert!(!metadata.canvas_state.page_info_map.contains_key(&deleted));
+        assert!(
+            !metadata
+                .background_config
+                .page_backgrounds
+                .contains_key(&deleted)
+        );
+        for page_id in &page_ids[1..] {
+            assert_complete(&mut file, &note_id, page_id);
+            assert!(
+                metadata
+                    .background_config
+                    .page_backgrounds
+                    .contains_key(page_id)
+            );
+        }
+        assert_eq!(strokes(&file, &note_id, &page_ids[1]).len(), 1);
+
+        assert!(matches!(
+            file.delete_page(&note_id, &deleted),
+            Err(Error::PageNotFound(page_id)) if page_id == deleted
+        ));
+    }
+
+    #[test]
+    fn duplicate_page_copies_it_with_fresh_ids() {
+        let mut file = file();
+        let note_id = note_id(&file, "Test");
+        let page_ids = pages(&file, &note_id);
+        let copy_id = file.duplicate_page(&note_id, &page_ids[0]).unwrap();
+        assert!(!page_ids.contains(&copy_id));
+
+        let mut file = reopen(&file);
+        assert_eq!(
+            pages(&file, &note_id),
+            [page_ids[0], copy_id, page_ids[1], page_ids[2]]
+        );
+        assert_complete(&mut file, &note_id, &page_ids[0]);
+        assert_complete(&mut file, &note_id, &copy_id);
+        let metadata = metadata(&file, &note_id);
+        assert_eq!(
+            metadata.canvas_state.page_info_map.get(&copy_id),
+            metadata.canvas_state.page_info_map.get(&page_ids[0])
+        );
+        assert_eq!(
+            metadata.background_config.page_backgrounds.get(&copy_id),
+            metadata
+                .background_config
+                .page_backgrounds
+                .get(&page_ids[0])
+        );
+
+        let original = strokes(&file, &note_id, &page_ids[0]);
+        let copy = strokes(&file, &note_id, &copy_id);
+        assert_fresh_copy(&file, &note_id, &original, &copy);
+    }
+
+    #[test]
+    fn move_page_keeps_its_ids() {
+        let mut file = file();
+        let from = note_id(&file, "Test");
+        let to = note_id(&file, "Other");
+        let page_ids = pages(&file, &from);
+        let target_pages = pages(&file, &to);
+        let moved = page_ids[0];
+        let original = strokes(&file, &from, &moved);
+        let page_info = metadata(&file, &from).canvas_state.page_info_map[&moved].clone();
+        let background = metadata(&file, &from).background_config.page_backgrounds[&moved].clone();
+        file.move_page(&from, &moved, &to).unwrap();
+
+        let mut file = reopen(&file);
+        assert_eq!(pages(&file, &from), page_ids[1..]);
+        assert!(files(&mut file, &from, &moved).is_empty());
+        assert_eq!(pages(&file, &to), [target_pages[0], moved]);
+        assert_complete(&mut file, &to, &moved);
+        assert_eq!(strokes(&file, &to, &moved), original);
+
+        let source = metadata(&file, &from);
+        assert!(!source.canvas_state.page_info_map.contains_key(&moved));
+        assert!(
+            !source
+                .background_config
+                .page_backgrounds
+                .contains_key(&moved)
+        );
+        let target = metadata(&file, &to);
+        assert_eq!(target.canvas_state.page_info_map[&moved], page_info);
+        assert_eq!(
+            target.background_config.page_backgrounds[&moved],
+            background
+        );
+    }
+
+    #[test]
+    fn copy_page_from_another_file_uses_fresh_ids() {
+        let source = file();
+        let source_note_id = note_id(&source, "Test");
+        let page_id = pages(&source, &source_note_id)[1];
+        let original = strokes(&source, &source_note_id, &page_id);
+
+        let mut file = file();
+        let to = note_id(&file, "Other");
+        let target_pages = pages(&file, &to);
+        let source_note = source.get_note(&source_note_id).unwrap();
+        let copy_id = file.copy_page(&source_note, &page_id, &to).unwrap();
+        assert!(matches!(
+            file.copy_page(&source_note, &PageUuid::new_random(), &to),
+            Err(Error::PageNotFound(_))
+        ));
+
+        let mut file = reopen(&file);
+        assert_eq!(pages(&file, &to), [target_pages[0], copy_id]);
+        assert_complete(&mut file, &to, &copy_id);
+        let copy = strokes(&file, &to, &copy_id);
+        assert_fresh_copy(&file, &to, &original, &copy);
+        let target = metadata(&file, &to);
+        assert!(target.canvas_state.page_info_map.contains_key(&copy_id));
+        assert!(
+            target
+                .background_config
+                .page_backgrounds
+                .contains_key(&copy_id)
+        );
+    }
+
+    #[test]
+    fn compact_removes_unused_files() {
+        let mut file = file();
+        let note_id = note_id(&file, "Test");
+        let page_ids = pages(&file, &note_id);
+        let note_dir = note_id.to_simple_string();
+
+        // The first page is also reserved and stays, the last one is detached and goes
+        let page_lists = file.note_metadata_mut(&note_id).unwrap();
+        page_lists.active_pages.pop();
+        page_lists.reserved_pages.push(page_ids[0]);
+        page_lists.detached_pages.push(page_ids[2]);
+
+        // An older revision of the shape group of the second page and a points file no shape
+        // refers to
+        let page_files = files(&mut file, &note_id, &page_ids[1]);
+        let (shape_path, _) = &page_files.shape_groups[0];
+        let (points_path, _) = &page_files.points_files[0];
+        let stale_path = format!("{}#1.zip", shape_path.rsplit_once('#').unwrap().0);
+        let shape_group = read_file(&mut file, shape_path);
+        file.container.put_file(stale_path.clone(), shape_group);
+        let page_dir = page_ids[1].to_simple_string();
+        let orphan_path = format!(
+            "{}/point/{}/{}#{}#points",
+            file.container.get_file_path(&note_dir),
+            page_dir,
+            page_dir,
+            PointsUuid::new_random().to_simple_string()
+        );
+        let points_file = read_file(&mut file, points_path);
+        file.container.put_file(orphan_path.clone(), points_file);
+
+        let size = |file: &File| file.get_note(&note_id).unwrap().size();
+        let size_before = size(&file);
+        let reclaimed = file.compact(&note_id).unwrap();
+        assert!(reclaimed > 0);
+        assert_eq!(reclaimed, size_before - size(&file));
+
+        let mut file = reopen(&file);
+        let paths = file.container.list_directory(&format!("{}/", note_dir));
+        assert!(!paths.contains(&stale_path));
+        assert!(!paths.contains(&orphan_path));
+        assert!(paths.contains(shape_path));
+        assert!(paths.contains(points_path));
+        assert!(files(&mut file, &note_id, &page_ids[2]).is_empty());
+
+        let metadata = metadata(&file, &note_id);
+        assert_eq!(metadata.active_pages, page_ids[..2]);
+        assert_eq!(metadata.reserved_pages, [page_ids[0]]);
+        assert!(metadata.detached_pages.is_empty());
+        assert!(
+            !metadata
+                .canvas_state
+                .page_info_map
+                .contains_key(&page_ids[2])
+        );
+        for page_id in &page_ids[..2] {
+            assert_complete(&mut file, &note_id, page_id);
+        }
+        assert_eq!(strokes(&file, &note_id, &page_ids[1]).len(), 1);
+    }
+
+    #[test]
+    fn device_sample_pages_are_duplicated_and_deleted() {
+        for (label, mut file) in device_samples() {
+            for note_id in file.list_notes().into_keys() {
+                let page_ids = pages(&file, &note_id);
+                let Some(page_id) = page_ids.first() else {
+                    continue;
+                };
+                let original = strokes(&file, &note_id, page_id);
+                let copy_id = file.duplicate_page(&note_id, page_id).unwrap();
+
+                let mut reopened = reopen(&file);
+                let copy = strokes(&reopened, &note_id, &copy_id);
+                assert_fresh_copy(&reopened, &note_id, &original, &copy);
+                reopened.delete_page(&note_id, &copy_id).unwrap();
+
+                let mut reopened = reopen(&reopened);
+                assert_eq!(pages(&reopened, &note_id), page_ids, "{}", label);
+                assert!(
+                    files(&mut reopened, &note_id, &copy_id).is_empty(),
+                    "{}",
+                    label
+                );
+                assert_eq!(strokes(&reopened, &note_id, page_id), original, "{}", label);
+            }
+        }
+    }
+
+    #[test]
+    fn compacted_device_samples_keep_their_active_pages() {
+        for (label, mut file) in device_samples() {
+            let mut expected = Vec::new();
+            for note_id in file.list_notes().into_keys() {
+                for page_id in pages(&file, &note_id) {
+                    expected.push((note_id, page_id, strokes(&file, &note_id, &page_id)));
+                }
+                file.compact(&note_id).unwrap();
+            }
+
+            let file = reopen(&file);
+            for note_id in file.list_notes().into_keys() {
+                let metadata = metadata(&file, &note_id);
+                assert!(metadata.detached_pages.is_empty(), "{}", label);
+                assert!(
+                    metadata
+                        .reserved_pages
+                        .iter()
+                        .all(|page_id| metadata.active_pages.contains(page_id)),
+                    "{}",
+                    label
+                );
+            }
+            for (note_id, page_id, original) in expected {
+                assert_eq!(strokes(&file, &note_id, &page_id), original, "{}", label);
+            }
+        }
+    }
+}
@@ -0,0 +1,469 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    io::Write,
+};
+
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use flate2::{Compression, write::ZlibEncoder};
+use pdf_writer::{
+    Content, Date, Filter, Finish, Name, Pdf, Ref, TextStr,
+    types::{BlendMode, LineCapStyle},
+    writers::ExtGraphicsState,
+};
+
+use crate::{
+    background::{
+        Background, BackgroundImage, LoadedBackground, PATTERN_COLOR, PATTERN_DOT_RADIUS,
+        PATTERN_LINE_WIDTH,
+    },
+    brush::{Blend, Brush},
+    color::Color,
+    error::Result,
+    note_tree::NoteMetadata,
+    points::{PathSegment, Stroke},
+    render::{Palette, Rect, Viewport},
+    shape::Shape,
+};
+
+pub struct PdfPage {
+    /// Page rect in page coordinates.
+    pub page: Rect,
+    /// Maps the page onto PDF user space, in points.
+    pub viewport: Viewport,
+    pub background: LoadedBackground,
+    pub strokes: Vec<(Shape, Stroke)>,
+}
+
+/// Resource name of the background image of a page.
+const BACKGROUND_IMAGE: Name<'static> = Name(b"Background");
+
+pub fn write_pdf(
+    mut writer: impl Write,
+    metadata: &NoteMetadata,
+    pages: &[PdfPage],
+    palette: &Palette,
+) -> Result {
+    let catalog_id = Ref::new(1);
+    let page_tree_id = Ref::new(2);
+    let document_info_id = Ref::new(3);
+    let mut next_id = Ref::new(4);
+    let page_ids = pages.iter().map(|_| next_id.bump()).collect::<Vec<_>>();
+    // Image XObjects by the archive path of their image, shared by pages with the same
+    // template
+    let mut image_ids = HashMap::new();
+
+    let mut pdf = Pdf::new();
+    pdf.catalog(catalog_id).pages(page_tree_id);
+    pdf.pages(page_tree_id)
+        .kids(page_ids.iter().copied())
+        .count(pages.len() as i32);
+    pdf.document_info(document_info_id)
+        .title(TextStr(&metadata.name))
+        .creation_date(convert_datetime(&metadata.created))
+        .modified_date(convert_datetime(&metadata.modified));
+
+    for (page, page_id) in pages.iter().zip(&page_ids) {
+        let content_id = next_id.bump();
+        let width = page.viewport.region.width() * page.viewport.scale;
+        let height = page.viewport.region.height() * page.viewport.scale;
+
+        let image_id = match (&page.background.background, &page.background.image) {
+            (Background::Image { path }, Some(image)) => match image_ids.get(path) {
+                Some(image_id) => Some(*image_id),
+                None => {
+                    let image_id = write_image(&mut pdf, &mut next_id, image)?;
+                    image_ids.insert(path.clone(), image_id);
+                    Some(image_id)
+                }
+            },
+            (_, Some(image)) => Some(write_image(&mut pdf, &mut next_id, image)?),
+            (_, None) => None,
+        };
+
+        let (content, graphics_states) = page_content(page, palette);
+
+        let mut page_writer = pdf.page(*page_id);
+        page_writer
+            .parent(page_tree_id)
+            .media_box(pdf_writer::Rect::new(0.0, 0.0, width, height))
+            .contents(content_id);
+        let mut resources = page_writer.resources();
+        if let Some(image_id) = image_id {
+            resources.x_objects().pair(BACKGROUND_IMAGE, image_id);
+        }
+        let mut ext_g_states = resources.ext_g_states();
+        for graphics_state in graphics_states {
+            let mut ext_g_state = ext_g_states
+                .insert(Name(graphics_state.name().as_bytes()))
+                .start::<ExtGraphicsState>();
+            ext_g_state.non_stroking_alpha(graphics_state.alpha as f32 / 255.0);
+            match graphics_state.blend {
+                Blend::Normal => {}
+                Blend::Multiply => {
+                    ext_g_state.blend_mode(BlendMode::Multiply);
+                }
+                Blend::Screen => {
+                    ext_g_state.blend_mode(BlendMode::Screen);
+                }
+            }
+        }
+        ext_g_states.finish();
+        resources.finish();
+        page_writer.finish();
+
+        pdf.stream(content_id, &content);
+    }
+
+    writer.write_all(&pdf.finish())?;
+
+    Ok(())
+}
+
+/// Transparency and blending of the ink, registered as an `ExtGState` in the page resources.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct GraphicsState {
+    alpha: u8,
+    blend: Blend,
+}
+
+impl GraphicsState {
+    fn name(&self) -> String {
+        match self.blend {
+            Blend::Normal => format!("Alpha{}", self.alpha),
+            Blend::Multiply => format!("Alpha{}Multiply", self.alpha),
+            Blend::Screen => format!("Alpha{}Screen", self.alpha),
+        }
+    }
+}
+
+/// Writes an image XObject with a soft mask for its alpha channel and returns its reference.
+fn write_image(pdf: &mut Pdf, next_id: &mut Ref, image: &BackgroundImage) -> Result<Ref> {
+    let image_id = next_id.bump();
+    let mask_id = next_id.bump();
+
+    let rgb = image
+        .rgba
+        .chunks_exact(4)
+        .flat_map(|pixel| [pixel[0], pixel[1], pixel[2]])
+        .collect::<Vec<_>>();
+    let alpha = image
+        .rgba
+        .chunks_exact(4)
+        .map(|pixel| pixel[3])
+        .collect::<Vec<_>>();
+
+    let rgb = compress(&rgb)?;
+    let mut image_writer = pdf.image_xobject(image_id, &rgb);
+    image_writer.filter(Filter::FlateDecode);
+    image_writer
+        .width(image.width as i32)
+        .height(image.height as i32)
+        .bits_per_component(8)
+        .s_mask(mask_id);
+    image_writer.color_space().device_rgb();
+    image_writer.finish();
+
+    let alpha = compress(&alpha)?;
+    let mut mask_writer = pdf.image_xobject(mask_id, &alpha);
+    mask_writer.filter(Filter::FlateDecode);
+    mask_writer
+        .width(image.width as i32)
+        .height(image.height as i32)
+        .bits_per_component(8);
+    mask_writer.color_space().device_gray();
+    mask_writer.finish();
+
+    Ok(image_id)
+}
+
+fn compress(data: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+/// Builds the content stream of a page and returns it together with the graphics states it
+/// refers to.
+fn page_content(page: &PdfPage, palette: &Palette) -> (Vec<u8>, BTreeSet<GraphicsState>) {
+    let region = &page.viewport.region;
+    let scale = page.viewport.scale;
+    let mut content = Content::new();
+    let mut graphics_states = BTreeSet::new();
+
+    // Page coordinates grow downwards from the top left, PDF user space grows upwards.
+    content.transform([
+        scale,
+        0.0,
+        0.0,
+        -scale,
+        -region.left * scale,
+        region.bottom * scale,
+    ]);
+
+    set_fill_color(&mut content, palette.background());
+    content.rect(region.left, region.top, region.width(), region.height());
+    content.fill_nonzero();
+
+    draw_background(&mut content, &page.page, region, &page.background, palette);
+
+    for (shape, stroke) in &page.strokes {
+        let brush = palette.brush(shape);
+        if stroke.points.is_empty() {
+            log::warn!("No points to draw for stroke");
+            continue;
+        }
+
+        content.save_state();
+        set_fill(&mut content, &mut graphics_states, &brush);
+        for path in stroke.outline_paths(&brush) {
+            content.move_to(path.start.0, path.start.1);
+            for segment in &path.segments {
+                match segment {
+                    PathSegment::Line((x, y)) => {
+                        content.line_to(*x, *y);
+                    }
+                    PathSegment::Curve {
+                        control1,
+                        control2,
+                        end,
+                    } => {
+                        content
+                            .cubic_to(control1.0, control1.1, control2.0, control2.1, end.0, end.1);
+                    }
+                }
+            }
+            content.close_path();
+        }
+        content.fill_nonzero();
+        content.restore_state();
+    }
+
+    (content.finish().into_vec(), graphics_states)
+}
+
+fn draw_background(
+    content: &mut Content,
+    page: &Rect,
+    area: &Rect,
+    background: &LoadedBackground,
+    palette: &Palette,
+) {
+    if background.image.is_some() {
+        content.save_state();
+        content.transform([
+            page.width(),
+            0.0,
+            0.0,
+            -page.height(),
+            page.left,
+            page.bottom,
+        ]);
+        content.x_object(BACKGROUND_IMAGE);
+        content.restore_state();
+    }
+
+    let lines = background.background.pattern_lines(page, area);
+    if !lines.is_empty() {
+        content.save_state();
+        set_stroke_color(content, palette.map(PATTERN_COLOR));
+        content.set_line_width(PATTERN_LINE_WIDTH);
+        for (start, end) in lines {
+            content.move_to(start.0, start.1);
+            content.line_to(end.0, end.1);
+        }
+        content.stroke();
+        content.restore_state();
+    }
+
+    let dots = background.background.pattern_dots(page, area);
+    if !dots.is_empty() {
+        content.save_state();
+        set_stroke_color(content, palette.map(PATTERN_COLOR));
+        content.set_line_width(PATTERN_DOT_RADIUS * 2.0);
+        content.set_line_cap(LineCapStyle::RoundCap);
+        for (x, y) in dots {
+            content.move_to(x, y);
+            content.line_to(x, y);
+        }
+        content.stroke();
+        content.restore_state();
+    }
+}
+
+fn set_stroke_color(content: &mut Content, color: Color) {
+    content.set_stroke_rgb(
+        color.red() as f32 / 255.0,
+        color.green() as f32 / 255.0,
+        color.blue() as f32 / 255.0,
+    );
+}
+
+fn set_fill_color(content: &mut Content, color: Color) {
+    content.set_fill_rgb(
+        color.red() as f32 / 255.0,
+        color.green() as f32 / 255.0,
+        color.blue() as f32 / 255.0,
+    );
+}
+
+fn set_fill(content: &mut Content, graphics_states: &mut BTreeSet<GraphicsState>, brush: &Brush) {
+    let color = brush.ink_color();
+    set_fill_color(content, color);
+
+    let graphics_state = GraphicsState {
+        alpha: color.alpha(),
+        blend: brush.blend,
+    };
+    if graphics_state.alpha != u8::MAX || graphics_state.blend != Blend::Normal {
+        content.set_parameters(Name(graphics_state.name().as_bytes()));
+        graphics_states.insert(graphics_state);
+    }
+}
+
+fn convert_datetime(datetime: &DateTime<Utc>) -> Date {
+    Date::new(datetime.year() as u16)
+        .month(datetime.month() as u8)
+        .day(datetime.day() as u8)
+        .hour(datetime.hour() as u8)
+        .minute(datetime.minute() as u8)
+        .second(datetime.second() as u8)
+        .utc_offset_hour(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, io::Cursor};
+
+    use super::*;
+    use crate::{NoteFile, testing};
+
+    /// Returns the dictionaries of the objects of a PDF written by pdf-writer, by number.
+    fn objects(pdf: &[u8]) -> BTreeMap<u32, String> {
+        let object_regex = regex::bytes::Regex::new(r"(?s-u)(\d+) 0 obj\n(.*?)\nendobj").unwrap();
+        object_regex
+            .captures_iter(pdf)
+            .map(|captures| {
+                let number = String::from_utf8_lossy(&captures[1]).parse().unwrap();
+                let object = String::from_utf8_lossy(&captures[2]);
+                let dictionary = object.split("\nstream\n").next().unwrap().to_string();
+                (number, dictionary)
+            })
+            .collect()
+    }
+
+    fn numbers(array: &str) -> Vec<f32> {
+        array
+            .split_whitespace()
+            .map(|number| number.parse().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn pages_are_sized_in_points() {
+        let file = NoteFile::read(Cursor::new(testing::archive())).unwrap();
+        let note_id = *file.list_notes().keys().next().unwrap();
+        let mut note = file.get_note(&note_id).unwrap();
+        let mut buffer = Vec::new();
+        note.export_pdf(&mut buffer).unwrap();
+        let objects = objects(&buffer);
+
+        let media_box_regex = regex::Regex::new(r"/MediaBox \[([^\]]*)\]").unwrap();
+        let media_boxes = objects
+            .values()
+            .filter(|object| object.contains("/Type /Page\n"))
+            .map(|object| numbers(&media_box_regex.captures(object).unwrap()[1]))
+            .collect::<Vec<_>>();
+        assert_eq!(media_boxes.len(), note.active_pages().len());
+        for (media_box, page_id) in media_boxes.iter().zip(note.active_pages().to_vec()) {
+            let page = note.get_page(&page_id).unwrap();
+            let page_rect = page.page_rect();
+            let points_per_unit = 72.0 / page.units_per_inch;
+            let expected = [
+                0.0,
+                0.0,
+                page_rect.width() * points_per_unit,
+                page_rect.height() * points_per_unit,
+            ];
+            for (value, expected) in media_box.iter().zip(expected) {
+                assert!((value - expected).abs() < 0.01, "{:?}", media_box);
+            }
+        }
+
+        let created = note.metadata.created.format("%Y%m%d%H%M%S").to_string();
+        let info = objects
+            .values()
+            .find(|object| object.contains("/Title"))
+            .unwrap();
+        assert!(info.contains("/Title (Test)"), "{}", info);
+        assert!(
+            info.contains(&format!("/CreationDate (D:{}", created)),
+            "{}",
+            info
+        );
+    }
+
+    #[test]
+    fn pages_with_the_same_image_share_it() {
+        let file = NoteFile::read(Cursor::new(testing::archive())).unwrap();
+        let note_id = *file.list_notes().keys().next().unwrap();
+        let metadata = file.get_note(&note_id).unwrap().metadata;
+
+        let image = BackgroundImage {
+            width: 2,
+            height: 2,
+            rgba: vec![0x80; 16],
+            png_data: Vec::new(),
+        };
+        let page = |path: &str| PdfPage {
+            page: Rect {
+                left: 0.0,
+                top: 0.0,
+                right: 100.0,
+                bottom: 200.0,
+            },
+            viewport: Viewport {
+                region: Rect {
+                    left: 0.0,
+                    top: 0.0,
+                    right: 100.0,
+                    bottom: 200.0,
+                },
+                scale: 0.5,
+            },
+            background: LoadedBackground {
+                background: Background::Image {
+                    path: path.to_string(),
+                },
+                image: Some(image.clone()),
+            },
+            strokes: Vec::new(),
+        };
+        let pages = [page("a.png"), page("b.png"), page("a.png")];
+        let mut buffer = Vec::new();
+        write_pdf(&mut buffer, &metadata, &pages, &Palette::Original).unwrap();
+        let objects = objects(&buffer);
+
+        // An image and its soft mask per distinct image
+        let images = objects
+            .values()
+            .filter(|object| object.contains("/Subtype /Image"))
+            .count();
+        assert_eq!(images, 4);
+
+        let x_object_regex = regex::Regex::new(r"/Background (\d+) 0 R").unwrap();
+        let page_objects = objects
+            .values()
+            .filter(|object| object.contains("/Type /Page\n"))
+            .collect::<Vec<_>>();
+        assert_eq!(page_objects.len(), 3);
+        let x_objects = page_objects
+            .iter()
+            .map(|object| x_object_regex.captures(object).unwrap()[1].to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(x_objects[0], x_objects[2]);
+        assert_ne!(x_objects[0], x_objects[1]);
+        for page in page_objects {
+            assert!(page.contains("/MediaBox [0 0 50 100]"), "{}", page);
+        }
+    }
+}
@@ -1,17 +1,67 @@
 use std::collections::HashMap;
 
-use byteorder::{BE, ReadBytesExt};
-use raqote::{DrawOptions, DrawTarget, PathBuilder, Source, StrokeStyle};
+use byteorder::{BE, ReadBytesExt, WriteBytesExt};
+use raqote::{
+    BlendMode, DrawOptions, DrawTarget, ExtendMode, FilterMode, Image, PathBuilder, Source,
+    Transform,
+};
 
 use crate::{
+    brush::{Blend, Brush, TEXTURE_SIZE},
     error::{Error, Result},
+    geometry::{self, WidthPoint},
     id::{PageUuid, PointsUuid, StrokeUuid},
 };
 
+pub use crate::geometry::{CubicBezier, OutlinePath, PathSegment};
+
+/// Maximum distance between the smoothed centre line of a stroke and the polygon it is drawn
+/// with, in page units.
+const SMOOTHING_TOLERANCE: f32 = 0.1;
+
+/// Size of the UUID fields of a points file, in bytes.
+const UUID_FIELD_SIZE: usize = 36;
+
+/// Size of the file header, in bytes.
+const HEADER_SIZE: u32 = 4 + 2 * UUID_FIELD_SIZE as u32;
+
+/// Size of a point, in bytes.
+const POINT_SIZE: u32 = 16;
+
+/// Layout of a UUID in a fixed size field of a points file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UuidLayout {
+    #[default]
+    Hyphenated,
+    /// Condensed to 32 characters and padded with spaces
+    Padded,
+}
+
+impl UuidLayout {
+    fn of(field: &str) -> Self {
+        if field.len() == UUID_FIELD_SIZE && !field.ends_with(' ') {
+            Self::Hyphenated
+        } else {
+            Self::Padded
+        }
+    }
+
+    fn field(&self, hyphenated: String, simple: String) -> [u8; UUID_FIELD_SIZE] {
+        let mut field = [b' '; UUID_FIELD_SIZE];
+        let uuid = match self {
+            Self::Hyphenated => hyphenated,
+            Self::Padded => simple,
+        };
+        field[..uuid.len()].copy_from_slice(uuid.as_bytes());
+        field
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Header {
     pub version: u32,
     pub page_id: PageUuid,
+    pub page_id_layout: UuidLayout,
     pub points_id: PointsUuid,
 }
 
@@ -27,6 +77,7 @@ impl Header {
         reader.read_exact(&mut buffer)?;
         let page_id_str = str::from_utf8(&buffer).map_err(|e| Error::UuidInvalidUtf8(e))?;
         let page_id = PageUuid::from_str(page_id_str.trim())?;
+        let page_id_layout = UuidLayout::of(page_id_str);
 
         // Clear buffer for the next read
         buffer.fill(0);
@@ -37,9 +88,20 @@ impl Header {
         Ok(Self {
             version,
             page_id,
+            page_id_layout,
             points_id,
         })
     }
+
+    pub fn write(&self, mut writer: impl std::io::Write) -> Result {
+        writer.write_u32::<BE>(self.version)?;
+        writer.write_all(&self.page_id_layout.field(
+            self.page_id.to_hyphenated_string(),
+            self.page_id.to_simple_string(),
+        ))?;
+        writer.write_all(self.points_id.to_hyphenated_string().as_bytes())?;
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -75,6 +137,17 @@ impl PointsTableEntry {
             flag,
         })
     }
+
+    pub fn write(&self, mut writer: impl std::io::Write) -> Result {
+        if self.point_count > 0x0FFFFFFF {
+            return Err(Error::TooManyPoints(self.point_count as usize));
+        }
+
+        writer.write_all(self.stroke_id.to_hyphenated_string().as_bytes())?;
+        writer.write_u32::<BE>(self.start_addr)?;
+        writer.write_u32::<BE>(self.point_count << 4 | (self.flag & 0xF) as u32)?;
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -123,11 +196,109 @@ impl Stroke {
         Ok(Self { points })
     }
 
+    pub fn write(&self, mut writer: impl std::io::Write) -> Result {
+        for point in &self.points {
+            writer.write_u32::<BE>(point.timestamp_rel)?;
+            writer.write_f32::<BE>(point.x)?;
+            writer.write_f32::<BE>(point.y)?;
+            writer.write_i8(point.tilt_x)?;
+            writer.write_i8(point.tilt_y)?;
+            writer.write_u16::<BE>(point.pressure)?;
+        }
+        Ok(())
+    }
+
+    /// Fits a smooth curve through the stroke, with one cubic segment between every pair of
+    /// points that deviate more than `tolerance` page units from a straight line.
+    pub fn to_bezier_path(&self, tolerance: f32) -> Vec<CubicBezier> {
+        let points = self
+            .points
+            .iter()
+            .map(|point| WidthPoint {
+                x: point.x,
+                y: point.y,
+                width: 0.0,
+            })
+            .collect::<Vec<_>>();
+        geometry::fit_curve(&points, tolerance)
+            .into_iter()
+            .map(|(curve, _, _)| curve)
+            .collect()
+    }
+
+    /// Returns a copy of the stroke without the points that lie within `tolerance` page units
+    /// of the line through their neighbours, for cheap previews.
+    pub fn decimate(&self, tolerance: f32) -> Stroke {
+        let points = self
+            .points
+            .iter()
+            .map(|point| WidthPoint {
+                x: point.x,
+                y: point.y,
+                width: 0.0,
+            })
+            .collect::<Vec<_>>();
+        let points = self
+            .points
+            .iter()
+            .zip(geometry::simplify_mask(&points, tolerance))
+            .filter(|(_, keep)| *keep)
+            .map(|(point, _)| point.clone())
+            .collect();
+        Stroke { points }
+    }
+
+    /// Returns the closed outlines of the stroke, with the width at every point driven by the
+    /// stylus pressure. The points are joined by a smooth curve. Solid lines yield a single
+    /// polygon, patterned lines one per dash.
+    pub fn outline(&self, brush: &Brush) -> Vec<Vec<(f32, f32)>> {
+        let points = self
+            .points
+            .iter()
+            .map(|point| WidthPoint {
+                x: point.x,
+                y: point.y,
+                width: brush.point_width(point),
+            })
+            .collect::<Vec<_>>();
+        let points = geometry::smooth(&points, SMOOTHING_TOLERANCE);
+        match brush.dash_pattern() {
+            Some(pattern) => geometry::dash(&points, &pattern, brush.dash_phase)
+                .iter()
+                .map(|dash| geometry::outline(dash, brush.cap()))
+                .collect(),
+            None => vec![geometry::outline(&points, brush.cap())],
+        }
+    }
+
+    /// Returns the outlines of [`Stroke::outline`] as paths of cubic Bézier segments, for
+    /// vector output. Patterned lines are split into dashes before the curve is fitted.
+    pub fn outline_paths(&self, brush: &Brush) -> Vec<OutlinePath> {
+        let points = self
+            .points
+            .iter()
+            .map(|point| WidthPoint {
+                x: point.x,
+                y: point.y,
+                width: brush.point_width(point),
+            })
+            .collect::<Vec<_>>();
+        match brush.dash_pattern() {
+            Some(pattern) => geometry::dash(&points, &pattern, brush.dash_phase)
+                .iter()
+                .filter_map(|dash| geometry::curve_outline(dash, brush.cap()))
+                .collect(),
+            None => geometry::curve_outline(&points, brush.cap())
+                .into_iter()
+                .collect(),
+        }
+    }
+
     pub fn render(
         &self,
         draw_target: &mut DrawTarget,
         draw_options: &DrawOptions,
-        stroke_style: &StrokeStyle,
+        brush: &Brush,
     ) -> Result<()> {
         if self.points.is_empty() {
             log::warn!("No points to draw for stroke");
@@ -135,23 +306,43 @@ impl Stroke {
         }
 
         let mut path = PathBuilder::new();
-        let mut first_point = true;
 
-        for point in &self.points {
-            if first_point {
-                path.move_to(point.x, point.y);
-                first_point = false;
-            } else {
-                path.line_to(point.x, point.y);
+        for polygon in self.outline(brush) {
+            let mut first_point = true;
+            for (x, y) in polygon {
+                if first_point {
+                    path.move_to(x, y);
+                    first_point = false;
+                } else {
+                    path.line_to(x, y);
+                }
             }
+            path.close();
         }
 
-        draw_target.stroke(
-            &path.finish(),
-            &Source::Solid(raqote::Color::new(255, 0, 0, 0).into()),
-            stroke_style,
-            draw_options,
-        );
+        let mut draw_options = *draw_options;
+        match brush.blend {
+            Blend::Normal => {}
+            Blend::Multiply => draw_options.blend_mode = BlendMode::Multiply,
+            Blend::Screen => draw_options.blend_mode = BlendMode::Screen,
+        }
+
+        let texture = brush.texture();
+        let source = match &texture {
+            Some(data) => Source::Image(
+                Image {
+                    width: TEXTURE_SIZE,
+                    height: TEXTURE_SIZE,
+                    data,
+                },
+                ExtendMode::Repeat,
+                FilterMode::Nearest,
+                Transform::identity(),
+            ),
+            None => Source::Solid(brush.ink_color().into()),
+        };
+
+        draw_target.fill(&path.finish(), &source, &draw_options);
 
         Ok(())
     }
@@ -161,9 +352,20 @@ impl Stroke {
 pub struct PointsFile {
     header: Header,
     points: HashMap<StrokeUuid, Stroke>,
+    /// Stroke table in file order, to keep the order and flags of the strokes on write.
+    table: Vec<PointsTableEntry>,
 }
 
 impl PointsFile {
+    /// Creates an empty points file.
+    pub fn new(header: Header) -> Self {
+        Self {
+            header,
+            points: HashMap::new(),
+            table: Vec::new(),
+        }
+    }
+
     pub fn read(mut reader: impl std::io::Read + std::io::Seek) -> Result<Self> {
         let header = Header::read(&mut reader)?;
 
@@ -179,19 +381,178 @@ impl PointsFile {
         }
 
         let mut points = HashMap::new();
-        for entry in points_table {
-            let stroke = Stroke::read(&mut reader, &entry)?;
+        for entry in &points_table {
+            let stroke = Stroke::read(&mut reader, entry)?;
             points.insert(entry.stroke_id, stroke);
         }
 
-        Ok(Self { header, points })
+        Ok(Self {
+            header,
+            points,
+            table: points_table,
+        })
+    }
+
+    /// Writes the header, the points of every stroke, the stroke table and the address of the
+    /// table. Strokes keep their original order and flag, added strokes are appended.
+    pub fn write(&self, mut writer: impl std::io::Write) -> Result {
+        let mut stroke_ids = self
+            .table
+            .iter()
+            .map(|entry| entry.stroke_id)
+            .filter(|stroke_id| self.points.contains_key(stroke_id))
+            .collect::<Vec<_>>();
+        let mut added = self
+            .points
+            .keys()
+            .filter(|stroke_id| !stroke_ids.contains(stroke_id))
+            .copied()
+            .collect::<Vec<_>>();
+        added.sort_by_key(|stroke_id| stroke_id.to_simple_string());
+        stroke_ids.extend(added);
+
+        let mut address = HEADER_SIZE;
+        let mut table = Vec::with_capacity(stroke_ids.len());
+        for stroke_id in &stroke_ids {
+            let point_count = self.points[stroke_id].points.len();
+            let flag = self
+                .table
+                .iter()
+                .find(|entry| entry.stroke_id == *stroke_id)
+                .map(|entry| entry.flag)
+                .unwrap_or_default();
+            table.push(PointsTableEntry {
+                stroke_id: *stroke_id,
+                start_addr: address,
+                point_count: point_count as u32,
+                flag,
+            });
+            address = u32::try_from(point_count)
+                .ok()
+                .and_then(|count| count.checked_mul(POINT_SIZE))
+                .and_then(|size| address.checked_add(size))
+                .ok_or(Error::TooManyPoints(point_count))?;
+        }
+
+        self.header.write(&mut writer)?;
+        for stroke_id in &stroke_ids {
+            self.points[stroke_id].write(&mut writer)?;
+        }
+        for entry in &table {
+            entry.write(&mut writer)?;
+        }
+        writer.write_u32::<BE>(address)?;
+        Ok(())
     }
 
     pub fn header(&self) -> &Header {
         &self.header
     }
 
+    pub fn header_mut(&mut self) -> &mut Header {
+        &mut self.header
+    }
+
+    /// Returns the IDs of the strokes in the file.
+    pub fn stroke_ids(&self) -> impl Iterator<Item = &StrokeUuid> {
+        self.points.keys()
+    }
+
+    /// Changes the ID of a stroke, keeping its position and flag in the stroke table.
+    pub fn rename_stroke(&mut self, stroke_id: &StrokeUuid, new_stroke_id: StrokeUuid) {
+        if let Some(stroke) = self.points.remove(stroke_id) {
+            self.points.insert(new_stroke_id, stroke);
+        }
+        for entry in &mut self.table {
+            if entry.stroke_id == *stroke_id {
+                entry.stroke_id = new_stroke_id;
+            }
+        }
+    }
+
     pub fn get_stroke(&self, stroke_id: &StrokeUuid) -> Option<&Stroke> {
         self.points.get(stroke_id)
     }
+
+    pub fn get_stroke_mut(&mut self, stroke_id: &StrokeUuid) -> Option<&mut Stroke> {
+        self.points.get_mut(stroke_id)
+    }
+
+    /// Adds or replaces the points of a stroke.
+    pub fn insert_stroke(&mut self, stroke_id: StrokeUuid, stroke: Stroke) {
+        self.points.insert(stroke_id, stroke);
+    }
+
+    pub fn remove_stroke(&mut self, stroke_id: &StrokeUuid) -> Option<Stroke> {
+        self.points.remove(stroke_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{brush::PenType, testing};
+
+    /// Points file of the page with two strokes of a created note, with a stroke flag the crate
+    /// does not interpret.
+    fn points_file() -> Vec<u8> {
+        let mut points = testing::entries(&testing::archive())
+            .into_iter()
+            .filter(|(path, _)| path.ends_with("#points"))
+            .map(|(_, file)| PointsFile::read(Cursor::new(file)).unwrap())
+            .find(|points| points.table.len() == 2)
+            .unwrap();
+        points.table[0].flag = 5;
+        let mut buffer = Vec::new();
+        points.write(&mut buffer).unwrap();
+        buffer
+    }
+
+    #[test]
+    fn unchanged_points_file_is_written_unchanged() {
+        let file = points_file();
+        let mut buffer = Vec::new();
+        PointsFile::read(Cursor::new(file.as_slice()))
+            .unwrap()
+            .write(&mut buffer)
+            .unwrap();
+        assert_eq!(buffer, file);
+    }
+
+    #[test]
+    fn edited_points_file_keeps_order_and_flags() {
+        let mut points = PointsFile::read(Cursor::new(points_file())).unwrap();
+        let table = points.table.clone();
+        let first = table[0].stroke_id;
+        points.get_stroke_mut(&first).unwrap().points.pop();
+        let added = StrokeUuid::new_random();
+        points.insert_stroke(
+            added,
+            Stroke {
+                points: testing::stroke(PenType::Ballpoint, 50.0).points,
+            },
+        );
+
+        let mut buffer = Vec::new();
+        points.write(&mut buffer).unwrap();
+        let written = PointsFile::read(Cursor::new(buffer)).unwrap();
+        assert_eq!(written.header, points.header);
+        assert_eq!(written.points, points.points);
+
+        let stroke_ids = written
+            .table
+            .iter()
+            .map(|entry| entry.stroke_id)
+            .collect::<Vec<_>>();
+        let mut expected = table
+            .iter()
+            .map(|entry| entry.stroke_id)
+            .collect::<Vec<_>>();
+        expected.push(added);
+        assert_eq!(stroke_ids, expected);
+        assert_eq!(written.table[0].flag, 5);
+        assert_eq!(written.table[0].point_count, table[0].point_count - 1);
+    }
 }
@@ -0,0 +1,518 @@
+use std::io::Write;
+
+use crate::{
+    brush::{Brush, PenType},
+    color::Color,
+    error::Result,
+    json::Layer,
+    points::{Point, Stroke},
+    render::Rect,
+    shape::Shape,
+};
+
+/// File header, padded with spaces to 43 bytes.
+const HEADER: &[u8; 43] = b"reMarkable .lines file, version=6          ";
+
+/// Size of the reMarkable canvas in pixels. Its x axis is centered on the page.
+const CANVAS_WIDTH: f32 = 1404.0;
+const CANVAS_HEIGHT: f32 = 1872.0;
+
+const ZERO: CrdtId = CrdtId(0, 0);
+const ROOT: CrdtId = CrdtId(0, 1);
+
+/// Author of all written items, registered in the author IDs block.
+const AUTHOR: u8 = 1;
+
+/// First ID handed out for items, after the IDs reserved by the format.
+const FIRST_ID: u64 = 11;
+
+// Uncertain: thickness scale of the medium pen size
+const THICKNESS_SCALE: f64 = 2.0;
+
+// Block types
+const MIGRATION_INFO: u8 = 0x00;
+const SCENE_TREE: u8 = 0x01;
+const TREE_NODE: u8 = 0x02;
+const SCENE_GROUP_ITEM: u8 = 0x04;
+const SCENE_LINE_ITEM: u8 = 0x05;
+const AUTHOR_IDS: u8 = 0x09;
+const PAGE_INFO: u8 = 0x0A;
+
+// Tag types
+const TAG_BYTE1: u8 = 0x1;
+const TAG_BYTE4: u8 = 0x4;
+const TAG_BYTE8: u8 = 0x8;
+const TAG_LENGTH4: u8 = 0xC;
+const TAG_ID: u8 = 0xF;
+
+// Item types of scene items
+const ITEM_GROUP: u8 = 0x02;
+const ITEM_LINE: u8 = 0x03;
+
+/// Colors of the reMarkable pen color indices, used to pick the closest one.
+const PEN_COLORS: [(u32, Color); 8] = [
+    (0, Color::from_argb(0xFF00_0000)),
+    (1, Color::from_argb(0xFF7D_7D7D)),
+    (2, Color::from_argb(0xFFFF_FFFF)),
+    (3, Color::from_argb(0xFFFF_ED75)),
+    (4, Color::from_argb(0xFF00_B000)),
+    (5, Color::from_argb(0xFFFF_C0CB)),
+    (6, Color::from_argb(0xFF00_62CC)),
+    (7, Color::from_argb(0xFFD9_0707)),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct CrdtId(u8, u64);
+
+/// Writes the strokes of a page as a reMarkable v6 scene, with one layer group per layer.
+/// The page is scaled to fit the reMarkable canvas. `author` identifies the writer of the
+/// strokes, so scenes written for the same note share it.
+pub fn write_remarkable(
+    mut writer: impl Write,
+    page: &Rect,
+    layers: &[(Layer, Vec<(Shape, Stroke)>)],
+    author: uuid::Uuid,
+) -> Result {
+    let scale = (CANVAS_WIDTH / page.width()).min(CANVAS_HEIGHT / page.height());
+    let transform = |point: &Point| {
+        (
+            (point.x - page.left) * scale - page.width() * scale / 2.0,
+            (point.y - page.top) * scale,
+        )
+    };
+
+    let mut next_id = FIRST_ID;
+    let mut new_id = |author: u8| {
+        next_id += 1;
+        CrdtId(author, next_id - 1)
+    };
+    let layer_ids = layers.iter().map(|_| new_id(0)).collect::<Vec<_>>();
+
+    let mut blocks = Vec::new();
+    // Uncertain: minimum and current versions of the blocks
+    write_block(&mut blocks, AUTHOR_IDS, 1, 1, |block| {
+        block.varuint(1);
+        block.subblock(0, |entry| {
+            let uuid = author.to_bytes_le();
+            entry.varuint(uuid.len() as u64);
+            entry.data.extend_from_slice(&uuid);
+            entry.data.extend_from_slice(&(AUTHOR as u16).to_le_bytes());
+        });
+    });
+    write_block(&mut blocks, MIGRATION_INFO, 1, 1, |block| {
+        block.id(1, CrdtId(AUTHOR, 1));
+        block.bool(2, true);
+    });
+    write_block(&mut blocks, PAGE_INFO, 0, 1, |block| {
+        block.int(1, 1);
+        block.int(2, 0);
+        block.int(3, 0);
+        block.int(4, 0);
+    });
+
+    for layer_id in &layer_ids {
+        write_block(&mut blocks, SCENE_TREE, 1, 1, |block| {
+            block.id(1, *layer_id);
+            block.id(2, ZERO);
+            block.bool(3, true);
+            block.subblock(4, |parent| parent.id(1, ROOT));
+        });
+    }
+
+    write_block(&mut blocks, TREE_NODE, 1, 1, |block| {
+        block.id(1, ROOT);
+        block.lww_string(2, ZERO, "");
+        block.lww_bool(3, ZERO, true);
+    });
+    for ((layer, _), layer_id) in layers.iter().zip(&layer_ids) {
+        let label_id = new_id(0);
+        write_block(&mut blocks, TREE_NODE, 1, 1, |block| {
+            block.id(1, *layer_id);
+            block.lww_string(2, label_id, &format!("Layer {}", layer.id.id() + 1));
+            block.lww_bool(3, ZERO, layer.show);
+        });
+    }
+
+    let mut left_id = ZERO;
+    for layer_id in &layer_ids {
+        let item_id = new_id(0);
+        write_block(&mut blocks, SCENE_GROUP_ITEM, 1, 1, |block| {
+            block.item(ROOT, item_id, left_id, ITEM_GROUP, |value| {
+                value.id(2, *layer_id);
+            });
+        });
+        left_id = item_id;
+    }
+
+    for ((_, strokes), layer_id) in layers.iter().zip(&layer_ids) {
+        let mut left_id = ZERO;
+        for (shape, stroke) in strokes {
+            if stroke.points.is_empty() {
+                log::warn!("No points to export for stroke");
+                continue;
+            }
+
+            let brush = Brush::from_shape(shape);
+            let item_id = new_id(AUTHOR);
+            write_block(&mut blocks, SCENE_LINE_ITEM, 2, 2, |block| {
+                block.item(*layer_id, item_id, left_id, ITEM_LINE, |value| {
+                    value.int(1, tool(brush.pen_type));
+                    value.int(2, color(brush.color));
+                    value.double(3, THICKNESS_SCALE);
+                    value.float(4, 0.0);
+                    value.subblock(5, |points| {
+                        for (index, point) in stroke.points.iter().enumerate() {
+                            let (x, y) = transform(point);
+                            let next = stroke.points.get(index + 1).unwrap_or(point);
+                            let (next_x, next_y) = transform(next);
+                            let angle = (next_y - y)
+                                .atan2(next_x - x)
+                                .rem_euclid(std::f32::consts::TAU);
+                            let pressure = point.pressure as f32 / brush.max_pressure;
+
+                            points.data.extend_from_slice(&x.to_le_bytes());
+                            points.data.extend_from_slice(&y.to_le_bytes());
+                            // Uncertain: pen speed is not recorded by Boox devices
+                            points.data.extend_from_slice(&0u16.to_le_bytes());
+                            let width = (brush.point_width(point) * scale * 4.0).round() as u16;
+                            points.data.extend_from_slice(&width.to_le_bytes());
+                            points
+                                .data
+                                .push((angle / std::f32::consts::TAU * 255.0).round() as u8);
+                            points
+                                .data
+                                .push((pressure.clamp(0.0, 1.0) * 255.0).round() as u8);
+                        }
+                    });
+                    value.id(6, CrdtId(0, 1));
+                });
+            });
+            left_id = item_id;
+        }
+    }
+
+    writer.write_all(HEADER)?;
+    writer.write_all(&blocks)?;
+
+    Ok(())
+}
+
+/// Maps the pen of a stroke to the closest reMarkable tool.
+fn tool(pen_type: PenType) -> u32 {
+    match pen_type {
+        PenType::Brush => 12,
+        PenType::Pencil | PenType::Charcoal => 14,
+        PenType::Ballpoint => 15,
+        PenType::Marker => 16,
+        PenType::Highlighter => 18,
+        PenType::Fountain => 21,
+        PenType::Unknown(_) => 17,
+    }
+}
+
+/// Picks the reMarkable pen color closest to the color of a stroke.
+fn color(color: Color) -> u32 {
+    let distance = |other: &Color| {
+        [
+            (color.red(), other.red()),
+            (color.green(), other.green()),
+            (color.blue(), other.blue()),
+        ]
+        .iter()
+        .map(|(a, b)| (*a as i32 - *b as i32).pow(2))
+        .sum::<i32>()
+    };
+    PEN_COLORS
+        .iter()
+        .min_by_key(|(_, pen_color)| distance(pen_color))
+        .map(|(index, _)| *index)
+        .unwrap_or_default()
+}
+
+fn write_block(
+    blocks: &mut Vec<u8>,
+    block_type: u8,
+    min_version: u8,
+    current_version: u8,
+    content_fn: impl FnOnce(&mut TaggedWriter),
+) {
+    let mut content = TaggedWriter::default();
+    content_fn(&mut content);
+    blocks.extend_from_slice(&(content.data.len() as u32).to_le_bytes());
+    blocks.extend_from_slice(&[0, min_version, current_version, block_type]);
+    blocks.extend_from_slice(&content.data);
+}
+
+/// Writes values prefixed with a tag of their index and type.
+#[derive(Default)]
+struct TaggedWriter {
+    data: Vec<u8>,
+}
+
+impl TaggedWriter {
+    fn varuint(&mut self, mut value: u64) {
+        loop {
+            let byte = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                self.data.push(byte);
+                return;
+            }
+            self.data.push(byte | 0x80);
+        }
+    }
+
+    fn tag(&mut self, index: u8, tag_type: u8) {
+        self.varuint(((index as u64) << 4) | tag_type as u64);
+    }
+
+    fn id(&mut self, index: u8, id: CrdtId) {
+        self.tag(index, TAG_ID);
+        self.data.push(id.0);
+        self.varuint(id.1);
+    }
+
+    fn bool(&mut self, index: u8, value: bool) {
+        self.tag(index, TAG_BYTE1);
+        self.data.push(value as u8);
+    }
+
+    fn int(&mut self, index: u8, value: u32) {
+        self.tag(index, TAG_BYTE4);
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn float(&mut self, index: u8, value: f32) {
+        self.tag(index, TAG_BYTE4);
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn double(&mut self, index: u8, value: f64) {
+        self.tag(index, TAG_BYTE8);
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn subblock(&mut self, index: u8, content_fn: impl FnOnce(&mut TaggedWriter)) {
+        let mut content = TaggedWriter::default();
+        content_fn(&mut content);
+        self.tag(index, TAG_LENGTH4);
+        self.data
+            .extend_from_slice(&(content.data.len() as u32).to_le_bytes());
+        self.data.extend_from_slice(&content.data);
+    }
+
+    fn string(&mut self, index: u8, value: &str) {
+        self.subblock(index, |string| {
+            string.varuint(value.len() as u64);
+            string.data.push(value.is_ascii() as u8);
+            string.data.extend_from_slice(value.as_bytes());
+        });
+    }
+
+    /// Writes a last-writer-wins register holding a string.
+    fn lww_string(&mut self, index: u8, timestamp: CrdtId, value: &str) {
+        self.subblock(index, |register| {
+            register.id(1, timestamp);
+            register.string(2, value);
+        });
+    }
+
+    /// Writes a last-writer-wins register holding a bool.
+    fn lww_bool(&mut self, index: u8, timestamp: CrdtId, value: bool) {
+        self.subblock(index, |register| {
+            register.id(1, timestamp);
+            register.bool(2, value);
+        });
+    }
+
+    /// Writes a scene item appended to the sequence of its parent after `left_id`.
+    fn item(
+        &mut self,
+        parent_id: CrdtId,
+        item_id: CrdtId,
+        left_id: CrdtId,
+        item_type: u8,
+        value_fn: impl FnOnce(&mut TaggedWriter),
+    ) {
+        self.id(1, parent_id);
+        self.id(2, item_id);
+        self.id(3, left_id);
+        self.id(4, ZERO);
+        self.int(5, 0);
+        self.subblock(6, |value| {
+            value.data.push(item_type);
+            value_fn(value);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{NoteFile, testing};
+
+    /// Size of a point record: x, y, speed, width, direction and pressure.
+    const POINT_SIZE: usize = 14;
+
+    struct Block {
+        block_type: u8,
+        min_version: u8,
+        current_version: u8,
+        content: Vec<u8>,
+    }
+
+    fn read_blocks(mut data: &[u8]) -> Vec<Block> {
+        let mut blocks = Vec::new();
+        while !data.is_empty() {
+            let length = u32::from_le_bytes(data[..4].try_into().unwrap()) as usize;
+            assert_eq!(data[4], 0);
+            blocks.push(Block {
+                block_type: data[7],
+                min_version: data[5],
+                current_version: data[6],
+                content: data[8..8 + length].to_vec(),
+            });
+            data = &data[8 + length..];
+        }
+        blocks
+    }
+
+    fn read_varuint(data: &mut &[u8]) -> u64 {
+        let mut value = 0;
+        for shift in (0..).step_by(7) {
+            let byte = data[0];
+            *data = &data[1..];
+            value |= ((byte & 0x7F) as u64) << shift;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        value
+    }
+
+    /// Reads tagged values as their index and data, with the length of subblocks removed.
+    fn read_tagged(mut data: &[u8]) -> Vec<(u8, Vec<u8>)> {
+        let mut values = Vec::new();
+        while !data.is_empty() {
+            let tag = read_varuint(&mut data);
+            let length = match tag as u8 & 0xF {
+                TAG_BYTE1 => 1,
+                TAG_BYTE4 => 4,
+                TAG_BYTE8 => 8,
+                TAG_LENGTH4 => {
+                    let length = u32::from_le_bytes(data[..4].try_into().unwrap()) as usize;
+                    data = &data[4..];
+                    length
+                }
+                TAG_ID => {
+                    let mut rest = &data[1..];
+                    read_varuint(&mut rest);
+                    data.len() - rest.len()
+                }
+                tag_type => panic!("Unknown tag type {}", tag_type),
+            };
+            values.push(((tag >> 4) as u8, data[..length].to_vec()));
+            data = &data[length..];
+        }
+        values
+    }
+
+    fn export(file: &NoteFile<Cursor<Vec<u8>>>, page_index: usize) -> Vec<u8> {
+        let note_id = *file.list_notes().keys().next().unwrap();
+        let mut note = file.get_note(&note_id).unwrap();
+        let page_id = note.active_pages()[page_index];
+        let mut buffer = Vec::new();
+        note.get_page(&page_id)
+            .unwrap()
+            .write_remarkable(&mut buffer)
+            .unwrap();
+        buffer
+    }
+
+    #[test]
+    fn writes_framed_blocks_and_point_records() {
+        let file = NoteFile::read(Cursor::new(testing::archive())).unwrap();
+        let note_id = *file.list_notes().keys().next().unwrap();
+        let scene = export(&file, 0);
+        assert_eq!(&scene[..43], HEADER);
+        let blocks = read_blocks(&scene[43..]);
+
+        // The author is the note, whichever page is written
+        assert_eq!(blocks[0].block_type, AUTHOR_IDS);
+        let authors = &blocks[0].content;
+        assert_eq!(authors[0], 1);
+        let entries = read_tagged(&authors[1..]);
+        let mut entry = entries[0].1.as_slice();
+        assert_eq!(read_varuint(&mut entry), 16);
+        assert_eq!(entry[..16], note_id.uuid().to_bytes_le());
+        assert_eq!(entry[16..], (AUTHOR as u16).to_le_bytes());
+        assert_eq!(
+            &export(&file, 1)[43..][..8 + authors.len()],
+            &scene[43..][..8 + authors.len()]
+        );
+
+        let lines = blocks
+            .iter()
+            .filter(|block| block.block_type == SCENE_LINE_ITEM)
+            .collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        for line in lines {
+            assert_eq!((line.min_version, line.current_version), (2, 2));
+            let item = read_tagged(&line.content);
+            let (index, value) = item.last().unwrap();
+            assert_eq!(*index, 6);
+            assert_eq!(value[0], ITEM_LINE);
+            let (_, points) = read_tagged(&value[1..])
+                .into_iter()
+                .find(|(index, _)| *index == 5)
+                .unwrap();
+            assert_eq!(points.len(), 5 * POINT_SIZE);
+            for point in points.chunks(POINT_SIZE) {
+                let x = f32::from_le_bytes(point[0..4].try_into().unwrap());
+                let y = f32::from_le_bytes(point[4..8].try_into().unwrap());
+                assert!(x.abs() <= CANVAS_WIDTH / 2.0);
+                assert!((0.0..=CANVAS_HEIGHT).contains(&y));
+                assert!(u16::from_le_bytes(point[10..12].try_into().unwrap()) > 0);
+            }
+        }
+    }
+
+    #[test]
+    fn exports_device_samples() {
+        for (label, mut note) in testing::device_sample_notes() {
+            for page_id in note.active_pages().to_vec() {
+                let mut page = note.get_page(&page_id).unwrap();
+                let mut points = page
+                    .strokes()
+                    .unwrap()
+                    .iter()
+                    .filter(|(_, stroke)| !stroke.points.is_empty())
+                    .map(|(_, stroke)| stroke.points.len() * POINT_SIZE)
+                    .collect::<Vec<_>>();
+                let mut scene = Vec::new();
+                page.write_remarkable(&mut scene).unwrap();
+                assert_eq!(&scene[..43], HEADER, "{}", label);
+
+                // Lines are grouped by layer
+                let mut written = read_blocks(&scene[43..])
+                    .iter()
+                    .filter(|block| block.block_type == SCENE_LINE_ITEM)
+                    .map(|line| {
+                        let item = read_tagged(&line.content);
+                        let (_, value) = item.last().unwrap();
+                        read_tagged(&value[1..])
+                            .into_iter()
+                            .find(|(index, _)| *index == 5)
+                            .map(|(_, points)| points.len())
+                            .unwrap()
+                    })
+                    .collect::<Vec<_>>();
+                points.sort();
+                written.sort();
+                assert_eq!(written, points, "{}", label);
+            }
+        }
+    }
+}
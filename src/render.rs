@@ -0,0 +1,420 @@
+use std::collections::HashMap;
+
+use raqote::{DrawOptions, DrawTarget, Image, PathBuilder, Source, StrokeStyle, Transform};
+
+use crate::{
+    background::{LoadedBackground, PATTERN_COLOR, PATTERN_DOT_RADIUS, PATTERN_LINE_WIDTH},
+    brush::{Blend, Brush},
+    color::Color,
+    json::Dimensions,
+    note_tree::NoteMetadata,
+    shape::Shape,
+};
+
+/// Screen resolution of the device, in pixels per inch.
+// Uncertain: matches the 300 ppi Carta panels of current devices, color panels use 150 ppi
+const DEVICE_PPI: f32 = 300.0;
+
+/// Options for rendering pages to raster and vector output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderOptions {
+    /// Draws the page template or background image under the ink.
+    pub background: bool,
+    /// Size of the raster output. Vector output keeps the physical page size.
+    pub size: RenderSize,
+    /// Part of the page to render, in page coordinates. Defaults to the whole page.
+    pub crop: Option<Rect>,
+    /// Renders decimated strokes without anti-aliasing, for fast low resolution previews.
+    /// Only applies to raster output.
+    pub draft: bool,
+    /// Colors the page and its ink are drawn with.
+    pub palette: Palette,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            background: true,
+            size: RenderSize::default(),
+            crop: None,
+            draft: false,
+            palette: Palette::default(),
+        }
+    }
+}
+
+/// Remaps the colors of the page background and the ink.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Palette {
+    /// Keeps the colors of the note.
+    #[default]
+    Original,
+    /// Inverts the lightness of every color while keeping its hue, so the page turns black and
+    /// black ink turns white.
+    Dark,
+    /// Replaces colors by exact ARGB value, e.g. the colors of the quick pens. The page takes the
+    /// color white is mapped to. Colors without a mapping are kept.
+    Custom(HashMap<Color, Color>),
+}
+
+impl Palette {
+    pub fn map(&self, color: Color) -> Color {
+        match self {
+            Self::Original => color,
+            Self::Dark => color.invert_lightness(),
+            Self::Custom(colors) => colors.get(&color).copied().unwrap_or(color),
+        }
+    }
+
+    /// Returns the color of the page.
+    pub fn background(&self) -> Color {
+        self.map(Color::WHITE)
+    }
+
+    /// Returns `true` if the page is dark, in which case translucent markers lighten instead of
+    /// darken it.
+    pub fn is_dark(&self) -> bool {
+        self.background().lightness() < 0.5
+    }
+
+    /// Returns the brush of a shape with its color remapped.
+    pub fn brush(&self, shape: &Shape) -> Brush {
+        let mut brush = Brush::from_shape(shape);
+        brush.color = self.map(brush.color);
+        if brush.blend == Blend::Multiply && self.is_dark() {
+            brush.blend = Blend::Screen;
+        }
+        brush
+    }
+}
+
+/// How the rendered region is scaled into the output.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RenderSize {
+    /// Output pixels per page unit.
+    Scale(f32),
+    /// Output resolution in dots per inch of the physical page.
+    Dpi(f32),
+    /// Scales the region to the given output width in pixels.
+    FitWidth(u32),
+    /// Scales the region to the given output height in pixels.
+    FitHeight(u32),
+    /// Scales the region to fit into the given box, keeping its aspect ratio.
+    Fit { width: u32, height: u32 },
+}
+
+impl Default for RenderSize {
+    fn default() -> Self {
+        Self::Scale(1.0)
+    }
+}
+
+/// Rectangle in page coordinates.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rect {
+    pub left: f32,
+    pub top: f32,
+    pub right: f32,
+    pub bottom: f32,
+}
+
+impl Rect {
+    pub fn width(&self) -> f32 {
+        self.right - self.left
+    }
+
+    pub fn height(&self) -> f32 {
+        self.bottom - self.top
+    }
+
+    /// Returns the smallest rectangle containing both rectangles.
+    pub fn union(&self, other: &Rect) -> Rect {
+        Rect {
+            left: self.left.min(other.left),
+            top: self.top.min(other.top),
+            right: self.right.max(other.right),
+            bottom: self.bottom.max(other.bottom),
+        }
+    }
+}
+
+impl From<&Dimensions> for Rect {
+    fn from(dimensions: &Dimensions) -> Self {
+        Self {
+            left: dimensions.left,
+            top: dimensions.top,
+            right: dimensions.right,
+            bottom: dimensions.bottom,
+        }
+    }
+}
+
+/// Maps a region of the page onto the output.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Viewport {
+    /// Rendered region, in page coordinates.
+    pub region: Rect,
+    /// Output units per page unit.
+    pub scale: f32,
+}
+
+impl Viewport {
+    pub fn new(region: Rect, size: RenderSize, units_per_inch: f32) -> Self {
+        let scale = match size {
+            RenderSize::Scale(scale) => scale,
+            RenderSize::Dpi(dpi) => dpi / units_per_inch,
+            RenderSize::FitWidth(width) => width as f32 / region.width(),
+            RenderSize::FitHeight(height) => height as f32 / region.height(),
+            RenderSize::Fit { width, height } => {
+                (width as f32 / region.width()).min(height as f32 / region.height())
+            }
+        };
+        Self { region, scale }
+    }
+
+    /// Width of the raster output in pixels.
+    pub fn width(&self) -> i32 {
+        (self.region.width() * self.scale).round().max(1.0) as i32
+    }
+
+    /// Height of the raster output in pixels.
+    pub fn height(&self) -> i32 {
+        (self.region.height() * self.scale).round().max(1.0) as i32
+    }
+
+    /// Returns the transform from page coordinates into output pixels.
+    pub fn transform(&self) -> Transform {
+        Transform::translation(-self.region.left, -self.region.top)
+            .then_scale(self.scale, self.scale)
+    }
+}
+
+/// Returns the number of page units per inch on the device the note was written on.
+///
+/// The default page rect covers the device screen, so its ratio to the screen size in pixels
+/// gives the page units per device pixel. The note's scale factor is used when either is
+/// missing.
+pub(crate) fn units_per_inch(metadata: &NoteMetadata) -> f32 {
+    let screen = &metadata.device_info.size;
+    let page = &metadata.canvas_state.default_page_rect;
+    let screen_width = screen.width.min(screen.height);
+    let page_width = page.width().min(page.height());
+
+    let units_per_pixel = if screen_width > 0.0 && page_width > 0.0 {
+        page_width / screen_width
+    } else if metadata.scale_factor > 0.0 {
+        metadata.scale_factor
+    } else {
+        1.0
+    };
+    DEVICE_PPI * units_per_pixel
+}
+
+/// Fills the output with the page color and draws the page background on top. Images cover
+/// the page rect, patterns the whole rendered area.
+pub(crate) fn draw_background(
+    draw_target: &mut DrawTarget,
+    page: &Rect,
+    area: &Rect,
+    background: &LoadedBackground,
+    palette: &Palette,
+) {
+    draw_target.clear(palette.background().into());
+
+    if let Some(image) = &background.image {
+        let data = image.premultiplied_argb();
+        draw_target.draw_image_with_size_at(
+            page.width(),
+            page.height(),
+            page.left,
+            page.top,
+            &Image {
+                width: image.width as i32,
+                height: image.height as i32,
+                data: &data,
+            },
+            &DrawOptions::new(),
+        );
+    }
+
+    let lines = background.background.pattern_lines(page, area);
+    if !lines.is_empty() {
+        let mut path = PathBuilder::new();
+        for (start, end) in lines {
+            path.move_to(start.0, start.1);
+            path.line_to(end.0, end.1);
+        }
+        draw_target.stroke(
+            &path.finish(),
+            &Source::Solid(palette.map(PATTERN_COLOR).into()),
+            &StrokeStyle {
+                width: PATTERN_LINE_WIDTH,
+                ..StrokeStyle::default()
+            },
+            &DrawOptions::new(),
+        );
+    }
+
+    let dots = background.background.pattern_dots(page, area);
+    if !dots.is_empty() {
+        let mut path = PathBuilder::new();
+        for (x, y) in dots {
+            path.move_to(x + PATTERN_DOT_RADIUS, y);
+            path.arc(x, y, PATTERN_DOT_RADIUS, 0.0, 2.0 * std::f32::consts::PI);
+            path.close();
+        }
+        draw_target.fill(
+            &path.finish(),
+            &Source::Solid(palette.map(PATTERN_COLOR).into()),
+            &DrawOptions::new(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{NoteFile, brush::PenType, testing};
+
+    const REGION: Rect = Rect {
+        left: 100.0,
+        top: 50.0,
+        right: 300.0,
+        bottom: 150.0,
+    };
+
+    #[test]
+    fn render_sizes_scale_the_region() {
+        for (size, scale, width, height) in [
+            (RenderSize::Scale(2.0), 2.0, 400, 200),
+            // 200 page units per inch
+            (RenderSize::Dpi(300.0), 1.5, 300, 150),
+            (RenderSize::FitWidth(100), 0.5, 100, 50),
+            (RenderSize::FitHeight(400), 4.0, 800, 400),
+            // The narrower fit wins
+            (
+                RenderSize::Fit {
+                    width: 100,
+                    height: 100,
+                },
+                0.5,
+                100,
+                50,
+            ),
+            (
+                RenderSize::Fit {
+                    width: 1000,
+                    height: 100,
+                },
+                1.0,
+                200,
+                100,
+            ),
+        ] {
+            let viewport = Viewport::new(REGION, size, 200.0);
+            assert_eq!(viewport.scale, scale, "{:?}", size);
+            assert_eq!((viewport.width(), viewport.height()), (width, height));
+        }
+
+        // Outputs are at least a pixel in size
+        let viewport = Viewport::new(REGION, RenderSize::Scale(0.001), 200.0);
+        assert_eq!((viewport.width(), viewport.height()), (1, 1));
+    }
+
+    #[test]
+    fn transform_maps_the_region_onto_the_output() {
+        let viewport = Viewport::new(REGION, RenderSize::Scale(2.0), 200.0);
+        let transform = viewport.transform();
+        let top_left = transform.transform_point(raqote::Point::new(REGION.left, REGION.top));
+        let bottom_right =
+            transform.transform_point(raqote::Point::new(REGION.right, REGION.bottom));
+        assert_eq!((top_left.x, top_left.y), (0.0, 0.0));
+        assert_eq!((bottom_right.x, bottom_right.y), (400.0, 200.0));
+    }
+
+    #[test]
+    fn crop_selects_the_rendered_region() {
+        let file = NoteFile::read(Cursor::new(testing::archive())).unwrap();
+        let note_id = *file.list_notes().keys().next().unwrap();
+        let mut note = file.get_note(&note_id).unwrap();
+        let page_id = note.active_pages()[0];
+        let mut page = note.get_page(&page_id).unwrap();
+
+        let whole = page.viewport(&RenderOptions::default()).unwrap();
+        assert_eq!(whole.region, page.page_rect());
+
+        let options = RenderOptions {
+            crop: Some(REGION),
+            size: RenderSize::Scale(2.0),
+            ..Default::default()
+        };
+        assert_eq!(page.viewport(&options).unwrap().region, REGION);
+        let draw_target = page.render_with_options(&options).unwrap();
+        assert_eq!((draw_target.width(), draw_target.height()), (400, 200));
+    }
+
+    #[test]
+    fn palettes_map_colors() {
+        let ink = Color::from_argb(0x8033_66AA);
+        assert_eq!(Palette::Original.map(ink), ink);
+        assert_eq!(Palette::Original.background(), Color::WHITE);
+        assert!(!Palette::Original.is_dark());
+
+        // Lightness is inverted, hue and alpha are kept
+        assert_eq!(Palette::Dark.map(Color::WHITE), Color::BLACK);
+        assert_eq!(Palette::Dark.map(Color::BLACK), Color::WHITE);
+        assert_eq!(Palette::Dark.map(ink), Color::from_argb(0x8055_88CC));
+        assert!(Palette::Dark.is_dark());
+
+        let custom = Palette::Custom(HashMap::from([
+            (Color::WHITE, Color::from_argb(0xFF20_2020)),
+            (ink, Color::from_argb(0xFFFF_0000)),
+        ]));
+        assert_eq!(custom.map(ink), Color::from_argb(0xFFFF_0000));
+        assert_eq!(custom.map(Color::BLACK), Color::BLACK);
+        assert_eq!(custom.background(), Color::from_argb(0xFF20_2020));
+        assert!(custom.is_dark());
+    }
+
+    #[test]
+    fn markers_lighten_dark_pages() {
+        let strokes = testing::strokes(1);
+        let (highlighter, _) = &strokes[0];
+        assert_eq!(highlighter.pen_type, Some(PenType::Highlighter));
+
+        let brush = Palette::Original.brush(highlighter);
+        assert_eq!(brush.blend, Blend::Multiply);
+        assert_eq!(brush.color, Color::from_argb(0xFF33_66AA));
+
+        let brush = Palette::Dark.brush(highlighter);
+        assert_eq!(brush.blend, Blend::Screen);
+        assert_eq!(
+            brush.color,
+            Color::from_argb(0xFF33_66AA).invert_lightness()
+        );
+    }
+
+    #[test]
+    fn units_per_inch_follow_the_device_screen() {
+        let mut metadata = testing::metadata();
+        metadata.device_info.size = crate::note_tree::DeviceDimensions {
+            width: 1000.0,
+            height: 2000.0,
+        };
+        metadata.canvas_state.default_page_rect.left = 0.0;
+        metadata.canvas_state.default_page_rect.right = 500.0;
+        metadata.canvas_state.default_page_rect.top = 0.0;
+        metadata.canvas_state.default_page_rect.bottom = 1000.0;
+        // Half a page unit per device pixel
+        assert_eq!(units_per_inch(&metadata), DEVICE_PPI / 2.0);
+
+        metadata.device_info.size.width = 0.0;
+        metadata.scale_factor = 2.0;
+        assert_eq!(units_per_inch(&metadata), DEVICE_PPI * 2.0);
+
+        metadata.scale_factor = 0.0;
+        assert_eq!(units_per_inch(&metadata), DEVICE_PPI);
+    }
+}
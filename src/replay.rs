@@ -0,0 +1,445 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use raqote::{DrawOptions, DrawTarget};
+
+use crate::{
+    error::{Error, Result},
+    points::Stroke,
+    render::{Palette, RenderOptions},
+    shape::Shape,
+};
+
+/// Options for replaying how a page was written.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayOptions {
+    pub render: RenderOptions,
+    /// Playback speed relative to the original writing speed.
+    pub speed: f32,
+    /// Frames per second of the replay.
+    pub fps: f32,
+    /// Longest pause between two strokes kept in the replay, in original time.
+    pub max_pause: Duration,
+}
+
+impl Default for ReplayOptions {
+    fn default() -> Self {
+        Self {
+            render: RenderOptions::default(),
+            speed: 1.0,
+            fps: 10.0,
+            max_pause: Duration::from_secs(1),
+        }
+    }
+}
+
+/// A rendered frame of a replay.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub width: u32,
+    pub height: u32,
+    /// Premultiplied ARGB pixels, as rendered by raqote.
+    pub data: Vec<u32>,
+    /// Time the frame is shown for.
+    pub delay: Duration,
+}
+
+impl Frame {
+    /// Returns the pixels as non-premultiplied RGBA bytes.
+    pub fn to_rgba(&self) -> Vec<u8> {
+        self.data
+            .iter()
+            .flat_map(|pixel| {
+                let alpha = (pixel >> 24) as u8;
+                let unpremultiply = |channel: u32| {
+                    if alpha == 0 {
+                        0
+                    } else {
+                        ((channel & 0xFF) * 255 / alpha as u32).min(255) as u8
+                    }
+                };
+                [
+                    unpremultiply(pixel >> 16),
+                    unpremultiply(pixel >> 8),
+                    unpremultiply(*pixel),
+                    alpha,
+                ]
+            })
+            .collect()
+    }
+
+    pub fn write_png(&self, writer: impl Write) -> Result {
+        let mut encoder = png::Encoder::new(writer, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.to_rgba())?;
+        writer.finish()?;
+        Ok(())
+    }
+}
+
+/// A stroke placed on the replay timeline.
+struct TimedStroke {
+    shape: Shape,
+    stroke: Stroke,
+    /// Replay time at which the first point is drawn, in milliseconds of original time.
+    start: f32,
+    /// Time of every point relative to the first one, in milliseconds.
+    times: Vec<f32>,
+}
+
+impl TimedStroke {
+    fn end(&self) -> f32 {
+        self.start + self.times.last().copied().unwrap_or_default()
+    }
+}
+
+/// Iterator over the frames of a page replay, created by [`crate::Page::replay`].
+///
+/// Strokes appear in the order they were written, point by point, with pauses between strokes
+/// shortened to the configured maximum.
+pub struct Replay {
+    draw_target: DrawTarget,
+    strokes: Vec<TimedStroke>,
+    /// Number of strokes already drawn onto `draw_target`.
+    finished: usize,
+    frame: usize,
+    frame_count: usize,
+    /// Original time covered by a frame, in milliseconds.
+    step: f32,
+    delay: Duration,
+    palette: Palette,
+}
+
+impl Replay {
+    pub(crate) fn new(
+        draw_target: DrawTarget,
+        mut strokes: Vec<(Shape, Stroke)>,
+        options: &ReplayOptions,
+    ) -> Self {
+        strokes.sort_by_key(|(shape, _)| shape.created);
+
+        let max_pause = options.max_pause.as_secs_f32() * 1000.0;
+        let mut clock = 0.0;
+        let mut previous_end = None;
+        let strokes = strokes
+            .into_iter()
+            .map(|(shape, stroke)| {
+                let first = stroke
+                    .points
+                    .first()
+                    .map(|point| point.timestamp_rel)
+                    .unwrap_or_default();
+                let times = stroke
+                    .points
+                    .iter()
+                    .map(|point| point.timestamp_rel.saturating_sub(first) as f32)
+                    .collect::<Vec<_>>();
+                let duration = times.last().copied().unwrap_or_default();
+
+                let created = shape.created.timestamp_millis();
+                let pause = previous_end
+                    .map(|end| (created - end) as f32)
+                    .unwrap_or_default()
+                    .clamp(0.0, max_pause);
+                let start = clock + pause;
+                clock = start + duration;
+                previous_end = Some(created + duration as i64);
+
+                TimedStroke {
+                    shape,
+                    stroke,
+                    start,
+                    times,
+                }
+            })
+            .collect();
+
+        let fps = options.fps.max(f32::EPSILON);
+        let step = 1000.0 * options.speed.max(f32::EPSILON) / fps;
+        Self {
+            draw_target,
+            strokes,
+            finished: 0,
+            frame: 0,
+            frame_count: (clock / step).ceil() as usize + 1,
+            step,
+            delay: Duration::from_secs_f32(1.0 / fps),
+            palette: options.render.palette.clone(),
+        }
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frame_count
+    }
+
+    /// Returns the playback duration of the replay.
+    pub fn duration(&self) -> Duration {
+        self.delay * self.frame_count as u32
+    }
+
+    fn render_frame(&mut self, time: f32) -> Result<Frame> {
+        let draw_options = DrawOptions::new();
+
+        // Strokes are drawn onto the shared target once complete, only the stroke in progress
+        // is drawn per frame.
+        while let Some(timed) = self.strokes.get(self.finished)
+            && timed.end() <= time
+        {
+            timed.stroke.render(
+                &mut self.draw_target,
+                &draw_options,
+                &self.palette.brush(&timed.shape),
+            )?;
+            self.finished += 1;
+        }
+
+        let mut draw_target = DrawTarget::from_vec(
+            self.draw_target.width(),
+            self.draw_target.height(),
+            self.draw_target.get_data().to_vec(),
+        );
+        draw_target.set_transform(self.draw_target.get_transform());
+        for timed in self.strokes[self.finished..]
+            .iter()
+            .take_while(|timed| timed.start <= time)
+        {
+            let count = timed
+                .times
+                .iter()
+                .take_while(|point_time| timed.start + **point_time <= time)
+                .count()
+                .max(1);
+            let partial = Stroke {
+                points: timed.stroke.points[..count].to_vec(),
+            };
+            partial.render(
+                &mut draw_target,
+                &draw_options,
+                &self.palette.brush(&timed.shape),
+            )?;
+        }
+
+        Ok(Frame {
+            width: draw_target.width() as u32,
+            height: draw_target.height() as u32,
+            data: draw_target.into_vec(),
+            delay: self.delay,
+        })
+    }
+}
+
+impl Iterator for Replay {
+    type Item = Result<Frame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.frame >= self.frame_count {
+            return None;
+        }
+        let time = self.frame as f32 * self.step;
+        self.frame += 1;
+        Some(self.render_frame(time))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.frame_count - self.frame;
+        (remaining, Some(remaining))
+    }
+}
+
+impl ExactSizeIterator for Replay {}
+
+/// Writes the frames as an animated PNG that loops forever. Frames are encoded as they are
+/// yielded, so a [`Replay`] can be written without keeping all of its frames in memory.
+pub fn write_apng<I>(writer: impl Write, frames: I) -> Result
+where
+    I: IntoIterator<Item = Result<Frame>>,
+    I::IntoIter: ExactSizeIterator,
+{
+    let mut frames = frames.into_iter();
+    let frame_count = frames.len();
+    let first = frames.next().ok_or(Error::EmptyReplay)??;
+
+    let mut encoder = png::Encoder::new(writer, first.width, first.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_animated(frame_count as u32, 0)?;
+    let mut writer = encoder.write_header()?;
+    for frame in std::iter::once(Ok(first)).chain(frames) {
+        let frame = frame?;
+        let delay = frame.delay.as_millis().min(u16::MAX as u128) as u16;
+        writer.set_frame_delay(delay, 1000)?;
+        writer.write_image_data(&frame.to_rgba())?;
+    }
+    writer.finish()?;
+
+    Ok(())
+}
+
+/// Writes the frames as an animated GIF that loops forever, encoding them as they are yielded.
+pub fn write_gif(writer: impl Write, frames: impl IntoIterator<Item = Result<Frame>>) -> Result {
+    let mut frames = frames.into_iter();
+    let first = frames.next().ok_or(Error::EmptyReplay)??;
+    let (Ok(width), Ok(height)) = (u16::try_from(first.width), u16::try_from(first.height)) else {
+        return Err(Error::UnsupportedImage(format!(
+            "{}x{} exceeds the maximum GIF size",
+            first.width, first.height
+        )));
+    };
+
+    let mut encoder = gif::Encoder::new(writer, width, height, &[])?;
+    encoder.set_repeat(gif::Repeat::Infinite)?;
+    for frame in std::iter::once(Ok(first)).chain(frames) {
+        let frame = frame?;
+        let mut rgba = frame.to_rgba();
+        let mut gif_frame = gif::Frame::from_rgba_speed(width, height, &mut rgba, 10);
+        // GIF delays are given in hundredths of a second
+        gif_frame.delay = (frame.delay.as_millis() / 10).min(u16::MAX as u128) as u16;
+        encoder.write_frame(&gif_frame)?;
+    }
+
+    Ok(())
+}
+
+/// Writes every frame to a numbered PNG file in `directory` as it is yielded and returns the
+/// file paths.
+pub fn write_png_sequence(
+    directory: impl AsRef<Path>,
+    frames: impl IntoIterator<Item = Result<Frame>>,
+) -> Result<Vec<PathBuf>> {
+    let directory = directory.as_ref();
+    std::fs::create_dir_all(directory)?;
+
+    frames
+        .into_iter()
+        .enumerate()
+        .map(|(index, frame)| {
+            let path = directory.join(format!("frame_{:05}.png", index));
+            let file = std::io::BufWriter::new(std::fs::File::create(&path)?);
+            frame?.write_png(file)?;
+            Ok(path)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use chrono::TimeDelta;
+
+    use super::*;
+    use crate::{NoteFile, testing};
+
+    const FPS: f32 = 10.0;
+
+    /// The two strokes of the first test page, written 400 ms each with points 100 ms apart,
+    /// the second one starting 5 s after the first ended.
+    fn strokes() -> Vec<(Shape, Stroke)> {
+        let file = NoteFile::read(Cursor::new(testing::archive())).unwrap();
+        let note_id = *file.list_notes().keys().next().unwrap();
+        let mut note = file.get_note(&note_id).unwrap();
+        let page_id = note.active_pages()[0];
+        let mut strokes = note.get_page(&page_id).unwrap().strokes().unwrap();
+        strokes.sort_by_key(|(shape, _)| shape.created);
+
+        let created = strokes[0].0.created;
+        for (index, (shape, stroke)) in strokes.iter_mut().enumerate() {
+            shape.created = created + TimeDelta::milliseconds(index as i64 * 5400);
+            for (point_index, point) in stroke.points.iter_mut().enumerate() {
+                point.timestamp_rel = 7000 + point_index as u32 * 100;
+            }
+        }
+        strokes
+    }
+
+    fn replay(strokes: Vec<(Shape, Stroke)>) -> Replay {
+        let options = ReplayOptions {
+            fps: FPS,
+            ..Default::default()
+        };
+        Replay::new(DrawTarget::new(400, 400), strokes, &options)
+    }
+
+    fn painted(frame: &Frame) -> usize {
+        frame.data.iter().filter(|pixel| **pixel >> 24 != 0).count()
+    }
+
+    #[test]
+    fn pauses_are_shortened() {
+        let replay = replay(strokes());
+        // The 5 s pause is shortened to 1 s
+        let starts = replay
+            .strokes
+            .iter()
+            .map(|timed| timed.start)
+            .collect::<Vec<_>>();
+        assert_eq!(starts, [0.0, 1400.0]);
+        assert_eq!(replay.strokes[1].end(), 1800.0);
+        assert_eq!(replay.frame_count(), 19);
+        assert_eq!(replay.len(), 19);
+        assert_eq!(replay.duration(), Duration::from_secs_f32(1.0 / FPS) * 19);
+    }
+
+    #[test]
+    fn overlapping_strokes_have_no_pause() {
+        let mut strokes = strokes();
+        strokes[1].0.created = strokes[0].0.created + TimeDelta::milliseconds(100);
+        let replay = replay(strokes);
+        assert_eq!(replay.strokes[1].start, 400.0);
+        assert_eq!(replay.frame_count(), 9);
+    }
+
+    #[test]
+    fn strokes_are_drawn_point_by_point() {
+        let strokes = strokes();
+        let frames = replay(strokes.clone()).collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(frames.len(), 19);
+
+        let painted = frames.iter().map(painted).collect::<Vec<_>>();
+        assert!(painted.windows(2).all(|pair| pair[0] <= pair[1]));
+        // Partial strokes while the first stroke is written, nothing new during the pause
+        assert!(painted[1] < painted[2] && painted[2] < painted[4]);
+        assert_eq!(painted[4], painted[13]);
+        assert!(painted[13] < painted[18]);
+
+        let mut draw_target = DrawTarget::new(400, 400);
+        let palette = Palette::default();
+        for (shape, stroke) in &strokes {
+            stroke
+                .render(&mut draw_target, &DrawOptions::new(), &palette.brush(shape))
+                .unwrap();
+        }
+        assert_eq!(frames.last().unwrap().data, draw_target.into_vec());
+    }
+
+    #[test]
+    fn frames_are_written_as_they_are_rendered() {
+        let mut buffer = Vec::new();
+        write_apng(&mut buffer, replay(strokes())).unwrap();
+        let mut decoder = png::Decoder::new(Cursor::new(buffer)).read_info().unwrap();
+        assert_eq!(decoder.info().animation_control().unwrap().num_frames, 19);
+        let mut frame = vec![0; decoder.output_buffer_size()];
+        for _ in 0..19 {
+            decoder.next_frame(&mut frame).unwrap();
+        }
+
+        let mut buffer = Vec::new();
+        write_gif(&mut buffer, replay(strokes())).unwrap();
+        let mut decoder = gif::DecodeOptions::new()
+            .read_info(Cursor::new(buffer))
+            .unwrap();
+        let mut frames = 0;
+        while decoder.read_next_frame().unwrap().is_some() {
+            frames += 1;
+        }
+        assert_eq!(frames, 19);
+
+        let result = write_apng(Vec::new(), Vec::<Result<Frame>>::new());
+        assert!(matches!(result, Err(Error::EmptyReplay)));
+    }
+}
@@ -0,0 +1,594 @@
+use std::io::Write;
+
+use flate2::{Compression, write::GzEncoder};
+
+use crate::{
+    background::{Background, PATTERN_COLOR},
+    brush::{Brush, PenType},
+    color::Color,
+    error::{Error, Result},
+    points::Stroke,
+    render::Rect,
+    shape::Shape,
+};
+
+/// Rnote measures documents in pixels, 96 per inch.
+const PIXELS_PER_INCH: f32 = 96.0;
+
+// Uncertain: file format version the snapshot layout below was taken from
+const FILE_VERSION: &str = "0.9.0";
+
+// Uncertain: pattern size Rnote uses for new documents, written for pages without a pattern
+const DEFAULT_PATTERN_SIZE: f64 = 32.0;
+
+pub struct RnotePage {
+    /// Page rect in page coordinates.
+    pub page: Rect,
+    pub units_per_inch: f32,
+    pub background: Background,
+    pub strokes: Vec<(Shape, Stroke)>,
+}
+
+/// Writes the pages as a gzip compressed Rnote document. The pages are stacked vertically in
+/// a fixed size layout, with the page format set to the largest page.
+pub fn write_rnote(writer: impl Write, pages: &[RnotePage]) -> Result {
+    let scale = |page: &RnotePage| PIXELS_PER_INCH / page.units_per_inch;
+    let format_width = pages
+        .iter()
+        .map(|page| page.page.width() * scale(page))
+        .fold(0.0, f32::max);
+    let format_height = pages
+        .iter()
+        .map(|page| page.page.height() * scale(page))
+        .fold(0.0, f32::max);
+
+    let mut stroke_components = vec![json::Slot::sentinel()];
+    let mut chrono_components = vec![json::Slot::sentinel()];
+    for (index, page) in pages.iter().enumerate() {
+        let scale = scale(page);
+        let offset = index as f32 * format_height;
+        for (shape, stroke) in &page.strokes {
+            if stroke.points.is_empty() {
+                log::warn!("No points to export for stroke");
+                continue;
+            }
+
+            let brush = Brush::from_shape(shape);
+            let mut elements = stroke.points.iter().map(|point| json::Element {
+                pos: [
+                    ((point.x - page.page.left) * scale) as f64,
+                    ((point.y - page.page.top) * scale + offset) as f64,
+                ],
+                pressure: (point.pressure as f32 / brush.max_pressure).clamp(0.0, 1.0) as f64,
+            });
+            let Some(start) = elements.next() else {
+                continue;
+            };
+            let path = json::PenPath {
+                start,
+                segments: elements.map(|end| json::Segment::LineTo { end }).collect(),
+            };
+
+            stroke_components.push(json::Slot::occupied(json::Stroke::BrushStroke {
+                path,
+                style: style(&brush, scale),
+            }));
+            chrono_components.push(json::Slot::occupied(json::ChronoComponent {
+                t: chrono_components.len() as u32,
+                layer: match brush.pen_type {
+                    PenType::Highlighter => json::StrokeLayer::Highlighter,
+                    _ => json::StrokeLayer::UserLayer(0),
+                },
+            }));
+        }
+    }
+
+    // Rnote documents have a single background
+    if pages
+        .iter()
+        .any(|page| page.background != pages[0].background)
+    {
+        log::warn!("Pages have different backgrounds, exporting the first one to Rnote");
+    }
+    let (pattern, spacing) = match pages.first().map(|page| &page.background) {
+        Some(Background::Lined { spacing }) => ("lines", *spacing),
+        Some(Background::Grid { spacing }) => ("grid", *spacing),
+        Some(Background::Dotted { spacing }) => ("dots", *spacing),
+        Some(Background::Image { path }) => {
+            log::warn!("Background image not exported to Rnote: {}", path);
+            ("none", 0.0)
+        }
+        Some(Background::Blank) | None => ("none", 0.0),
+    };
+    let pattern_size = match pages.first() {
+        Some(page) if spacing > 0.0 => (spacing * scale(page)) as f64,
+        _ => DEFAULT_PATTERN_SIZE,
+    };
+
+    let file = json::File {
+        version: FILE_VERSION,
+        data: json::Data {
+            engine_snapshot: json::EngineSnapshot {
+                document: json::Document {
+                    x: 0.0,
+                    y: 0.0,
+                    width: format_width as f64,
+                    height: (format_height * pages.len().max(1) as f32) as f64,
+                    format: json::Format {
+                        width: format_width as f64,
+                        height: format_height as f64,
+                        dpi: PIXELS_PER_INCH as f64,
+                        orientation: if format_width > format_height {
+                            "landscape"
+                        } else {
+                            "portrait"
+                        },
+                        border_color: json::Color::from(PATTERN_COLOR),
+                        show_borders: true,
+                        show_origin_indicator: false,
+                    },
+                    background: json::Background {
+                        color: json::Color::from(Color::WHITE),
+                        pattern,
+                        pattern_size: [pattern_size, pattern_size],
+                        pattern_color: json::Color::from(PATTERN_COLOR),
+                    },
+                    layout: "fixed_size",
+                },
+                stroke_components,
+                chrono_components,
+            },
+        },
+    };
+
+    let mut writer = GzEncoder::new(writer, Compression::default());
+    serde_json::to_writer(&mut writer, &file).map_err(|e| Error::Json {
+        error: e,
+        json_string: String::new(),
+    })?;
+    writer.finish()?;
+
+    Ok(())
+}
+
+/// Picks the Rnote pen style closest to the pen the stroke was drawn with.
+fn style(brush: &Brush, scale: f32) -> json::Style {
+    let stroke_width = (brush.width * scale) as f64;
+    let stroke_color = Some(json::Color::from(brush.ink_color()));
+    match brush.pen_type {
+        PenType::Pencil | PenType::Charcoal => json::Style::Textured(json::TexturedOptions {
+            seed: None,
+            stroke_width,
+            stroke_color,
+            density: 5.0,
+            distribution: "normal",
+            pressure_curve: "linear",
+        }),
+        pen_type => json::Style::Smooth(json::SmoothOptions {
+            stroke_width,
+            stroke_color,
+            fill_color: None,
+            pressure_curve: match pen_type {
+                PenType::Marker | PenType::Highlighter => "const",
+                PenType::Ballpoint => "sqrt",
+                _ => "linear",
+            },
+            line_cap: match pen_type {
+                PenType::Marker | PenType::Highlighter => "straight",
+                _ => "round",
+            },
+        }),
+    }
+}
+
+mod json {
+    use serde::Serialize;
+
+    #[derive(Debug, Serialize)]
+    pub struct File {
+        pub version: &'static str,
+        pub data: Data,
+    }
+
+    #[derive(Debug, Serialize)]
+    pub struct Data {
+        pub engine_snapshot: EngineSnapshot,
+    }
+
+    #[derive(Debug, Serialize)]
+    pub struct EngineSnapshot {
+        pub document: Document,
+        pub stroke_components: Vec<Slot<Stroke>>,
+        pub chrono_components: Vec<Slot<ChronoComponent>>,
+    }
+
+    /// Slot of a serialized slot map. Odd versions mark occupied slots, the first slot is an
+    /// empty sentinel.
+    #[derive(Debug, Serialize)]
+    pub struct Slot<T> {
+        pub value: Option<T>,
+        pub version: u32,
+    }
+
+    impl<T> Slot<T> {
+        pub fn sentinel() -> Self {
+            Self {
+                value: None,
+                version: 0,
+            }
+        }
+
+        pub fn occupied(value: T) -> Self {
+            Self {
+                value: Some(value),
+                version: 1,
+            }
+        }
+    }
+
+    #[derive(Debug, Serialize)]
+    pub struct Document {
+        pub x: f64,
+        pub y: f64,
+        pub width: f64,
+        pub height: f64,
+        pub format: Format,
+        pub background: Background,
+        pub layout: &'static str,
+    }
+
+    #[derive(Debug, Serialize)]
+    pub struct Format {
+        pub width: f64,
+        pub height: f64,
+        pub dpi: f64,
+        pub orientation: &'static str,
+        pub border_color: Color,
+        pub show_borders: bool,
+        pub show_origin_indicator: bool,
+    }
+
+    #[derive(Debug, Serialize)]
+    pub struct Background {
+        pub color: Color,
+        pub pattern: &'static str,
+        pub pattern_size: [f64; 2],
+        pub pattern_color: Color,
+    }
+
+    #[derive(Debug, Serialize)]
+    pub struct Color {
+        pub r: f64,
+        pub g: f64,
+        pub b: f64,
+        pub a: f64,
+    }
+
+    impl From<crate::color::Color> for Color {
+        fn from(color: crate::color::Color) -> Self {
+            let channel = |value: u8| value as f64 / 255.0;
+            Self {
+                r: channel(color.red()),
+                g: channel(color.green()),
+                b: channel(color.blue()),
+                a: channel(color.alpha()),
+            }
+        }
+    }
+
+    #[derive(Debug, Serialize)]
+    pub enum Stroke {
+        #[serde(rename = "brushstroke")]
+        BrushStroke { path: PenPath, style: Style },
+    }
+
+    #[derive(Debug, Serialize)]
+    pub struct PenPath {
+        pub start: Element,
+        pub segments: Vec<Segment>,
+    }
+
+    #[derive(Debug, Serialize)]
+    pub struct Element {
+        pub pos: [f64; 2],
+        pub pressure: f64,
+    }
+
+    #[derive(Debug, Serialize)]
+    pub enum Segment {
+        #[serde(rename = "lineto")]
+        LineTo { end: Element },
+    }
+
+    #[derive(Debug, Serialize)]
+    pub enum Style {
+        #[serde(rename = "smooth")]
+        Smooth(SmoothOptions),
+        #[serde(rename = "textured")]
+        Textured(TexturedOptions),
+    }
+
+    #[derive(Debug, Serialize)]
+    pub struct SmoothOptions {
+        pub stroke_width: f64,
+        pub stroke_color: Option<Color>,
+        pub fill_color: Option<Color>,
+        pub pressure_curve: &'static str,
+        pub line_cap: &'static str,
+    }
+
+    #[derive(Debug, Serialize)]
+    pub struct TexturedOptions {
+        pub seed: Option<u64>,
+        pub stroke_width: f64,
+        pub stroke_color: Option<Color>,
+        pub density: f64,
+        pub distribution: &'static str,
+        pub pressure_curve: &'static str,
+    }
+
+    // Uncertain: strokes are assumed to be hidden unless they have a chrono component
+    #[derive(Debug, Serialize)]
+    pub struct ChronoComponent {
+        pub t: u32,
+        pub layer: StrokeLayer,
+    }
+
+    #[derive(Debug, Serialize)]
+    pub enum StrokeLayer {
+        #[serde(rename = "user_layer")]
+        UserLayer(u32),
+        #[serde(rename = "highlighter")]
+        Highlighter,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::BTreeSet,
+        io::{Cursor, Read},
+    };
+
+    use flate2::read::GzDecoder;
+    use serde_json::Value;
+
+    use crate::{NoteFile, testing};
+
+    // Uncertain: layout of the snapshot as serialized by the serde types of rnote-engine 0.9,
+    // with one array element per variant. Strings list the accepted values separated by `|`.
+    const LAYOUT: &str = r#"{
+        "version": "0.9.0",
+        "data": {
+            "engine_snapshot": {
+                "document": {
+                    "x": 0.0,
+                    "y": 0.0,
+                    "width": 0.0,
+                    "height": 0.0,
+                    "format": {
+                        "width": 0.0,
+                        "height": 0.0,
+                        "dpi": 0.0,
+                        "orientation": "portrait|landscape",
+                        "border_color": { "r": 0.0, "g": 0.0, "b": 0.0, "a": 0.0 },
+                        "show_borders": true,
+                        "show_origin_indicator": false
+                    },
+                    "background": {
+                        "color": { "r": 0.0, "g": 0.0, "b": 0.0, "a": 0.0 },
+                        "pattern": "none|lines|grid|dots",
+                        "pattern_size": [0.0, 0.0],
+                        "pattern_color": { "r": 0.0, "g": 0.0, "b": 0.0, "a": 0.0 }
+                    },
+                    "layout": "fixed_size|continuous_vertical|semi_infinite|infinite"
+                },
+                "stroke_components": [
+                    { "value": null, "version": 0 },
+                    {
+                        "value": {
+                            "brushstroke": {
+                                "path": {
+                                    "start": { "pos": [0.0, 0.0], "pressure": 0.0 },
+                                    "segments": [
+                                        { "lineto": { "end": { "pos": [0.0, 0.0], "pressure": 0.0 } } }
+                                    ]
+                                },
+                                "style": {
+                                    "smooth": {
+                                        "stroke_width": 0.0,
+                                        "stroke_color": { "r": 0.0, "g": 0.0, "b": 0.0, "a": 0.0 },
+                                        "fill_color": null,
+                                        "pressure_curve": "const|linear|sqrt|cbrt|pow2|pow3",
+                                        "line_cap": "straight|round"
+                                    }
+                                }
+                            }
+                        },
+                        "version": 1
+                    },
+                    {
+                        "value": {
+                            "brushstroke": {
+                                "path": {
+                                    "start": { "pos": [0.0, 0.0], "pressure": 0.0 },
+                                    "segments": [
+                                        { "lineto": { "end": { "pos": [0.0, 0.0], "pressure": 0.0 } } }
+                                    ]
+                                },
+                                "style": {
+                                    "textured": {
+                                        "seed": null,
+                                        "stroke_width": 0.0,
+                                        "stroke_color": { "r": 0.0, "g": 0.0, "b": 0.0, "a": 0.0 },
+                                        "density": 0.0,
+                                        "distribution": "uniform|normal|exponential|reverse_exponential",
+                                        "pressure_curve": "const|linear|sqrt|cbrt|pow2|pow3"
+                                    }
+                                }
+                            }
+                        },
+                        "version": 1
+                    }
+                ],
+                "chrono_components": [
+                    { "value": null, "version": 0 },
+                    { "value": { "t": 0, "layer": { "user_layer": 0 } }, "version": 1 },
+                    { "value": { "t": 0, "layer": "highlighter" }, "version": 1 }
+                ]
+            }
+        }
+    }"#;
+
+    /// Returns whether a value has the same keys, types and accepted strings as the layout.
+    /// Array elements may match any element of the layout, arrays of numbers must have the
+    /// same length.
+    fn matches(value: &Value, layout: &Value) -> bool {
+        match (value, layout) {
+            (Value::Object(value), Value::Object(layout)) => {
+                value.len() == layout.len()
+                    && value.iter().all(|(key, value)| {
+                        layout.get(key).is_some_and(|layout| matches(value, layout))
+                    })
+            }
+            (Value::Array(value), Value::Array(layout)) => {
+                (!layout.iter().all(Value::is_number) || value.len() == layout.len())
+                    && value
+                        .iter()
+                        .all(|value| layout.iter().any(|layout| matches(value, layout)))
+            }
+            (Value::String(value), Value::String(layout)) => {
+                layout.split('|').any(|accepted| accepted == value)
+            }
+            (Value::Number(_), Value::Number(_))
+            | (Value::Bool(_), Value::Bool(_))
+            | (Value::Null, Value::Null) => true,
+            _ => false,
+        }
+    }
+
+    fn read_rnote(data: &[u8]) -> Value {
+        let mut document = String::new();
+        GzDecoder::new(data).read_to_string(&mut document).unwrap();
+        serde_json::from_str(&document).unwrap()
+    }
+
+    /// Collects the paths of all object keys of a value, with array elements left out of the
+    /// path.
+    fn key_paths(value: &Value, path: &str, paths: &mut BTreeSet<String>) {
+        match value {
+            Value::Object(object) => {
+                for (key, value) in object {
+                    let path = format!("{}/{}", path, key);
+                    key_paths(value, &path, paths);
+                    paths.insert(path);
+                }
+            }
+            Value::Array(array) => {
+                for value in array {
+                    key_paths(value, path, paths);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Checks the export against documents saved by Rnote itself, in the directory named by
+    /// the `RNOTE_SAMPLES` environment variable. Every key written must occur at the same
+    /// place in one of the documents.
+    #[test]
+    fn export_uses_keys_of_rnote_samples() {
+        let Some(directory) = std::env::var_os("RNOTE_SAMPLES") else {
+            return;
+        };
+        let mut known = BTreeSet::new();
+        for entry in std::fs::read_dir(directory).unwrap() {
+            let path = entry.unwrap().path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "rnote")
+            {
+                key_paths(&read_rnote(&std::fs::read(path).unwrap()), "", &mut known);
+            }
+        }
+
+        let file = NoteFile::read(Cursor::new(testing::archive())).unwrap();
+        let note_id = *file.list_notes().keys().next().unwrap();
+        let mut note = file.get_note(&note_id).unwrap();
+        let mut buffer = Vec::new();
+        note.export_rnote(&mut buffer).unwrap();
+        let mut written = BTreeSet::new();
+        key_paths(&read_rnote(&buffer), "", &mut written);
+        let unknown = written.difference(&known).collect::<Vec<_>>();
+        assert!(unknown.is_empty(), "Unknown keys: {:?}", unknown);
+    }
+
+    #[test]
+    fn export_matches_snapshot_layout() {
+        let file = NoteFile::read(Cursor::new(testing::archive())).unwrap();
+        let note_id = *file.list_notes().keys().next().unwrap();
+        let mut note = file.get_note(&note_id).unwrap();
+        let mut buffer = Vec::new();
+        note.export_rnote(&mut buffer).unwrap();
+
+        let value = read_rnote(&buffer);
+        let layout = serde_json::from_str::<Value>(LAYOUT).unwrap();
+        assert!(matches(&value, &layout), "{}", value);
+
+        // One slot per stroke after the sentinel, with a chrono component each
+        let snapshot = &value["data"]["engine_snapshot"];
+        let strokes = snapshot["stroke_components"].as_array().unwrap();
+        let chrono_components = snapshot["chrono_components"].as_array().unwrap();
+        assert_eq!(strokes.len(), 1 + 3);
+        assert_eq!(chrono_components.len(), strokes.len());
+        let pressures = strokes[1..]
+            .iter()
+            .flat_map(|stroke| {
+                let path = &stroke["value"]["brushstroke"]["path"];
+                let segments = path["segments"].as_array().unwrap();
+                std::iter::once(&path["start"])
+                    .chain(segments.iter().map(|segment| &segment["lineto"]["end"]))
+                    .map(|element| element["pressure"].as_f64().unwrap())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(pressures.len(), 3 * 5);
+        assert!(
+            pressures
+                .iter()
+                .all(|pressure| (0.0..=1.0).contains(pressure))
+        );
+
+        // The pages are stacked in a format as large as the largest page
+        let document = &snapshot["document"];
+        let format_height = document["format"]["height"].as_f64().unwrap();
+        assert!((document["height"].as_f64().unwrap() - format_height * 3.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn exports_device_samples() {
+        let layout = serde_json::from_str::<Value>(LAYOUT).unwrap();
+        for (label, mut note) in testing::device_sample_notes() {
+            let mut buffer = Vec::new();
+            note.export_rnote(&mut buffer).unwrap();
+            let value = read_rnote(&buffer);
+            assert!(matches(&value, &layout), "{}", label);
+
+            let mut strokes = 0;
+            for page_id in note.active_pages().to_vec() {
+                strokes += note
+                    .get_page(&page_id)
+                    .unwrap()
+                    .visible_strokes()
+                    .unwrap()
+                    .iter()
+                    .filter(|(_, stroke)| !stroke.points.is_empty())
+                    .count();
+            }
+            let written = value["data"]["engine_snapshot"]["stroke_components"]
+                .as_array()
+                .unwrap();
+            assert_eq!(written.len(), 1 + strokes, "{}", label);
+        }
+    }
+}
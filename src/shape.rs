@@ -1,22 +1,63 @@
-use zip::ZipArchive;
+use std::io::Write;
+
+use prost::Message;
+use zip::{ZipArchive, ZipWriter, write::SimpleFileOptions};
+
+pub use json::{DisplayScale, LineStyle};
 
 use crate::{
-    id::{PointsUuid, ShapeGroupUuid, StrokeUuid},
+    brush::PenType,
+    color::Color,
+    id::{LayerId, PointsUuid, ShapeGroupUuid, StrokeUuid},
     json::Dimensions,
-    shape::json::{DisplayScale, LineStyle, LineStyleContainer},
-    utils::{convert_timestamp_to_datetime, parse_json},
+    shape::json::LineStyleContainer,
+    utils::{
+        convert_datetime_to_timestamp, convert_timestamp_to_datetime, encode_protobuf, parse_json,
+        update_json,
+    },
 };
 
 #[derive(Debug, Clone)]
 pub struct ShapeGroup {
-    shapes: Vec<Shape>,
+    pub(crate) shapes: Vec<Shape>,
+    /// Name of the file holding the shapes inside the zip archive.
+    pub(crate) entry_name: String,
+    options: SimpleFileOptions,
+    /// Zip archive the shape group was read from.
+    raw: Vec<u8>,
 }
 
 impl ShapeGroup {
+    pub(crate) fn new(entry_name: String, shapes: Vec<Shape>) -> Self {
+        Self {
+            shapes,
+            entry_name,
+            options: SimpleFileOptions::default(),
+            raw: Vec::new(),
+        }
+    }
+
     pub fn read(mut reader: impl std::io::Read) -> crate::error::Result<Self> {
         let mut buf = Vec::new();
         reader.read_to_end(&mut buf)?;
 
+        let mut archive = ZipArchive::new(std::io::Cursor::new(buf.as_slice()))?;
+        let entry = archive.by_index(0)?;
+        let entry_name = entry.name().to_string();
+        let options = SimpleFileOptions::default()
+            .compression_method(entry.compression())
+            .last_modified_time(entry.last_modified().unwrap_or_default());
+        drop(entry);
+
+        Ok(Self {
+            shapes: Self::read_shapes(&buf)?,
+            entry_name,
+            options,
+            raw: buf,
+        })
+    }
+
+    fn read_shapes(buf: &[u8]) -> crate::error::Result<Vec<Shape>> {
         let reader = std::io::Cursor::new(buf);
 
         let mut archive = ZipArchive::new(reader)?;
@@ -24,25 +65,48 @@ impl ShapeGroup {
         let mut reader = archive.by_index(0)?;
 
         let container = protobuf::ShapeContainer::read(&mut reader)?;
-        let shapes = container
+        container
             .shapes
             .iter()
-            .map(Shape::from_protobuf)
-            .collect::<crate::error::Result<_>>()?;
-        Ok(Self { shapes })
+            .map(|buf| Shape::from_protobuf(buf))
+            .collect()
+    }
+
+    /// Writes the shape group as a zip archive holding the encoded shapes, named and compressed
+    /// like the archive it was read from.
+    pub fn write(&self, mut writer: impl std::io::Write) -> crate::error::Result {
+        if Self::read_shapes(&self.raw).is_ok_and(|shapes| shapes == self.shapes) {
+            writer.write_all(&self.raw)?;
+            return Ok(());
+        }
+
+        let container = protobuf::ShapeContainer {
+            shapes: self
+                .shapes
+                .iter()
+                .map(Shape::to_protobuf)
+                .collect::<crate::error::Result<_>>()?,
+        };
+
+        let mut zip = ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        zip.start_file(self.entry_name.as_str(), self.options)?;
+        zip.write_all(&container.encode_to_vec())?;
+        writer.write_all(&zip.finish()?.into_inner())?;
+        Ok(())
     }
-    
+
     pub fn shapes(&self) -> &[Shape] {
         &self.shapes
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Shape {
     pub stroke_id: StrokeUuid,
     pub created: chrono::DateTime<chrono::Utc>,
     pub modified: chrono::DateTime<chrono::Utc>,
-    pub unknown: i64,
+    pub color: Color,
+    pub pen_type: Option<PenType>,
     pub stroke_width: f32,
     pub bbox: Dimensions,
     pub render_scale: DisplayScale,
@@ -50,16 +114,37 @@ pub struct Shape {
     pub points_id: Option<PointsUuid>,
     pub line_style: Option<LineStyle>,
     pub shape_group_id: ShapeGroupUuid,
+    /// Layer the shape is drawn on. Read from protobuf field 20, which was not confirmed
+    /// against a note with several layers, so it may be `None` or wrong for such notes.
+    pub layer_id: Option<LayerId>,
     pub points_json: String,
+    /// Encoded message the shape was read from.
+    pub(crate) raw: Vec<u8>,
 }
 
 impl Shape {
-    fn from_protobuf(shape: &protobuf::Shape) -> crate::error::Result<Self> {
+    fn from_protobuf(buf: &[u8]) -> crate::error::Result<Self> {
+        let shape = protobuf::Shape::decode(buf)?;
+        let shape_type = protobuf::ShapeType::decode(buf)
+            .inspect_err(|e| log::debug!("Failed to decode shape type: {}", e))
+            .ok()
+            .and_then(|shape_type| shape_type.shape_type);
+        let layer_id = protobuf::ShapeLayer::decode(buf)
+            .inspect_err(|e| log::debug!("Failed to decode shape layer: {}", e))
+            .ok()
+            .and_then(|shape_layer| shape_layer.layer_id);
+
         Ok(Self {
             stroke_id: StrokeUuid::from_str(&shape.stroke_uuid)?,
             created: convert_timestamp_to_datetime(shape.created)?,
             modified: convert_timestamp_to_datetime(shape.modified)?,
-            unknown: shape.unknown,
+            color: if shape.color == 0 {
+                log::debug!("Shape without color, falling back to black");
+                Color::BLACK
+            } else {
+                Color::from_argb(shape.color as u32)
+            },
+            pen_type: shape_type.map(|shape_type| PenType::from_raw(shape_type as u32)),
             stroke_width: shape.stroke_width,
             bbox: parse_json(&shape.bbox_json)?,
             render_scale: parse_json(&shape.render_scale_json)?,
@@ -76,35 +161,130 @@ impl Shape {
                 Some(line_style_container.line_style)
             },
             shape_group_id: ShapeGroupUuid::from_str(&shape.shape_group_uuid)?,
+            layer_id: layer_id.map(|layer_id| LayerId::new(layer_id as u32)),
             points_json: shape.empty_array_json.clone(),
+            raw: buf.to_vec(),
         })
     }
+
+    fn to_protobuf(&self) -> crate::error::Result<Vec<u8>> {
+        if Self::from_protobuf(&self.raw).is_ok_and(|original| original == *self) {
+            return Ok(self.raw.clone());
+        }
+
+        let mut shape = protobuf::Shape::decode(self.raw.as_slice())?;
+        shape.stroke_uuid = self.stroke_id.to_string_like(&shape.stroke_uuid);
+        shape.created = convert_datetime_to_timestamp(&self.created);
+        shape.modified = convert_datetime_to_timestamp(&self.modified);
+        // Shapes without a color are read as black, keep them without one
+        if shape.color != 0 || self.color != Color::BLACK {
+            shape.color = self.color.argb() as i32 as i64;
+        }
+        shape.stroke_width = self.stroke_width;
+        shape.bbox_json = update_json(&shape.bbox_json, &self.bbox)?;
+        shape.render_scale_json = update_json(&shape.render_scale_json, &self.render_scale)?;
+        shape.z_order = self.z_order;
+        shape.points_uuid = match &self.points_id {
+            Some(points_id) => points_id.to_string_like(&shape.points_uuid),
+            None => String::new(),
+        };
+        shape.line_style_json = match &self.line_style {
+            Some(line_style) => update_json(
+                &shape.line_style_json,
+                &LineStyleContainer {
+                    line_style: line_style.clone(),
+                },
+            )?,
+            None => String::new(),
+        };
+        shape.shape_group_uuid = self.shape_group_id.to_string_like(&shape.shape_group_uuid);
+        shape.empty_array_json = self.points_json.clone();
+
+        // The shape type and layer are only replaced when set, so values that failed to decode
+        // are kept as they were.
+        let mut known_tags = protobuf::Shape::TAGS.to_vec();
+        let mut extra = Vec::new();
+        if let Some(pen_type) = self.pen_type {
+            known_tags.push(9);
+            extra.extend(
+                protobuf::ShapeType {
+                    shape_type: Some(pen_type.to_raw() as i32),
+                }
+                .encode_to_vec(),
+            );
+        }
+        if let Some(layer_id) = self.layer_id {
+            known_tags.push(20);
+            extra.extend(
+                protobuf::ShapeLayer {
+                    layer_id: Some(layer_id.id() as i32),
+                }
+                .encode_to_vec(),
+            );
+        }
+
+        let mut buf = encode_protobuf(&shape, &self.raw, &known_tags)?;
+        buf.extend(extra);
+        Ok(buf)
+    }
+}
+
+/// Line pattern of a shape, decoded from `LineStyle::type_`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LineType {
+    #[default]
+    Solid,
+    Dashed,
+    Dotted,
+    DashDot,
+    Unknown(u8),
+}
+
+impl LineType {
+    // Uncertain: only solid and dashed lines have been observed so far
+    pub fn from_raw(value: u8) -> Self {
+        match value {
+            0 => Self::Solid,
+            1 => Self::Dashed,
+            2 => Self::Dotted,
+            3 => Self::DashDot,
+            value => Self::Unknown(value),
+        }
+    }
 }
 
 mod json {
-    use serde::Deserialize;
+    use serde::{Deserialize, Serialize};
 
-    #[derive(Debug, Clone, Deserialize)]
+    use super::LineType;
+
+    #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
     #[serde(rename_all = "camelCase")]
     pub struct DisplayScale {
-        display_scale: f32,
-        max_pressure: f32,
-        revised_display_scale: f32,
-        source: u32,
+        pub display_scale: f32,
+        pub max_pressure: f32,
+        pub revised_display_scale: f32,
+        pub source: u32,
     }
 
-    #[derive(Debug, Clone, Deserialize)]
+    #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
     #[serde(rename_all = "camelCase")]
     pub struct LineStyleContainer {
         pub line_style: LineStyle,
     }
 
-    #[derive(Debug, Clone, Deserialize)]
+    #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
     #[serde(rename_all = "camelCase")]
     pub struct LineStyle {
         pub phase: f32,
         pub type_: u8,
     }
+
+    impl LineStyle {
+        pub fn line_type(&self) -> LineType {
+            LineType::from_raw(self.type_)
+        }
+    }
 }
 
 mod protobuf {
@@ -112,10 +292,12 @@ mod protobuf {
 
     use crate::error::Result;
 
+    /// Shapes are kept encoded, so fields with an unconfirmed wire type can be decoded on their
+    /// own without failing the whole shape.
     #[derive(Clone, PartialEq, Message)]
     pub struct ShapeContainer {
-        #[prost(message, repeated, tag = "1")]
-        pub shapes: Vec<Shape>,
+        #[prost(bytes = "vec", repeated, tag = "1")]
+        pub shapes: Vec<Vec<u8>>,
     }
 
     impl ShapeContainer {
@@ -137,9 +319,9 @@ mod protobuf {
         // Confirmed
         #[prost(uint64, tag = "3")]
         pub modified: u64,
-        // Uncertain
+        // Uncertain: signed ARGB value
         #[prost(sint64, tag = "4")]
-        pub unknown: i64,
+        pub color: i64,
         // Uncertain
         #[prost(float, tag = "5")]
         pub stroke_width: f32,
@@ -165,4 +347,119 @@ mod protobuf {
         #[prost(string, tag = "21")]
         pub empty_array_json: String,
     }
+
+    impl Shape {
+        pub const TAGS: &[u32] = &[1, 2, 3, 4, 5, 7, 11, 12, 16, 17, 18, 21];
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct ShapeType {
+        // Uncertain
+        #[prost(int32, optional, tag = "9")]
+        pub shape_type: Option<i32>,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct ShapeLayer {
+        // Uncertain: not confirmed against a note with several layers
+        #[prost(int32, optional, tag = "20")]
+        pub layer_id: Option<i32>,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+
+    use super::*;
+    use crate::testing;
+
+    /// Shape file of the page with two strokes of a created note, stored like a device does,
+    /// with fields and JSON keys the crate does not model.
+    fn shape_file() -> Vec<u8> {
+        let (entry_name, container) = testing::entries(&testing::archive())
+            .into_iter()
+            .filter(|(path, _)| path.contains("/shape/"))
+            .map(|(_, file)| {
+                let mut archive = ZipArchive::new(Cursor::new(file)).unwrap();
+                let mut entry = archive.by_index(0).unwrap();
+                let entry_name = entry.name().to_string();
+                (
+                    entry_name,
+                    protobuf::ShapeContainer::read(&mut entry).unwrap(),
+                )
+            })
+            .find(|(_, container)| container.shapes.len() == 2)
+            .unwrap();
+        let shapes = container
+            .shapes
+            .iter()
+            .map(|shape| {
+                let mut encoded = protobuf::Shape::decode(shape.as_slice()).unwrap();
+                encoded.bbox_json = testing::add_unknown_key(&encoded.bbox_json);
+                encoded.render_scale_json = testing::add_unknown_key(&encoded.render_scale_json);
+                let shape = encode_protobuf(&encoded, shape, protobuf::Shape::TAGS).unwrap();
+                testing::add_unknown_field(&shape)
+            })
+            .collect();
+
+        let options = SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Stored)
+            .last_modified_time(zip::DateTime::from_date_and_time(2024, 5, 6, 7, 8, 10).unwrap());
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        zip.start_file(entry_name, options).unwrap();
+        zip.write_all(&protobuf::ShapeContainer { shapes }.encode_to_vec())
+            .unwrap();
+        zip.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn unchanged_shape_group_is_written_unchanged() {
+        let file = shape_file();
+        let mut buffer = Vec::new();
+        ShapeGroup::read(file.as_slice())
+            .unwrap()
+            .write(&mut buffer)
+            .unwrap();
+        assert_eq!(buffer, file);
+    }
+
+    #[test]
+    fn edited_shape_group_keeps_unknown_data() {
+        let file = shape_file();
+        let mut group = ShapeGroup::read(file.as_slice()).unwrap();
+        group.shapes[0].color = Color::from_argb(0xFF00_FF00);
+        group.shapes[0].bbox.right += 10.0;
+        group.shapes.remove(1);
+        let expected = group.shapes.clone();
+
+        let mut buffer = Vec::new();
+        group.write(&mut buffer).unwrap();
+        let written = ShapeGroup::read(buffer.as_slice()).unwrap();
+        assert_eq!(written.entry_name, group.entry_name);
+        assert_eq!(written.shapes.len(), expected.len());
+        for (shape, expected) in written.shapes.iter().zip(&expected) {
+            assert_eq!(
+                Shape {
+                    raw: expected.raw.clone(),
+                    ..shape.clone()
+                },
+                *expected
+            );
+            assert!(testing::has_unknown_field(&shape.raw));
+            let encoded = protobuf::Shape::decode(shape.raw.as_slice()).unwrap();
+            assert!(testing::has_unknown_key(&encoded.bbox_json));
+            assert!(testing::has_unknown_key(&encoded.render_scale_json));
+        }
+
+        let mut original = ZipArchive::new(Cursor::new(file)).unwrap();
+        let original = original.by_index(0).unwrap();
+        let mut archive = ZipArchive::new(Cursor::new(buffer)).unwrap();
+        let mut entry = archive.by_index(0).unwrap();
+        assert_eq!(entry.compression(), original.compression());
+        assert_eq!(entry.last_modified(), original.last_modified());
+        let mut content = Vec::new();
+        entry.read_to_end(&mut content).unwrap();
+        assert!(!content.is_empty());
+    }
 }
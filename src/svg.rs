@@ -0,0 +1,305 @@
+use std::io::Write;
+
+use base64::{Engine, prelude::BASE64_STANDARD};
+
+use crate::{
+    background::{LoadedBackground, PATTERN_COLOR, PATTERN_DOT_RADIUS, PATTERN_LINE_WIDTH},
+    brush::{Blend, Brush},
+    error::Result,
+    points::{OutlinePath, PathSegment, Stroke},
+    render::{Palette, Rect, Viewport},
+    shape::Shape,
+};
+
+pub fn write_svg(
+    mut writer: impl Write,
+    viewport: &Viewport,
+    page: &Rect,
+    background: &LoadedBackground,
+    strokes: &[(Shape, Stroke)],
+    palette: &Palette,
+) -> Result {
+    let region = &viewport.region;
+
+    writeln!(
+        writer,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" viewBox="{} {} {} {}">"#,
+        region.width() * viewport.scale,
+        region.height() * viewport.scale,
+        region.left,
+        region.top,
+        region.width(),
+        region.height()
+    )?;
+    writeln!(
+        writer,
+        r#"<rect x="{}" y="{}" width="{}" height="{}" fill="{}"/>"#,
+        region.left,
+        region.top,
+        region.width(),
+        region.height(),
+        palette.background().to_hex_rgb()
+    )?;
+    write_background(&mut writer, page, region, background, palette)?;
+
+    for (shape, stroke) in strokes {
+        if stroke.points.is_empty() {
+            log::warn!("No points to draw for stroke");
+            continue;
+        }
+
+        let brush = palette.brush(shape);
+        writeln!(
+            writer,
+            r#"<path d="{}" {}/>"#,
+            path_data(&stroke.outline_paths(&brush)),
+            fill_attributes(&brush)
+        )?;
+    }
+
+    writeln!(writer, "</svg>")?;
+
+    Ok(())
+}
+
+fn write_background(
+    writer: &mut impl Write,
+    page: &Rect,
+    area: &Rect,
+    background: &LoadedBackground,
+    palette: &Palette,
+) -> Result {
+    if let Some(image) = &background.image {
+        writeln!(
+            writer,
+            r#"<image x="{}" y="{}" width="{}" height="{}" preserveAspectRatio="none" href="data:image/png;base64,{}"/>"#,
+            page.left,
+            page.top,
+            page.width(),
+            page.height(),
+            BASE64_STANDARD.encode(&image.png_data)
+        )?;
+    }
+
+    let lines = background.background.pattern_lines(page, area);
+    if !lines.is_empty() {
+        let data = lines
+            .iter()
+            .map(|(start, end)| format!("M{} {} L{} {}", start.0, start.1, end.0, end.1))
+            .collect::<Vec<_>>()
+            .join(" ");
+        writeln!(
+            writer,
+            r#"<path d="{}" fill="none" stroke="{}" stroke-width="{}"/>"#,
+            data,
+            palette.map(PATTERN_COLOR).to_hex_rgb(),
+            PATTERN_LINE_WIDTH
+        )?;
+    }
+
+    let dots = background.background.pattern_dots(page, area);
+    if !dots.is_empty() {
+        writeln!(
+            writer,
+            r#"<g fill="{}">"#,
+            palette.map(PATTERN_COLOR).to_hex_rgb()
+        )?;
+        for (x, y) in dots {
+            writeln!(
+                writer,
+                r#"<circle cx="{}" cy="{}" r="{}"/>"#,
+                x, y, PATTERN_DOT_RADIUS
+            )?;
+        }
+        writeln!(writer, "</g>")?;
+    }
+
+    Ok(())
+}
+
+fn fill_attributes(brush: &Brush) -> String {
+    let color = brush.ink_color();
+    let mut attributes = format!(r#"fill="{}""#, color.to_hex_rgb());
+    if color.alpha() != u8::MAX {
+        attributes.push_str(&format!(
+            r#" fill-opacity="{}""#,
+            color.alpha() as f32 / 255.0
+        ));
+    }
+    match brush.blend {
+        Blend::Normal => {}
+        Blend::Multiply => attributes.push_str(r#" style="mix-blend-mode:multiply""#),
+        Blend::Screen => attributes.push_str(r#" style="mix-blend-mode:screen""#),
+    }
+    attributes
+}
+
+fn path_data(outline: &[OutlinePath]) -> String {
+    let mut data = String::new();
+    for path in outline {
+        if !data.is_empty() {
+            data.push(' ');
+        }
+        data.push_str(&format!("M{} {}", path.start.0, path.start.1));
+        for segment in &path.segments {
+            match segment {
+                PathSegment::Line((x, y)) => data.push_str(&format!(" L{} {}", x, y)),
+                PathSegment::Curve {
+                    control1,
+                    control2,
+                    end,
+                } => data.push_str(&format!(
+                    " C{} {} {} {} {} {}",
+                    control1.0, control1.1, control2.0, control2.1, end.0, end.1
+                )),
+            }
+        }
+        data.push_str(" Z");
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{background::Background, color::Color, points::Stroke, testing};
+
+    const REGION: Rect = Rect {
+        left: 10.0,
+        top: 20.0,
+        right: 110.0,
+        bottom: 70.0,
+    };
+
+    fn to_svg(strokes: &[(Shape, Stroke)], background: Background, palette: &Palette) -> String {
+        let viewport = Viewport {
+            region: REGION,
+            scale: 2.0,
+        };
+        let background = LoadedBackground {
+            background,
+            image: None,
+        };
+        let mut buffer = Vec::new();
+        write_svg(
+            &mut buffer,
+            &viewport,
+            &REGION,
+            &background,
+            strokes,
+            palette,
+        )
+        .unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+
+    /// The strokes of the first two test pages: a ballpoint, a fountain pen and a highlighter
+    /// stroke, followed by an empty stroke.
+    fn strokes() -> Vec<(Shape, Stroke)> {
+        let mut strokes = testing::strokes(0);
+        strokes.sort_by_key(|(shape, _)| shape.pen_type.map(|pen_type| pen_type.to_raw()));
+        strokes.extend(testing::strokes(1));
+        let empty = (strokes[0].0.clone(), Stroke { points: Vec::new() });
+        strokes.push(empty);
+        strokes
+    }
+
+    fn paths<'a>(document: &'a roxmltree::Document) -> Vec<roxmltree::Node<'a, 'a>> {
+        document
+            .root_element()
+            .children()
+            .filter(|node| node.has_tag_name("path"))
+            .collect()
+    }
+
+    #[test]
+    fn view_box_maps_the_region() {
+        let svg = to_svg(&[], Background::Blank, &Palette::Original);
+        let document = roxmltree::Document::parse(&svg).unwrap();
+        let root = document.root_element();
+        assert_eq!(root.attribute("width"), Some("200"));
+        assert_eq!(root.attribute("height"), Some("100"));
+        assert_eq!(root.attribute("viewBox"), Some("10 20 100 50"));
+
+        let page = root.first_element_child().unwrap();
+        assert!(page.has_tag_name("rect"));
+        let attributes = ["x", "y", "width", "height", "fill"].map(|name| page.attribute(name));
+        assert_eq!(attributes, ["10", "20", "100", "50", "#ffffff"].map(Some));
+        assert!(paths(&document).is_empty());
+    }
+
+    #[test]
+    fn strokes_are_written_as_one_path_each() {
+        let svg = to_svg(&strokes(), Background::Blank, &Palette::Original);
+        let document = roxmltree::Document::parse(&svg).unwrap();
+        let paths = paths(&document);
+        assert_eq!(paths.len(), 3);
+        for path in &paths {
+            let data = path.attribute("d").unwrap();
+            assert!(data.starts_with('M'), "{}", data);
+            assert!(data.contains(" C"), "{}", data);
+            assert!(data.ends_with(" Z"), "{}", data);
+        }
+
+        for path in &paths[..2] {
+            assert_eq!(path.attribute("fill"), Some("#3366aa"));
+            assert_eq!(path.attribute("fill-opacity"), None);
+            assert_eq!(path.attribute("style"), None);
+        }
+        let highlighter = &paths[2];
+        assert_eq!(highlighter.attribute("fill"), Some("#3366aa"));
+        let opacity = highlighter.attribute("fill-opacity").unwrap();
+        assert!((opacity.parse::<f32>().unwrap() - 0.5).abs() < 0.01);
+        assert_eq!(
+            highlighter.attribute("style"),
+            Some("mix-blend-mode:multiply")
+        );
+    }
+
+    #[test]
+    fn palette_maps_the_page_and_ink() {
+        let svg = to_svg(&strokes(), Background::Blank, &Palette::Dark);
+        let document = roxmltree::Document::parse(&svg).unwrap();
+        let page = document.root_element().first_element_child().unwrap();
+        assert_eq!(page.attribute("fill"), Some("#000000"));
+
+        let ink = Color::from_argb(0xFF33_66AA)
+            .invert_lightness()
+            .to_hex_rgb();
+        let paths = paths(&document);
+        assert!(
+            paths
+                .iter()
+                .all(|path| path.attribute("fill") == Some(&ink))
+        );
+        assert_eq!(paths[2].attribute("style"), Some("mix-blend-mode:screen"));
+    }
+
+    #[test]
+    fn patterns_cover_the_region() {
+        let svg = to_svg(&[], Background::Lined { spacing: 20.0 }, &Palette::Original);
+        let document = roxmltree::Document::parse(&svg).unwrap();
+        let paths = paths(&document);
+        assert_eq!(paths.len(), 1);
+        assert_eq!(paths[0].attribute("fill"), Some("none"));
+        assert!(paths[0].attribute("stroke").is_some());
+
+        let svg = to_svg(
+            &[],
+            Background::Dotted { spacing: 20.0 },
+            &Palette::Original,
+        );
+        let document = roxmltree::Document::parse(&svg).unwrap();
+        let dots = document
+            .descendants()
+            .filter(|node| node.has_tag_name("circle"))
+            .collect::<Vec<_>>();
+        assert!(!dots.is_empty());
+        for dot in dots {
+            let x = dot.attribute("cx").unwrap().parse::<f32>().unwrap();
+            let y = dot.attribute("cy").unwrap().parse::<f32>().unwrap();
+            assert!((REGION.left..=REGION.right).contains(&x));
+            assert!((REGION.top..=REGION.bottom).contains(&y));
+        }
+    }
+}
@@ -0,0 +1,190 @@
+//! Fixtures shared by the tests of the modules.
+
+use std::{
+    collections::BTreeMap,
+    io::{Cursor, Read, Write},
+    path::PathBuf,
+};
+
+use zip::{ZipArchive, ZipWriter, write::SimpleFileOptions};
+
+use crate::{
+    Note, NoteFile,
+    brush::PenType,
+    builder::{NoteBuilder, PageBuilder, StrokeBuilder},
+    color::Color,
+    note_tree::NoteMetadata,
+    points::{Point, Stroke},
+    shape::Shape,
+};
+
+/// Tag of the field added by [`add_unknown_field`], which no message of the format uses.
+pub const UNKNOWN_TAG: u32 = 99;
+
+/// Key added by [`add_unknown_key`], which no JSON object of the format uses.
+pub const UNKNOWN_KEY: &str = "unknownTestKey";
+
+pub fn stroke(pen_type: PenType, y: f32) -> StrokeBuilder {
+    StrokeBuilder {
+        pen_type,
+        color: Color::from_argb(0xFF33_66AA),
+        width: 4.0,
+        points: (0..5)
+            .map(|index| Point {
+                timestamp_rel: 1000 + index * 10,
+                x: 100.0 + index as f32 * 20.0,
+                y: y + (index % 2) as f32 * 5.0,
+                tilt_x: -3,
+                tilt_y: 7,
+                pressure: 500 + index as u16 * 400,
+            })
+            .collect(),
+    }
+}
+
+/// A note with two pages of strokes and an empty page.
+pub fn note() -> NoteBuilder {
+    NoteBuilder::new("Test")
+        .page(
+            PageBuilder::new()
+                .stroke(stroke(PenType::Ballpoint, 100.0))
+                .stroke(stroke(PenType::Fountain, 200.0)),
+        )
+        .page(PageBuilder::new().stroke(stroke(PenType::Highlighter, 300.0)))
+        .page(PageBuilder::new())
+}
+
+/// Writes [`note`] as a single-note archive.
+pub fn archive() -> Vec<u8> {
+    let mut buffer = Cursor::new(Vec::new());
+    note().write(&mut buffer).unwrap();
+    buffer.into_inner()
+}
+
+/// Returns the metadata of [`note`] as read back from [`archive`].
+pub fn metadata() -> NoteMetadata {
+    let file = NoteFile::read(Cursor::new(archive())).unwrap();
+    let note_id = *file.list_notes().keys().next().unwrap();
+    file.get_note(&note_id).unwrap().metadata
+}
+
+/// Returns the strokes of a page of [`note`].
+pub fn strokes(page: usize) -> Vec<(Shape, Stroke)> {
+    let file = NoteFile::read(Cursor::new(archive())).unwrap();
+    let note_id = *file.list_notes().keys().next().unwrap();
+    let mut note = file.get_note(&note_id).unwrap();
+    let page_id = note.active_pages()[page];
+    note.get_page(&page_id).unwrap().strokes().unwrap()
+}
+
+/// Returns the `.note` files in the directory named by the `BOOX_NOTE_SAMPLES` environment
+/// variable, so that tests can be run against notes written by a device. Returns none if the
+/// variable is not set.
+pub fn device_samples() -> Vec<(PathBuf, Vec<u8>)> {
+    let Some(directory) = std::env::var_os("BOOX_NOTE_SAMPLES") else {
+        return Vec::new();
+    };
+    let mut samples = std::fs::read_dir(&directory)
+        .unwrap_or_else(|e| panic!("Cannot read {}: {}", directory.to_string_lossy(), e))
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "note")
+        })
+        .map(|path| {
+            let data = std::fs::read(&path).unwrap();
+            (path, data)
+        })
+        .collect::<Vec<_>>();
+    samples.sort();
+    samples
+}
+
+/// Returns the notes of the [`device_samples`], each with a label naming the sample file.
+pub fn device_sample_notes() -> Vec<(String, Note<Cursor<Vec<u8>>>)> {
+    let mut notes = Vec::new();
+    for (path, archive) in device_samples() {
+        let file = NoteFile::read(Cursor::new(archive)).unwrap();
+        for (note_id, name) in file.list_notes() {
+            let label = format!("{} in {}", name, path.display());
+            notes.push((label, file.get_note(&note_id).unwrap()));
+        }
+    }
+    notes
+}
+
+/// Returns the content of every file of an archive by path.
+pub fn entries(archive: &[u8]) -> BTreeMap<String, Vec<u8>> {
+    let mut archive = ZipArchive::new(Cursor::new(archive)).unwrap();
+    (0..archive.len())
+        .map(|index| {
+            let mut file = archive.by_index(index).unwrap();
+            let mut data = Vec::new();
+            file.read_to_end(&mut data).unwrap();
+            (file.name().to_string(), data)
+        })
+        .collect()
+}
+
+/// Returns the content of the first file whose path contains `pattern`.
+pub fn entry(entries: &BTreeMap<String, Vec<u8>>, pattern: &str) -> Vec<u8> {
+    entries
+        .iter()
+        .find(|(path, _)| path.contains(pattern))
+        .map(|(_, data)| data.clone())
+        .unwrap_or_else(|| panic!("No file matching {}", pattern))
+}
+
+/// Writes files into a new archive.
+pub fn zip(entries: &BTreeMap<String, Vec<u8>>) -> Vec<u8> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    for (path, data) in entries {
+        zip.start_file(path.as_str(), SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(data).unwrap();
+    }
+    zip.finish().unwrap().into_inner()
+}
+
+/// Appends a varint field with [`UNKNOWN_TAG`] to an encoded message.
+pub fn add_unknown_field(message: &[u8]) -> Vec<u8> {
+    let mut message = message.to_vec();
+    prost::encoding::encode_key(UNKNOWN_TAG, prost::encoding::WireType::Varint, &mut message);
+    prost::encoding::encode_varint(42, &mut message);
+    message
+}
+
+/// Returns whether an encoded message holds the field added by [`add_unknown_field`].
+pub fn has_unknown_field(message: &[u8]) -> bool {
+    let mut rest = message;
+    while !rest.is_empty() {
+        let (tag, wire_type) = prost::encoding::decode_key(&mut rest).unwrap();
+        if tag == UNKNOWN_TAG {
+            return true;
+        }
+        prost::encoding::skip_field(
+            wire_type,
+            tag,
+            &mut rest,
+            prost::encoding::DecodeContext::default(),
+        )
+        .unwrap();
+    }
+    false
+}
+
+/// Adds [`UNKNOWN_KEY`] to a JSON object.
+pub fn add_unknown_key(json: &str) -> String {
+    let mut value = serde_json::from_str::<serde_json::Value>(json).unwrap();
+    value
+        .as_object_mut()
+        .unwrap()
+        .insert(UNKNOWN_KEY.to_string(), serde_json::json!({"kept": [1, 2]}));
+    value.to_string()
+}
+
+/// Returns whether a JSON object holds the key added by [`add_unknown_key`].
+pub fn has_unknown_key(json: &str) -> bool {
+    serde_json::from_str::<serde_json::Value>(json).unwrap()[UNKNOWN_KEY]
+        == serde_json::json!({"kept": [1, 2]})
+}
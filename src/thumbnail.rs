@@ -0,0 +1,244 @@
+use std::path::{Path, PathBuf};
+
+use raqote::DrawTarget;
+
+use crate::{
+    background::BackgroundImage,
+    error::Result,
+    id::{NoteUuid, PageUuid},
+};
+
+/// Returns the cache file prefix shared by all revisions of a page's thumbnail.
+fn cache_prefix(note_id: &NoteUuid, page_id: &PageUuid, max_size: u32) -> String {
+    format!(
+        "{}_{}_{}_",
+        note_id.to_simple_string(),
+        page_id.to_simple_string(),
+        max_size
+    )
+}
+
+/// Returns the cache file of a thumbnail, keyed by the modification time of the page model.
+pub fn cache_path(
+    directory: &Path,
+    note_id: &NoteUuid,
+    page_id: &PageUuid,
+    max_size: u32,
+    modified: &chrono::DateTime<chrono::Utc>,
+) -> PathBuf {
+    directory.join(format!(
+        "{}{}.png",
+        cache_prefix(note_id, page_id, max_size),
+        modified.timestamp_millis()
+    ))
+}
+
+/// Loads a cached thumbnail. Missing or unreadable files count as a cache miss.
+pub fn load(path: &Path) -> Option<DrawTarget> {
+    let data = std::fs::read(path).ok()?;
+    let image = BackgroundImage::decode_png(data)
+        .inspect_err(|e| log::warn!("Ignoring broken thumbnail {}: {}", path.display(), e))
+        .ok()?;
+    Some(DrawTarget::from_vec(
+        image.width as i32,
+        image.height as i32,
+        image.premultiplied_argb(),
+    ))
+}
+
+/// Stores a thumbnail and removes the outdated revisions of the same page.
+pub fn store(
+    directory: &Path,
+    note_id: &NoteUuid,
+    page_id: &PageUuid,
+    max_size: u32,
+    path: &Path,
+    draw_target: &DrawTarget,
+) -> Result {
+    std::fs::create_dir_all(directory)?;
+
+    let prefix = cache_prefix(note_id, page_id, max_size);
+    for entry in std::fs::read_dir(directory)? {
+        let entry_path = entry?.path();
+        let is_stale = entry_path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with(&prefix));
+        if is_stale && entry_path != path {
+            std::fs::remove_file(&entry_path)?;
+        }
+    }
+
+    draw_target.write_png(path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use chrono::{TimeDelta, Utc};
+    use raqote::SolidSource;
+
+    use super::*;
+    use crate::{NoteFile, page_model::PageModelGroup, testing};
+
+    const MAX_SIZE: u32 = 1024;
+
+    /// A fresh directory in the system's temporary directory, removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            let name = format!("boox-note-thumbnails-{}", uuid::Uuid::new_v4().simple());
+            Self(std::env::temp_dir().join(name))
+        }
+
+        fn files(&self) -> Vec<String> {
+            let mut files = std::fs::read_dir(&self.0)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+                .collect::<Vec<_>>();
+            files.sort();
+            files
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn thumbnails(archive: &[u8], cache_dir: Option<&Path>) -> Vec<(PageUuid, DrawTarget)> {
+        let file = NoteFile::read(Cursor::new(archive.to_vec())).unwrap();
+        let note_id = *file.list_notes().keys().next().unwrap();
+        let mut note = file.get_note(&note_id).unwrap();
+        match cache_dir {
+            Some(cache_dir) => note.thumbnails_cached(MAX_SIZE, cache_dir).unwrap(),
+            None => note.thumbnails(MAX_SIZE).unwrap(),
+        }
+    }
+
+    /// Moves the modification time of a page of the archive forward.
+    fn touch(archive: &[u8], page_id: &PageUuid) -> Vec<u8> {
+        let mut entries = testing::entries(archive);
+        for (path, data) in entries.iter_mut() {
+            if !path.contains("/pageModel/pb/") {
+                continue;
+            }
+            let mut group = PageModelGroup::read(data.as_slice()).unwrap();
+            for page_model in &mut group.page_models {
+                if page_model.page_id == *page_id {
+                    page_model.modified += TimeDelta::seconds(1);
+                }
+            }
+            data.clear();
+            group.write(&mut *data).unwrap();
+        }
+        testing::zip(&entries)
+    }
+
+    #[test]
+    fn draft_thumbnails_fit_the_size() {
+        let thumbnails = thumbnails(&testing::archive(), None);
+        assert_eq!(thumbnails.len(), 3);
+        let ink = SolidSource::from_unpremultiplied_argb(0xFF, 0x33, 0x66, 0xAA);
+        let ink = u32::from_be_bytes([ink.a, ink.r, ink.g, ink.b]);
+
+        let (_, first) = &thumbnails[0];
+        assert!(first.width() as u32 <= MAX_SIZE && first.height() as u32 <= MAX_SIZE);
+        assert!(first.width() as u32 == MAX_SIZE || first.height() as u32 == MAX_SIZE);
+        // Drafts are drawn without anti-aliasing
+        assert!(first.get_data().contains(&ink));
+        assert!(
+            first
+                .get_data()
+                .iter()
+                .all(|pixel| *pixel == 0xFFFF_FFFF || *pixel == ink)
+        );
+    }
+
+    #[test]
+    fn cached_thumbnails_are_reused_until_the_page_changes() {
+        let cache_dir = TempDir::new();
+        let archive = testing::archive();
+        let first = thumbnails(&archive, Some(&cache_dir.0));
+        let files = cache_dir.files();
+        assert_eq!(files.len(), 3);
+        for ((_, cached), (_, rendered)) in first.iter().zip(thumbnails(&archive, None)) {
+            assert_eq!(cached.get_data(), rendered.get_data());
+        }
+
+        // Replace the stored thumbnail of the first page, which is then loaded instead of
+        // rendering the page again
+        let page_id = first[0].0;
+        let path = cache_dir.0.join(
+            files
+                .iter()
+                .find(|file| file.contains(&page_id.to_simple_string()))
+                .unwrap(),
+        );
+        let mut marker = DrawTarget::new(1, 1);
+        marker.clear(SolidSource::from_unpremultiplied_argb(0xFF, 0xFF, 0, 0));
+        marker.write_png(&path).unwrap();
+        let second = thumbnails(&archive, Some(&cache_dir.0));
+        assert_eq!(second[0].1.get_data(), marker.get_data());
+        assert_eq!(cache_dir.files(), files);
+
+        // Modifying the page invalidates its thumbnail only
+        let archive = touch(&archive, &page_id);
+        let third = thumbnails(&archive, Some(&cache_dir.0));
+        assert_eq!(third[0].1.get_data(), first[0].1.get_data());
+        let updated = cache_dir.files();
+        assert_eq!(updated.len(), 3);
+        assert!(!path.exists());
+        let unchanged = files
+            .iter()
+            .filter(|file| !file.contains(&page_id.to_simple_string()))
+            .collect::<Vec<_>>();
+        assert!(unchanged.iter().all(|file| updated.contains(file)));
+    }
+
+    #[test]
+    fn storing_removes_stale_revisions_of_the_page_only() {
+        let cache_dir = TempDir::new();
+        std::fs::create_dir_all(&cache_dir.0).unwrap();
+        let note_id = NoteUuid::new_random();
+        let page_id = PageUuid::new_random();
+        let other_page_id = PageUuid::new_random();
+        let now = Utc::now();
+        let earlier = now - TimeDelta::seconds(10);
+
+        let kept = [
+            cache_path(&cache_dir.0, &note_id, &other_page_id, MAX_SIZE, &earlier),
+            cache_path(&cache_dir.0, &note_id, &page_id, MAX_SIZE * 2, &earlier),
+            cache_dir.0.join("unrelated.png"),
+        ];
+        let stale = cache_path(&cache_dir.0, &note_id, &page_id, MAX_SIZE, &earlier);
+        for path in kept.iter().chain([&stale]) {
+            std::fs::write(path, b"").unwrap();
+        }
+
+        let path = cache_path(&cache_dir.0, &note_id, &page_id, MAX_SIZE, &now);
+        let draw_target = DrawTarget::new(2, 3);
+        store(
+            &cache_dir.0,
+            &note_id,
+            &page_id,
+            MAX_SIZE,
+            &path,
+            &draw_target,
+        )
+        .unwrap();
+
+        assert!(!stale.exists());
+        assert!(kept.iter().all(|path| path.exists()));
+        let loaded = load(&path).unwrap();
+        assert_eq!((loaded.width(), loaded.height()), (2, 3));
+
+        // Broken and missing files are cache misses
+        assert!(load(&kept[0]).is_none());
+        assert!(load(&cache_dir.0.join("missing.png")).is_none());
+    }
+}
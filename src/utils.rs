@@ -1,5 +1,10 @@
 use chrono::{DateTime, Utc};
-use serde::{Deserialize, de::DeserializeOwned};
+use prost::{
+    Message,
+    encoding::{DecodeContext, decode_key, skip_field},
+};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::{Map, Value};
 
 use crate::error::{Error, Result};
 
@@ -7,6 +12,10 @@ pub fn convert_timestamp_to_datetime(ts: u64) -> Result<DateTime<Utc>> {
     DateTime::<Utc>::from_timestamp_millis(ts as i64).ok_or_else(|| Error::InvalidTimestamp(ts))
 }
 
+pub fn convert_datetime_to_timestamp(datetime: &DateTime<Utc>) -> u64 {
+    datetime.timestamp_millis() as u64
+}
+
 pub fn parse_json<T: DeserializeOwned>(json_str: &str) -> Result<T> {
     serde_json::from_str(json_str).map_err(|e| Error::Json {
         error: e,
@@ -21,3 +30,274 @@ where
     let color: i32 = Deserialize::deserialize(deserializer)?;
     Ok(color as u32)
 }
+
+pub fn serialize_color<S>(color: &u32, serializer: S) -> std::result::Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.serialize_i32(*color as i32)
+}
+
+/// Encodes `value` as JSON, reusing `original` if it already holds the same value so unchanged
+/// fields are written back exactly as they were read. Otherwise the changes are merged into
+/// `original`, so keys the crate does not model survive.
+pub fn update_json<T>(original: &str, value: &T) -> Result<String>
+where
+    T: Serialize + DeserializeOwned + PartialEq,
+{
+    let json_error = |e| Error::Json {
+        error: e,
+        json_string: original.to_string(),
+    };
+
+    let Ok(modeled) = serde_json::from_str::<T>(original) else {
+        return serde_json::to_string(value).map_err(json_error);
+    };
+    if modeled == *value {
+        return Ok(original.to_string());
+    }
+
+    let original_json = serde_json::from_str(original).map_err(json_error)?;
+    let modeled_json = serde_json::to_value(&modeled).map_err(json_error)?;
+    let updated_json = serde_json::to_value(value).map_err(json_error)?;
+    serde_json::to_string(&merge_json(original_json, &modeled_json, updated_json))
+        .map_err(json_error)
+}
+
+/// Merges `updated` into `original`. Keys of `original` missing from `modeled`, its value as
+/// the crate models it, are not modeled and kept. Keys missing from `updated` only are removed.
+fn merge_json(original: Value, modeled: &Value, updated: Value) -> Value {
+    match (original, modeled, updated) {
+        (Value::Object(mut original), Value::Object(modeled), Value::Object(updated)) => {
+            let original_keys = original.keys().cloned().collect::<Vec<_>>();
+            let mut merged = Map::new();
+            for (key, value) in updated {
+                let modeled = modeled.get(&key);
+                let key = spell_id(key, original_keys.iter().map(String::as_str));
+                let value = match (original.remove(&key), modeled) {
+                    (Some(original), Some(modeled)) => merge_json(original, modeled, value),
+                    _ => value,
+                };
+                merged.insert(key, value);
+            }
+            for (key, value) in original {
+                if !modeled.contains_key(&key) {
+                    merged.insert(key, value);
+                }
+            }
+            Value::Object(merged)
+        }
+        (Value::Array(original), Value::Array(modeled), Value::Array(updated))
+            if original.len() == modeled.len() && modeled.len() == updated.len() =>
+        {
+            let spellings = original
+                .iter()
+                .filter_map(Value::as_str)
+                .collect::<Vec<_>>();
+            let updated = spell_ids(updated, &spellings);
+            Value::Array(
+                original
+                    .into_iter()
+                    .zip(modeled)
+                    .zip(updated)
+                    .map(|((original, modeled), updated)| merge_json(original, modeled, updated))
+                    .collect(),
+            )
+        }
+        (Value::Array(original), _, Value::Array(updated)) => {
+            let spellings = original
+                .iter()
+                .filter_map(Value::as_str)
+                .collect::<Vec<_>>();
+            Value::Array(spell_ids(updated, &spellings))
+        }
+        (Value::String(original), _, Value::String(updated)) => {
+            Value::String(spell_id(updated, [original.as_str()].into_iter()))
+        }
+        (_, _, updated) => updated,
+    }
+}
+
+/// Spells the UUID strings of `values` like [`spell_id`] does.
+fn spell_ids(values: Vec<Value>, spellings: &[&str]) -> Vec<Value> {
+    values
+        .into_iter()
+        .map(|value| match value {
+            Value::String(id) => Value::String(spell_id(id, spellings.iter().copied())),
+            value => value,
+        })
+        .collect()
+}
+
+/// The crate serializes IDs in the simple format, while the app may have written them
+/// hyphenated. Returns `id` as spelled in `spellings` if it holds the same UUID, so unchanged
+/// IDs are written back as they were read, otherwise in the style of the UUIDs there. Strings
+/// that are no UUIDs are returned unchanged.
+fn spell_id<'a>(id: String, spellings: impl Iterator<Item = &'a str>) -> String {
+    let Ok(uuid) = uuid::Uuid::parse_str(&id) else {
+        return id;
+    };
+
+    let mut style = None;
+    for spelling in spellings {
+        match uuid::Uuid::parse_str(spelling) {
+            Ok(other) if other == uuid => return spelling.to_string(),
+            Ok(_) => style = style.or(Some(spelling.len())),
+            Err(_) => {}
+        }
+    }
+    match style {
+        Some(36) => uuid.hyphenated().to_string(),
+        Some(32) => uuid.simple().to_string(),
+        _ => id,
+    }
+}
+
+/// Encodes a protobuf message and appends the fields of `raw` whose tags are not listed in
+/// `known_tags`, so data the crate does not model survives a rewrite.
+pub fn encode_protobuf(message: &impl Message, raw: &[u8], known_tags: &[u32]) -> Result<Vec<u8>> {
+    let mut buf = message.encode_to_vec();
+
+    let mut rest = raw;
+    while !rest.is_empty() {
+        let field = rest;
+        let (tag, wire_type) = decode_key(&mut rest)?;
+        skip_field(wire_type, tag, &mut rest, DecodeContext::default())?;
+        if !known_tags.contains(&tag) {
+            buf.extend_from_slice(&field[..field.len() - rest.len()]);
+        }
+    }
+
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::id::PageUuid;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Settings {
+        width: f32,
+        size: Size,
+        pages: BTreeMap<String, Size>,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Size {
+        width: u32,
+    }
+
+    const ORIGINAL: &str = r#"{"width":2.5,"unknown":[1,{"a":null}],"size":{"width":3,"unit":"px"},"pages":{"a":{"width":1,"extra":true},"b":{"width":2}}}"#;
+
+    #[test]
+    fn update_json_reuses_unchanged_json() {
+        let settings = parse_json::<Settings>(ORIGINAL).unwrap();
+        assert_eq!(update_json(ORIGINAL, &settings).unwrap(), ORIGINAL);
+    }
+
+    #[test]
+    fn update_json_keeps_unknown_keys() {
+        let mut settings = parse_json::<Settings>(ORIGINAL).unwrap();
+        settings.width = 4.0;
+        settings.size.width = 5;
+        settings.pages.get_mut("a").unwrap().width = 6;
+
+        let updated = update_json(ORIGINAL, &settings).unwrap();
+        let updated = serde_json::from_str::<Value>(&updated).unwrap();
+        assert_eq!(
+            updated,
+            serde_json::json!({
+                "width": 4.0,
+                "unknown": [1, {"a": null}],
+                "size": {"width": 5, "unit": "px"},
+                "pages": {"a": {"width": 6, "extra": true}, "b": {"width": 2}},
+            })
+        );
+    }
+
+    #[test]
+    fn update_json_removes_deleted_entries() {
+        let mut settings = parse_json::<Settings>(ORIGINAL).unwrap();
+        settings.pages.remove("a");
+        settings.pages.insert("c".to_string(), Size { width: 7 });
+
+        let updated = update_json(ORIGINAL, &settings).unwrap();
+        let updated = serde_json::from_str::<Value>(&updated).unwrap();
+        assert_eq!(
+            updated["pages"],
+            serde_json::json!({"b": {"width": 2}, "c": {"width": 7}})
+        );
+        assert_eq!(updated["unknown"], serde_json::json!([1, {"a": null}]));
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct PageList {
+        page_name_list: Vec<PageUuid>,
+        page_info_map: HashMap<PageUuid, Size>,
+    }
+
+    const FIRST_ID: &str = "0b6a2f3e-7d41-4c58-9e2f-1a3b5c7d9e0f";
+    const SECOND_ID: &str = "5c8e1d2a-3b4f-4a6c-8d9e-0f1a2b3c4d5e";
+
+    #[test]
+    fn update_json_keeps_the_spelling_of_ids() {
+        let original = format!(
+            r#"{{"pageInfoMap":{{"{FIRST_ID}":{{"width":1}},"{SECOND_ID}":{{"width":2}}}},"pageNameList":["{FIRST_ID}","{SECOND_ID}"]}}"#
+        );
+        let mut pages = parse_json::<PageList>(&original).unwrap();
+        pages.page_name_list.reverse();
+        pages
+            .page_info_map
+            .values_mut()
+            .for_each(|size| size.width += 1);
+
+        let updated = update_json(&original, &pages).unwrap();
+        assert_eq!(
+            updated,
+            format!(
+                r#"{{"pageInfoMap":{{"{FIRST_ID}":{{"width":2}},"{SECOND_ID}":{{"width":3}}}},"pageNameList":["{SECOND_ID}","{FIRST_ID}"]}}"#
+            )
+        );
+    }
+
+    #[test]
+    fn update_json_spells_new_ids_like_the_others() {
+        let original = format!(
+            r#"{{"pageInfoMap":{{"{FIRST_ID}":{{"width":1}}}},"pageNameList":["{FIRST_ID}"]}}"#
+        );
+        let mut pages = parse_json::<PageList>(&original).unwrap();
+        let page_id = PageUuid::new_random();
+        pages.page_name_list.push(page_id);
+        pages.page_info_map.insert(page_id, Size { width: 2 });
+
+        let updated = update_json(&original, &pages).unwrap();
+        let updated = serde_json::from_str::<Value>(&updated).unwrap();
+        let page_id = page_id.to_hyphenated_string();
+        assert_eq!(
+            updated["pageNameList"],
+            serde_json::json!([FIRST_ID, page_id])
+        );
+        assert_eq!(
+            updated["pageInfoMap"][&page_id],
+            serde_json::json!({"width": 2})
+        );
+    }
+
+    #[test]
+    fn update_json_encodes_new_values() {
+        let settings = Settings {
+            width: 1.0,
+            size: Size { width: 2 },
+            pages: BTreeMap::new(),
+        };
+        let updated = update_json("", &settings).unwrap();
+        assert_eq!(parse_json::<Settings>(&updated).unwrap(), settings);
+    }
+}
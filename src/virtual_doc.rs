@@ -1,11 +1,17 @@
+use prost::Message;
+
 use crate::{
     error::Result,
     id::{PageUuid, VirtualDocUuid},
-    utils::{convert_timestamp_to_datetime, parse_json},
-    virtual_doc::json::Content,
+    utils::{
+        convert_datetime_to_timestamp, convert_timestamp_to_datetime, encode_protobuf, parse_json,
+        update_json,
+    },
 };
 
-#[derive(Debug, Clone)]
+pub use json::Content;
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct VirtualDoc {
     pub virtual_doc_id: VirtualDocUuid,
     pub created: chrono::DateTime<chrono::Utc>,
@@ -13,11 +19,19 @@ pub struct VirtualDoc {
     pub page_id: PageUuid,
     pub stability: f32,
     pub content: Content,
+    /// Encoded message the virtual doc was read from.
+    pub(crate) raw: Vec<u8>,
 }
 
 impl VirtualDoc {
     pub fn read(mut reader: impl std::io::Read) -> Result<Self> {
-        let doc = protobuf::VirtualDoc::read(&mut reader)?;
+        let mut raw = Vec::new();
+        reader.read_to_end(&mut raw)?;
+        Self::from_protobuf(raw)
+    }
+
+    fn from_protobuf(raw: Vec<u8>) -> Result<Self> {
+        let doc = protobuf::VirtualDoc::decode(raw.as_slice())?;
         Ok(Self {
             virtual_doc_id: VirtualDocUuid::from_str(&doc.virtual_doc_uuid)?,
             created: convert_timestamp_to_datetime(doc.created)?,
@@ -25,16 +39,38 @@ impl VirtualDoc {
             page_id: PageUuid::from_str(&doc.template_uuid)?,
             stability: doc.stability,
             content: parse_json(&doc.content_json)?,
+            raw,
         })
     }
+
+    pub fn write(&self, mut writer: impl std::io::Write) -> Result {
+        writer.write_all(&self.to_protobuf()?)?;
+        Ok(())
+    }
+
+    fn to_protobuf(&self) -> Result<Vec<u8>> {
+        if Self::from_protobuf(self.raw.clone()).is_ok_and(|original| original == *self) {
+            return Ok(self.raw.clone());
+        }
+
+        let mut doc = protobuf::VirtualDoc::decode(self.raw.as_slice())?;
+        doc.virtual_doc_uuid = self.virtual_doc_id.to_string_like(&doc.virtual_doc_uuid);
+        doc.created = convert_datetime_to_timestamp(&self.created);
+        doc.modified = convert_datetime_to_timestamp(&self.modified);
+        doc.template_uuid = self.page_id.to_string_like(&doc.template_uuid);
+        doc.stability = self.stability;
+        doc.content_json = update_json(&doc.content_json, &self.content)?;
+
+        encode_protobuf(&doc, &self.raw, protobuf::VirtualDoc::TAGS)
+    }
 }
 
 mod json {
-    use serde::Deserialize;
+    use serde::{Deserialize, Serialize};
 
     use crate::json::Dimensions;
 
-    #[derive(Debug, Clone, Deserialize)]
+    #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
     #[serde(rename_all = "camelCase")]
     pub struct Content {
         pub content_id: String,
@@ -48,8 +84,6 @@ mod json {
 mod protobuf {
     use prost::Message;
 
-    use crate::error::Result;
-
     #[derive(Clone, PartialEq, Message)]
     pub struct VirtualDoc {
         // Confirmed
@@ -76,10 +110,63 @@ mod protobuf {
     }
 
     impl VirtualDoc {
-        pub fn read(mut reader: impl std::io::Read) -> Result<Self> {
-            let mut buf = Vec::new();
-            reader.read_to_end(&mut buf)?;
-            Ok(VirtualDoc::decode(&buf[..])?)
-        }
+        pub const TAGS: &[u32] = &[1, 2, 3, 4, 5, 7, 9];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use prost::Message;
+
+    use super::*;
+    use crate::testing;
+
+    /// A virtual doc with fields and JSON keys the crate does not model.
+    fn virtual_doc_file() -> Vec<u8> {
+        let content = r#"{"contentId":"1","contentPageId":"2","contentPageSize":{"top":0.0,"right":1860.0,"bottom":2480.0,"left":0.0,"empty":false,"stability":0},"contentRelativePath":"","contentType":"note"}"#;
+        let virtual_doc = protobuf::VirtualDoc {
+            virtual_doc_uuid: VirtualDocUuid::new_random().to_simple_string(),
+            created: 1_700_000_000_000,
+            modified: 1_700_000_100_000,
+            template_uuid: PageUuid::new_random().to_simple_string(),
+            stability: 1.0,
+            unknown: 0.5,
+            content_json: testing::add_unknown_key(content),
+        };
+        testing::add_unknown_field(&virtual_doc.encode_to_vec())
+    }
+
+    #[test]
+    fn unchanged_virtual_doc_is_written_unchanged() {
+        let file = virtual_doc_file();
+        let mut buffer = Vec::new();
+        VirtualDoc::read(file.as_slice())
+            .unwrap()
+            .write(&mut buffer)
+            .unwrap();
+        assert_eq!(buffer, file);
+    }
+
+    #[test]
+    fn edited_virtual_doc_keeps_unknown_data() {
+        let mut virtual_doc = VirtualDoc::read(virtual_doc_file().as_slice()).unwrap();
+        virtual_doc.page_id = PageUuid::new_random();
+        virtual_doc.content.content_type = "pdf".to_string();
+
+        let mut buffer = Vec::new();
+        virtual_doc.write(&mut buffer).unwrap();
+        let written = VirtualDoc::read(buffer.as_slice()).unwrap();
+        assert_eq!(
+            VirtualDoc {
+                raw: virtual_doc.raw.clone(),
+                ..written.clone()
+            },
+            virtual_doc
+        );
+
+        assert!(testing::has_unknown_field(&written.raw));
+        let encoded = protobuf::VirtualDoc::decode(written.raw.as_slice()).unwrap();
+        assert_eq!(encoded.unknown, 0.5);
+        assert!(testing::has_unknown_key(&encoded.content_json));
     }
 }
@@ -1,11 +1,16 @@
+use prost::Message;
+
 use crate::{
     error::Result,
     id::PageUuid,
     json::Dimensions,
-    utils::{convert_timestamp_to_datetime, parse_json},
+    utils::{
+        convert_datetime_to_timestamp, convert_timestamp_to_datetime, encode_protobuf, parse_json,
+        update_json,
+    },
 };
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct VirtualPage {
     pub page_id: PageUuid,
     pub created: chrono::DateTime<chrono::Utc>,
@@ -17,44 +22,80 @@ pub struct VirtualPage {
     pub geo_layout: String,
     pub template_path: String,
     pub page_number: String,
+    /// Encoded container the virtual page was read from.
+    pub(crate) raw: Vec<u8>,
 }
 
 impl VirtualPage {
     pub fn read(mut reader: impl std::io::Read) -> Result<Self> {
-        let container = protobuf::VirtualPageContainer::read(&mut reader)?;
+        let mut raw = Vec::new();
+        reader.read_to_end(&mut raw)?;
+        Self::from_protobuf(raw)
+    }
+
+    fn from_protobuf(raw: Vec<u8>) -> Result<Self> {
+        let container = protobuf::VirtualPageContainer::decode(raw.as_slice())?;
+        let virtual_page = protobuf::VirtualPage::decode(container.virtual_page.as_slice())?;
         Ok(Self {
-            page_id: PageUuid::from_str(&container.virtual_page.page_uuid)?,
-            created: convert_timestamp_to_datetime(container.virtual_page.created)?,
-            modified: convert_timestamp_to_datetime(container.virtual_page.modified)?,
-            zoom_scale: container.virtual_page.zoom_scale,
-            dimensions: parse_json(&container.virtual_page.dimensions_json)?,
-            layout: parse_json(&container.virtual_page.layout_json)?,
-            geo: parse_json(&container.virtual_page.geo_json)?,
-            geo_layout: container.virtual_page.geo_layout.clone(),
-            template_path: container.virtual_page.template_path.clone(),
-            page_number: container.virtual_page.page_number.clone(),
+            page_id: PageUuid::from_str(&virtual_page.page_uuid)?,
+            created: convert_timestamp_to_datetime(virtual_page.created)?,
+            modified: convert_timestamp_to_datetime(virtual_page.modified)?,
+            zoom_scale: virtual_page.zoom_scale,
+            dimensions: parse_json(&virtual_page.dimensions_json)?,
+            layout: parse_json(&virtual_page.layout_json)?,
+            geo: parse_json(&virtual_page.geo_json)?,
+            geo_layout: virtual_page.geo_layout,
+            template_path: virtual_page.template_path,
+            page_number: virtual_page.page_number,
+            raw,
         })
     }
+
+    pub fn write(&self, mut writer: impl std::io::Write) -> Result {
+        writer.write_all(&self.to_protobuf()?)?;
+        Ok(())
+    }
+
+    fn to_protobuf(&self) -> Result<Vec<u8>> {
+        if Self::from_protobuf(self.raw.clone()).is_ok_and(|original| original == *self) {
+            return Ok(self.raw.clone());
+        }
+
+        let mut container = protobuf::VirtualPageContainer::decode(self.raw.as_slice())?;
+        let mut virtual_page = protobuf::VirtualPage::decode(container.virtual_page.as_slice())?;
+        virtual_page.page_uuid = self.page_id.to_string_like(&virtual_page.page_uuid);
+        virtual_page.created = convert_datetime_to_timestamp(&self.created);
+        virtual_page.modified = convert_datetime_to_timestamp(&self.modified);
+        virtual_page.zoom_scale = self.zoom_scale;
+        virtual_page.dimensions_json =
+            update_json(&virtual_page.dimensions_json, &self.dimensions)?;
+        virtual_page.layout_json = update_json(&virtual_page.layout_json, &self.layout)?;
+        virtual_page.geo_json = update_json(&virtual_page.geo_json, &self.geo)?;
+        virtual_page.geo_layout = self.geo_layout.clone();
+        virtual_page.template_path = self.template_path.clone();
+        virtual_page.page_number = self.page_number.clone();
+
+        container.virtual_page = encode_protobuf(
+            &virtual_page,
+            &container.virtual_page,
+            protobuf::VirtualPage::TAGS,
+        )?;
+        encode_protobuf(&container, &self.raw, protobuf::VirtualPageContainer::TAGS)
+    }
 }
 
 pub mod protobuf {
     use prost::Message;
 
-    use crate::error::Result;
-
+    /// The page is kept encoded, so it can be written back with the fields it was read with.
     #[derive(Clone, PartialEq, Message)]
     pub struct VirtualPageContainer {
-        #[prost(message, required, tag = "1")]
-        pub virtual_page: VirtualPage,
+        #[prost(bytes = "vec", tag = "1")]
+        pub virtual_page: Vec<u8>,
     }
 
     impl VirtualPageContainer {
-        pub fn read(mut reader: impl std::io::Read) -> Result<Self> {
-            let mut buf = Vec::new();
-            reader.read_to_end(&mut buf)?;
-
-            Ok(VirtualPageContainer::decode(&buf[..])?)
-        }
+        pub const TAGS: &[u32] = &[1];
     }
 
     #[derive(Clone, PartialEq, Message)]
@@ -90,4 +131,69 @@ pub mod protobuf {
         #[prost(string, tag = "12")]
         pub page_number: String,
     }
+
+    impl VirtualPage {
+        pub const TAGS: &[u32] = &[1, 2, 3, 4, 6, 7, 8, 9, 10, 12];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use prost::Message;
+
+    use super::*;
+    use crate::testing;
+
+    /// Virtual page file of a created note, with fields and JSON keys the crate does not model.
+    fn virtual_page_file() -> Vec<u8> {
+        let file = testing::entry(&testing::entries(&testing::archive()), "/virtual/page/pb/");
+        let container = protobuf::VirtualPageContainer::decode(file.as_slice()).unwrap();
+        let mut virtual_page =
+            protobuf::VirtualPage::decode(container.virtual_page.as_slice()).unwrap();
+        virtual_page.dimensions_json = testing::add_unknown_key(&virtual_page.dimensions_json);
+        virtual_page.layout_json = testing::add_unknown_key(&virtual_page.layout_json);
+        virtual_page.geo_json = testing::add_unknown_key(&virtual_page.geo_json);
+        let container = protobuf::VirtualPageContainer {
+            virtual_page: testing::add_unknown_field(&virtual_page.encode_to_vec()),
+        };
+        testing::add_unknown_field(&container.encode_to_vec())
+    }
+
+    #[test]
+    fn unchanged_virtual_page_is_written_unchanged() {
+        let file = virtual_page_file();
+        let mut buffer = Vec::new();
+        VirtualPage::read(file.as_slice())
+            .unwrap()
+            .write(&mut buffer)
+            .unwrap();
+        assert_eq!(buffer, file);
+    }
+
+    #[test]
+    fn edited_virtual_page_keeps_unknown_data() {
+        let mut virtual_page = VirtualPage::read(virtual_page_file().as_slice()).unwrap();
+        virtual_page.template_path = "template/lined.png".to_string();
+        virtual_page.geo.bottom += 10.0;
+        virtual_page.zoom_scale = 2.0;
+
+        let mut buffer = Vec::new();
+        virtual_page.write(&mut buffer).unwrap();
+        let written = VirtualPage::read(buffer.as_slice()).unwrap();
+        assert_eq!(
+            VirtualPage {
+                raw: virtual_page.raw.clone(),
+                ..written.clone()
+            },
+            virtual_page
+        );
+
+        assert!(testing::has_unknown_field(&written.raw));
+        let container = protobuf::VirtualPageContainer::decode(written.raw.as_slice()).unwrap();
+        assert!(testing::has_unknown_field(&container.virtual_page));
+        let encoded = protobuf::VirtualPage::decode(container.virtual_page.as_slice()).unwrap();
+        assert!(testing::has_unknown_key(&encoded.dimensions_json));
+        assert!(testing::has_unknown_key(&encoded.layout_json));
+        assert!(testing::has_unknown_key(&encoded.geo_json));
+    }
 }
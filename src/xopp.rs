@@ -0,0 +1,274 @@
+use std::io::Write;
+
+use flate2::{Compression, write::GzEncoder};
+
+use crate::{
+    background::Background,
+    brush::{Brush, PenType},
+    color::Color,
+    error::Result,
+    id::LayerId,
+    note_tree::NoteMetadata,
+    points::Stroke,
+    render::Rect,
+    shape::Shape,
+};
+
+/// Xournal++ measures pages in points, 72 per inch.
+const POINTS_PER_INCH: f32 = 72.0;
+
+pub struct XoppPage {
+    /// Page rect in page coordinates.
+    pub page: Rect,
+    pub units_per_inch: f32,
+    pub background: Background,
+    /// Layers from bottom to top with their strokes in drawing order.
+    pub layers: Vec<(LayerId, Vec<(Shape, Stroke)>)>,
+}
+
+/// Writes the pages as a gzip compressed Xournal++ document.
+pub fn write_xopp(writer: impl Write, metadata: &NoteMetadata, pages: &[XoppPage]) -> Result {
+    let mut writer = GzEncoder::new(writer, Compression::default());
+
+    writeln!(writer, r#"<?xml version="1.0" standalone="no"?>"#)?;
+    writeln!(
+        writer,
+        r#"<xournal creator="{} {}" fileversion="4">"#,
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION")
+    )?;
+    writeln!(writer, "<title>{}</title>", escape(&metadata.name))?;
+
+    for page in pages {
+        let scale = POINTS_PER_INCH / page.units_per_inch;
+        writeln!(
+            writer,
+            r#"<page width="{}" height="{}">"#,
+            page.page.width() * scale,
+            page.page.height() * scale
+        )?;
+        let style = match &page.background {
+            Background::Blank => "plain",
+            Background::Lined { .. } => "lined",
+            Background::Grid { .. } => "graph",
+            Background::Dotted { .. } => "dotted",
+            Background::Image { path } => {
+                log::warn!("Background image not exported to Xournal++: {}", path);
+                "plain"
+            }
+        };
+        writeln!(
+            writer,
+            r##"<background type="solid" color="#ffffffff" style="{}"/>"##,
+            style
+        )?;
+
+        for (layer_id, strokes) in &page.layers {
+            writeln!(writer, r#"<layer name="Layer {}">"#, layer_id.id())?;
+            for (shape, stroke) in strokes {
+                if stroke.points.is_empty() {
+                    log::warn!("No points to export for stroke");
+                    continue;
+                }
+                write_stroke(&mut writer, shape, stroke, &page.page, scale)?;
+            }
+            writeln!(writer, "</layer>")?;
+        }
+
+        writeln!(writer, "</page>")?;
+    }
+
+    writeln!(writer, "</xournal>")?;
+    writer.finish()?;
+
+    Ok(())
+}
+
+fn write_stroke(
+    writer: &mut impl Write,
+    shape: &Shape,
+    stroke: &Stroke,
+    page: &Rect,
+    scale: f32,
+) -> Result {
+    let brush = Brush::from_shape(shape);
+    let tool = match brush.pen_type {
+        PenType::Highlighter => "highlighter",
+        _ => "pen",
+    };
+
+    // Xournal++ takes the nominal width followed by the width of each segment
+    let mut widths = vec![brush.width * scale];
+    if !matches!(brush.pen_type, PenType::Marker | PenType::Highlighter) {
+        widths.extend(stroke.points.windows(2).map(|segment| {
+            (brush.point_width(&segment[0]) + brush.point_width(&segment[1])) / 2.0 * scale
+        }));
+    }
+    let coordinates = stroke
+        .points
+        .iter()
+        .map(|point| {
+            format!(
+                "{} {}",
+                (point.x - page.left) * scale,
+                (point.y - page.top) * scale
+            )
+        })
+        .collect::<Vec<_>>();
+
+    writeln!(
+        writer,
+        r#"<stroke tool="{}" color="{}" width="{}">{}</stroke>"#,
+        tool,
+        to_hex_rgba(brush.ink_color()),
+        widths
+            .iter()
+            .map(|width| width.to_string())
+            .collect::<Vec<_>>()
+            .join(" "),
+        coordinates.join(" ")
+    )?;
+    Ok(())
+}
+
+fn to_hex_rgba(color: Color) -> String {
+    format!("{}{:02x}", color.to_hex_rgb(), color.alpha())
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+
+    use flate2::read::GzDecoder;
+
+    use super::*;
+    use crate::{NoteFile, json::Layer, page_model::PageModelGroup, testing};
+
+    /// The test note with a hidden layer on the first page and the only layer of the second
+    /// page hidden.
+    fn archive() -> Vec<u8> {
+        let archive = testing::archive();
+        let file = NoteFile::read(Cursor::new(archive.clone())).unwrap();
+        let note_id = *file.list_notes().keys().next().unwrap();
+        let page_ids = file.get_note(&note_id).unwrap().active_pages().to_vec();
+
+        let mut entries = testing::entries(&archive);
+        for (path, data) in entries.iter_mut() {
+            if !path.contains("/pageModel/pb/") {
+                continue;
+            }
+            let mut group = PageModelGroup::read(data.as_slice()).unwrap();
+            for page_model in &mut group.page_models {
+                if page_model.page_id == page_ids[0] {
+                    page_model.layers.push(Layer {
+                        id: LayerId::new(1),
+                        lock: false,
+                        show: false,
+                    });
+                } else if page_model.page_id == page_ids[1] {
+                    page_model.layers[0].show = false;
+                }
+            }
+            data.clear();
+            group.write(&mut *data).unwrap();
+        }
+        testing::zip(&entries)
+    }
+
+    #[test]
+    fn exports_visible_layers() {
+        let file = NoteFile::read(Cursor::new(archive())).unwrap();
+        let note_id = *file.list_notes().keys().next().unwrap();
+        let mut note = file.get_note(&note_id).unwrap();
+        let mut buffer = Vec::new();
+        note.export_xopp(&mut buffer).unwrap();
+
+        let mut document = String::new();
+        GzDecoder::new(buffer.as_slice())
+            .read_to_string(&mut document)
+            .unwrap();
+        let document = roxmltree::Document::parse(&document).unwrap();
+        let pages = document
+            .descendants()
+            .filter(|node| node.has_tag_name("page"))
+            .collect::<Vec<_>>();
+        assert_eq!(pages.len(), 3);
+
+        let page_ids = note.active_pages().to_vec();
+        for (page, page_id) in pages.iter().zip(&page_ids) {
+            let page_data = note.get_page(page_id).unwrap();
+            let scale = POINTS_PER_INCH / page_data.units_per_inch;
+            let attribute = |name| page.attribute(name).unwrap().parse::<f32>().unwrap();
+            assert_eq!(attribute("width"), page_data.page_rect().width() * scale);
+            assert_eq!(attribute("height"), page_data.page_rect().height() * scale);
+        }
+
+        let layers = pages
+            .iter()
+            .map(|page| {
+                page.children()
+                    .filter(|node| node.has_tag_name("layer"))
+                    .map(|layer| {
+                        layer
+                            .children()
+                            .filter(|node| node.has_tag_name("stroke"))
+                            .collect::<Vec<_>>()
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(layers.iter().map(Vec::len).collect::<Vec<_>>(), [1, 0, 1]);
+
+        // Pressure sensitive pens have a nominal width and one width per segment
+        let strokes = &layers[0][0];
+        assert_eq!(strokes.len(), 2);
+        for stroke in strokes {
+            let widths = stroke.attribute("width").unwrap().split(' ').count();
+            let points = stroke.text().unwrap().split(' ').count() / 2;
+            assert_eq!(points, 5);
+            assert_eq!(widths, 1 + (points - 1));
+        }
+    }
+
+    #[test]
+    fn exports_device_samples() {
+        for (label, mut note) in testing::device_sample_notes() {
+            let mut buffer = Vec::new();
+            note.export_xopp(&mut buffer).unwrap();
+            let mut document = String::new();
+            GzDecoder::new(buffer.as_slice())
+                .read_to_string(&mut document)
+                .unwrap();
+            let document = roxmltree::Document::parse(&document).unwrap();
+
+            let page_ids = note.active_pages().to_vec();
+            let pages = document
+                .descendants()
+                .filter(|node| node.has_tag_name("page"))
+                .collect::<Vec<_>>();
+            assert_eq!(pages.len(), page_ids.len(), "{}", label);
+            for (page, page_id) in pages.iter().zip(&page_ids) {
+                let strokes = note
+                    .get_page(page_id)
+                    .unwrap()
+                    .visible_strokes()
+                    .unwrap()
+                    .into_iter()
+                    .filter(|(_, stroke)| !stroke.points.is_empty())
+                    .count();
+                let written = page
+                    .descendants()
+                    .filter(|node| node.has_tag_name("stroke"))
+                    .count();
+                assert_eq!(written, strokes, "{}", label);
+            }
+        }
+    }
+}